@@ -1,12 +1,27 @@
-use std::{ops::DerefMut, sync::Arc};
+use std::sync::Arc;
 
 use ascii::AsciiString;
 use maud::html;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use tiny_http::Request;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 
-use crate::{block_in_place, http_client::{qf_client::QFClient, wfm_client::WFMClient}, rivens::inventory::riven_lookop::RivenDataLookup, server::RIVEN_LOOKUP, AppError};
+use crate::{
+    block_in_place,
+    config::Config,
+    http_client::{auth_state::AuthState, qf_client::QFClient, wfm_client::WFMClient},
+    pages::reroll::uri_reroll,
+    rivens::{
+        inventory::{
+            convert_raw_inventory::Item, database::database::InventoryDB,
+            riven_lookop::RivenDataLookup,
+        },
+        reroll::{RollGrade, RollRule, WeaponRolls},
+        wfm_auctions::close_auction,
+    },
+    server::{riven_lookup, set_riven_lookup},
+    AppError,
+};
 
 #[derive(Deserialize, Debug)]
 struct Login {
@@ -19,31 +34,33 @@ pub fn uri_api_login(
     body: &str,
     wfm: Arc<Mutex<WFMClient>>,
     qf: Arc<Mutex<QFClient>>,
-    logged_in: &mut Option<bool>,
+    config: &Config,
+    logged_in: &watch::Sender<Option<bool>>,
 ) -> Result<(), AppError> {
-    let (email, password) = {
-        let log =
-            serde_urlencoded::from_str::<Login>(body).expect("bruh this aint urlencoded tf u doin");
-        (log.email, log.password)
+    let (email, password) = match serde_urlencoded::from_str::<Login>(body) {
+        Ok(log) => (log.email, log.password),
+        Err(e) => {
+            eprintln!("ERROR: Could not parse login form: {e}");
+            return rq
+                .respond(tiny_http::Response::empty(400))
+                .map_err(|e| AppError::new(e.to_string(), "uri_api_login".to_string()));
+        }
     };
 
     let (status, id, check_code, ingame_name) = block_in_place!(async move {
-        let mut wfm = wfm.lock().await;
-        let wfm = wfm.deref_mut();
+        let mut wfm = wfm.lock().await.clone();
         wfm.login(&email, &password).await
     }).map_err(|e| e.prop("uri_login_req".into()))?;
 
     block_in_place!(async {
-        let qf = qf.clone();
-        let mut qf = qf.lock().await;
-        let qf = qf.deref_mut();
+        let mut qf = qf.lock().await.clone();
         qf.login(id, check_code, ingame_name).await
     }).map_err(|e| e.prop("uri_login_req".into()))?;
 
-    let lookup = block_in_place!( async { RivenDataLookup::setup(qf.clone()).await }).expect(
-        "FATAL: Could not retrieve riven lookup data"
-    );
-    RIVEN_LOOKUP.set(lookup).expect("FATAL: Could not store riven lookup data in sync OnceCell");
+    // replaced on every login, another account might be logging in
+    let lookup = block_in_place!( async { RivenDataLookup::setup(qf.clone(), &config.paths.riven_lookup).await })
+        .map_err(|e| e.prop("uri_api_login".into()))?;
+    set_riven_lookup(lookup);
 
     // for testing
     let authorized = status.code == 200;
@@ -51,7 +68,7 @@ pub fn uri_api_login(
     let pagecontent = if !authorized {
         html! {p id="login_failed" style="text-align: center; color: red;" {b {"Login Failed, Please try again"}}}
     } else {
-        *logged_in = Some(authorized);
+        logged_in.send_replace(Some(authorized));
         html! {""}
     };
     let r = tiny_http::Response::from_string(pagecontent.into_string()).with_header(
@@ -70,33 +87,498 @@ pub fn uri_api_login(
     }
 }
 
-pub fn uri_api_delete_riven(rq: Request, _id: &str) -> Result<(), AppError> {
-    rq.respond(tiny_http::Response::empty(200)).map_err(|e| AppError::new(e.to_string(), "uri_api_delete_riven".to_string()))
+// the page starts over at `/`, which shows the login or the new account's rivens
+fn respond_refresh(rq: Request, loc: &str) -> Result<(), AppError> {
+    rq.respond(tiny_http::Response::empty(200).with_header(tiny_http::Header {
+        field: "HX-Refresh".parse().unwrap(),
+        value: AsciiString::from_ascii("true").unwrap(),
+    }))
+    .map_err(|e| AppError::new(e.to_string(), loc.to_string()))
+}
+
+pub fn uri_api_logout(
+    rq: Request,
+    auth: Arc<Mutex<AuthState>>,
+    logged_in: &watch::Sender<Option<bool>>,
+) -> Result<(), AppError> {
+    block_in_place!(async {
+        let mut auth = auth.lock().await;
+        auth.wipe()
+    })
+    .map_err(|e| e.prop("uri_api_logout".into()))?;
+    logged_in.send_replace(Some(false));
+    respond_refresh(rq, "uri_api_logout")
+}
+
+// keeps the current account around to switch back to
+pub fn uri_api_add_account(
+    rq: Request,
+    auth: Arc<Mutex<AuthState>>,
+    logged_in: &watch::Sender<Option<bool>>,
+) -> Result<(), AppError> {
+    block_in_place!(async {
+        let mut auth = auth.lock().await;
+        auth.sign_out()
+    })
+    .map_err(|e| e.prop("uri_api_add_account".into()))?;
+    logged_in.send_replace(Some(false));
+    respond_refresh(rq, "uri_api_add_account")
+}
+
+pub fn uri_api_switch_account(
+    rq: Request,
+    name: &str,
+    auth: Arc<Mutex<AuthState>>,
+    qf: Arc<Mutex<QFClient>>,
+    config: &Config,
+    logged_in: &watch::Sender<Option<bool>>,
+) -> Result<(), AppError> {
+    let switched = block_in_place!(async {
+        let mut auth = auth.lock().await;
+        auth.switch_profile(name)
+    });
+    if let Err(e) = switched {
+        eprintln!("ERROR: Could not switch account: {e}");
+        return rq
+            .respond(tiny_http::Response::empty(404))
+            .map_err(|e| AppError::new(e.to_string(), "uri_api_switch_account".to_string()));
+    }
+    let lookup = block_in_place!(async { RivenDataLookup::setup(qf, &config.paths.riven_lookup).await })
+        .map_err(|e| e.prop("uri_api_switch_account".into()))?;
+    set_riven_lookup(lookup);
+    // imports the new account's auctions
+    logged_in.send_replace(Some(true));
+    respond_refresh(rq, "uri_api_switch_account")
 }
-pub fn uri_api_blacklist_riven(rq: Request, _id: &str) -> Result<(), AppError> {
-    rq.respond(tiny_http::Response::empty(200)).map_err(|e| AppError::new(e.to_string(), "uri_api_delete_riven".to_string()))
+
+pub fn uri_api_delete_riven(
+    rq: Request,
+    id: &str,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    // the auction row is kept when closing fails so it isn't lost track of
+    let status = match block_in_place!(close_auction(id, wfm, db)) {
+        Ok(_) => 200,
+        Err(e) => {
+            eprintln!("ERROR: Could not delete auction: {e}");
+            502
+        }
+    };
+    rq.respond(tiny_http::Response::empty(status)).map_err(|e| AppError::new(e.to_string(), "uri_api_delete_riven".to_string()))
+}
+async fn blacklist_riven(
+    oid: &str,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let auction = {
+        let mut db = db.lock().await;
+        let db = match db.as_mut() {
+            Some(v) => v,
+            None => return Err(AppError::new("Database is closed".into(), "blacklist_riven".into())),
+        };
+        db.blacklist_item(oid)
+            .map_err(|e| AppError::new(e.to_string(), "blacklist_riven: blacklist_item".into()))?;
+        db.select_auction(oid)
+            .map_err(|e| AppError::new(e.to_string(), "blacklist_riven: select_auction".into()))?
+    };
+
+    // blacklisted rivens shouldn't stay up on the market either
+    if let Some(wfm_id) = auction.and_then(|auc| auc.id) {
+        let mut wfm = wfm.lock().await.clone();
+        wfm.close_auction(&wfm_id)
+            .await
+            .map_err(|e| e.prop("blacklist_riven".into()))?;
+        let mut db = db.lock().await;
+        if let Some(db) = db.as_mut() {
+            db.delete_auction(oid)
+                .map_err(|e| AppError::new(e.to_string(), "blacklist_riven: delete_auction".into()))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn uri_api_blacklist_riven(
+    rq: Request,
+    id: &str,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    if let Err(e) = block_in_place!(blacklist_riven(id, wfm, db)) {
+        eprintln!("ERROR: Could not blacklist riven: {e}");
+        return rq
+            .respond(tiny_http::Response::empty(502))
+            .map_err(|e| AppError::new(e.to_string(), "uri_api_blacklist_riven".to_string()));
+    }
+
+    // closes the edit screen and removes the riven from the table
+    let card_id = format!("a{id}");
+    let pagecontent = html! {
+        div id=(card_id) hx-swap-oob="delete" {}
+    };
+    let r = tiny_http::Response::from_string(pagecontent.into_string()).with_header(
+        tiny_http::Header {
+            field: "Content-Type".parse().unwrap(),
+            value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
+        },
+    );
+    rq.respond(r).map_err(|e| AppError::new(e.to_string(), "uri_api_blacklist_riven".to_string()))
+}
+
+pub fn uri_api_unblacklist_riven(
+    rq: Request,
+    id: &str,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let res = block_in_place!(async {
+        let mut db = db.lock().await;
+        match db.as_mut() {
+            Some(db) => db.unblacklist_item(id).map_err(|e| e.to_string()),
+            None => Err("Database is closed".to_string()),
+        }
+    });
+    let status = match res {
+        Ok(_) => 200,
+        Err(e) => {
+            eprintln!("ERROR: Could not unblacklist riven: {e}");
+            500
+        }
+    };
+    rq.respond(tiny_http::Response::empty(status)).map_err(|e| AppError::new(e.to_string(), "uri_api_unblacklist_riven".to_string()))
 }
 
 #[derive(Debug, Deserialize)]
 struct EditOptions {
-    price: i64,
+    // left empty in bulk edits to keep each auction's current price
+    #[serde(default, deserialize_with = "deserialize_price")]
+    price: Option<u32>,
+    // unchecked checkboxes aren't sent with the form at all
+    #[serde(default, deserialize_with = "deserialize_checkbox")]
     visible: bool,
     description: String,
 }
 
-pub fn uri_api_update_riven(rq: Request, _mult: bool, _id: &str, body: Option<&str>) -> Result<(), AppError> {
-    if let Some(body) = body {
-        let v = body.find("visible=").unwrap();
-        let d = body.find("description=").unwrap();
-        let left = &body[..v];
-        let middle = &body[v..d];
-        let middle = middle.replace("on", "true").replace("off", "false");
-        let right = &body[d..];
-        let mut body = left.to_string();
-        body.push_str(&middle);
-        body.push_str(right);
-        let body = serde_urlencoded::from_str::<EditOptions>(body.as_str()).expect("bruh this aint urlencoded tf u doin");
-        println!("{body:#?}");
-    };
-    rq.respond(tiny_http::Response::empty(200)).map_err(|e| AppError::new(e.to_string(), "uri_api_update_riven".to_string()))
+fn deserialize_price<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_checkbox<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Ok(value == "on")
+}
+
+// descriptions can reference the riven they're applied to, mostly useful
+// when one description is shared by a bulk edit
+fn fill_note_template(template: &str, item: &Item) -> String {
+    template
+        .replace("{weapon}", &item.weapon_name)
+        .replace("{name}", &item.name)
+}
+
+async fn save_auction(
+    oid: &str,
+    options: &EditOptions,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let (item, auction) = {
+        let db = db.lock().await;
+        let db = match db.as_ref() {
+            Some(v) => v,
+            None => return Err(AppError::new("Database is closed".into(), "save_auction".into())),
+        };
+        let item = db
+            .select_item(oid)
+            .map_err(|e| AppError::new(e.to_string(), "save_auction: select_item".into()))?;
+        let auction = db
+            .select_auction(oid)
+            .map_err(|e| AppError::new(e.to_string(), "save_auction: select_auction".into()))?;
+        let blacklisted = db
+            .is_blacklisted(oid)
+            .map_err(|e| AppError::new(e.to_string(), "save_auction: is_blacklisted".into()))?;
+        if blacklisted {
+            return Err(AppError::new(
+                format!("Riven is blacklisted: {oid}"),
+                "save_auction".into(),
+            ));
+        }
+        (item, auction)
+    };
+    let item = match item {
+        Some(v) => v,
+        None => {
+            return Err(AppError::new(
+                format!("No riven with the id: {oid}"),
+                "save_auction".into(),
+            ))
+        }
+    };
+
+    let price = match options.price.or(auction.as_ref().and_then(|auc| auc.buyout_price)) {
+        Some(v) => v,
+        None => {
+            return Err(AppError::new(
+                format!("No price given for riven: {oid}"),
+                "save_auction".into(),
+            ))
+        }
+    };
+    let note = fill_note_template(&options.description, &item);
+
+    let new_auction = {
+        let mut wfm = wfm.lock().await.clone();
+        match auction.and_then(|auc| auc.id) {
+            Some(wfm_id) => {
+                wfm.update_auction(&wfm_id, oid, price, options.visible, &note)
+                    .await
+            }
+            None => wfm.create_auction(&item, price, options.visible, &note).await,
+        }
+        .map_err(|e| e.prop("save_auction".into()))?
+    };
+
+    let mut db = db.lock().await;
+    match db.as_mut() {
+        Some(db) => db
+            .insert_auctions(&[new_auction])
+            .map_err(|e| AppError::new(e.to_string(), "save_auction: insert_auctions".into())),
+        None => Err(AppError::new("Database is closed".into(), "save_auction".into())),
+    }
+}
+
+fn bulk_edit_ids(body: &str) -> Vec<Arc<str>> {
+    serde_urlencoded::from_str::<Vec<(String, String)>>(body)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(key, _)| key == "oid")
+        .map(|(_, oid)| oid.into())
+        .collect()
+}
+
+async fn bulk_edit(
+    oids: Vec<Arc<str>>,
+    options: &EditOptions,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Vec<(String, Result<(), AppError>)> {
+    let mut results = Vec::with_capacity(oids.len());
+    // requests go out one after another so they all pass through the
+    // client's rate limiter
+    for oid in oids {
+        let title = {
+            let db = db.lock().await;
+            match db.as_ref().and_then(|db| db.select_item(&oid).ok().flatten()) {
+                Some(item) => format!("{} {}", item.weapon_name, item.name),
+                None => oid.to_string(),
+            }
+        };
+        let res = save_auction(&oid, options, wfm.clone(), db.clone()).await;
+        if let Err(e) = &res {
+            eprintln!("ERROR: Could not save auction: {e}");
+        }
+        results.push((title, res));
+    }
+    results
+}
+
+pub fn uri_api_update_riven(
+    rq: Request,
+    mult: bool,
+    id: &str,
+    body: Option<&str>,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    // prices like `-5` or `1e3` don't fit the form
+    let (body, options) = match body.map(|body| (body, serde_urlencoded::from_str::<EditOptions>(body))) {
+        Some((body, Ok(options))) => (body, options),
+        _ => {
+            return rq
+                .respond(tiny_http::Response::empty(400))
+                .map_err(|e| AppError::new(e.to_string(), "uri_api_update_riven".to_string()))
+        }
+    };
+
+    if mult {
+        let results = block_in_place!(bulk_edit(bulk_edit_ids(body), &options, wfm, db));
+        let pagecontent = html! {
+            div style="flex-grow: 1;" {
+                div class="celltitle" {"Bulk Edit Results"}
+                hr {}
+                @for (title, res) in &results {
+                    @match res {
+                        Ok(_) => p style="margin: 10px;" {(title) ": Saved"},
+                        Err(e) => p style="margin: 10px; color: red;" {(title) ": " (e.err)},
+                    }
+                }
+            }
+            div style="padding-bottom: 13px;" {
+                button class="cellbutton" hx-delete="/edit_cancel" hx-target="#edit_screen" hx-swap="outerHTML swap:.08s" {"Close"}
+            }
+        };
+        let r = tiny_http::Response::from_string(pagecontent.into_string()).with_header(
+            tiny_http::Header {
+                field: "Content-Type".parse().unwrap(),
+                value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
+            },
+        );
+        return rq.respond(r).map_err(|e| AppError::new(e.to_string(), "uri_api_update_riven".to_string()));
+    }
+
+    // keep the edit dialog open when the listing fails so the user can retry
+    let status = match block_in_place!(save_auction(id, &options, wfm, db)) {
+        Ok(_) => 200,
+        Err(e) => {
+            eprintln!("ERROR: Could not save auction: {e}");
+            502
+        }
+    };
+    rq.respond(tiny_http::Response::empty(status)).map_err(|e| AppError::new(e.to_string(), "uri_api_update_riven".to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+struct RollRuleOptions {
+    weapon_url_name: String,
+    grade: String,
+    #[serde(default)]
+    positives: String,
+    #[serde(default)]
+    negatives: String,
+}
+
+fn split_stats(stats: &str) -> Vec<Arc<str>> {
+    stats
+        .split(',')
+        .map(|stat| stat.trim())
+        .filter(|stat| !stat.is_empty())
+        .map(Arc::from)
+        .collect()
+}
+
+// checks the weapon and every stat in the rule exist before it gets stored
+fn roll_rule(options: RollRuleOptions) -> Result<RollRule, AppError> {
+    let lookup = riven_lookup()
+        .ok_or_else(|| AppError::new("Riven data isn't loaded yet".into(), "roll_rule".into()))?;
+    let weapon_url_name = options.weapon_url_name.trim();
+    let rolls = WeaponRolls::from_lookup(&lookup, weapon_url_name)
+        .map_err(|e| e.prop("roll_rule".into()))?;
+    let rule = RollRule {
+        weapon_url_name: weapon_url_name.into(),
+        grade: RollGrade::try_from(options.grade.as_str())
+            .map_err(|e| e.prop("roll_rule".into()))?,
+        positives: split_stats(&options.positives),
+        negatives: split_stats(&options.negatives),
+    };
+    // rivens that miss every rule are the dissolve candidates
+    if rule.grade == RollGrade::Dissolve {
+        return Err(AppError::new(
+            "Rules can only be for god rolls or decent rolls".into(),
+            "roll_rule".into(),
+        ));
+    }
+    if let Some(stat) = rule
+        .positives
+        .iter()
+        .chain(rule.negatives.iter())
+        .find(|stat| rolls.upgrades.iter().all(|upgr| upgr.url_name != **stat))
+    {
+        return Err(AppError::new(
+            format!("{} can't roll {}", weapon_url_name, stat),
+            "roll_rule".into(),
+        ));
+    }
+    Ok(rule)
+}
+
+pub fn uri_api_save_roll_rule(
+    rq: Request,
+    body: Option<&str>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let options = match body.map(serde_urlencoded::from_str::<RollRuleOptions>) {
+        Some(Ok(v)) => v,
+        _ => {
+            return rq
+                .respond(tiny_http::Response::empty(400))
+                .map_err(|e| AppError::new(e.to_string(), "uri_api_save_roll_rule".to_string()))
+        }
+    };
+    let rule = match roll_rule(options) {
+        Ok(v) => v,
+        Err(e) => return uri_reroll(rq, db, Some(&e.err)).map_err(|e| e.prop("uri_api_save_roll_rule".into())),
+    };
+    let res = block_in_place!(async {
+        let mut db = db.lock().await;
+        match db.as_mut() {
+            Some(db) => db.insert_roll_rule(&rule).map_err(|e| e.to_string()),
+            None => Err("Database is closed".to_string()),
+        }
+    });
+    let notice = res.err().map(|e| {
+        eprintln!("ERROR: Could not save roll rule: {e}");
+        e
+    });
+    uri_reroll(rq, db, notice.as_deref()).map_err(|e| e.prop("uri_api_save_roll_rule".into()))
+}
+
+pub fn uri_api_delete_roll_rule(
+    rq: Request,
+    id: &str,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let res = match id.split_once('/') {
+        Some((weapon_url_name, grade)) => RollGrade::try_from(grade)
+            .map_err(|e| e.err.to_string())
+            .and_then(|grade| {
+                block_in_place!(async {
+                    let mut db = db.lock().await;
+                    match db.as_mut() {
+                        Some(db) => db
+                            .delete_roll_rule(weapon_url_name, &grade)
+                            .map_err(|e| e.to_string()),
+                        None => Err("Database is closed".to_string()),
+                    }
+                })
+            }),
+        None => Err(format!("Invalid roll rule: {id}")),
+    };
+    let status = match res {
+        Ok(_) => 200,
+        Err(e) => {
+            eprintln!("ERROR: Could not delete roll rule: {e}");
+            500
+        }
+    };
+    rq.respond(tiny_http::Response::empty(status)).map_err(|e| AppError::new(e.to_string(), "uri_api_delete_roll_rule".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bulk_edit_ids, EditOptions};
+
+    #[test]
+    fn test_edit_options() {
+        let single =
+            serde_urlencoded::from_str::<EditOptions>("price=150&visible=on&description=hi")
+                .unwrap();
+        assert_eq!(single.price, Some(150));
+        assert!(single.visible);
+
+        let body = "oid=abc&oid=def&price=&description=%7Bweapon%7D";
+        let bulk = serde_urlencoded::from_str::<EditOptions>(body).unwrap();
+        assert_eq!(bulk.price, None);
+        assert!(!bulk.visible);
+        assert_eq!(bulk.description, "{weapon}");
+        assert_eq!(bulk_edit_ids(body), vec!["abc".into(), "def".into()]);
+
+        // answered with a 400 instead of taking the server down
+        assert!(serde_urlencoded::from_str::<EditOptions>("price=-5&description=x").is_err());
+        assert!(serde_urlencoded::from_str::<EditOptions>("price=1e3&description=x").is_err());
+        assert!(serde_urlencoded::from_str::<EditOptions>("price=5&description=x").is_ok());
+    }
 }
@@ -0,0 +1,437 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use serde_json::{json, to_string_pretty};
+use tokio::sync::{broadcast, Mutex};
+
+use crate::{
+    http_client::{
+        auth_state::AuthState, qf_client::QFClient, session_refresh::refresh_sessions,
+        wfm_client::WFMClient,
+    },
+    rivens::{
+        inventory::{
+            database::{database::InventoryDB, inventory_sync::sync_db},
+            raw_inventory::decrypt_last_data,
+            riven_lookop::RivenDataLookup,
+        },
+        price_suggestion::get_price_suggestion,
+        wfm_auctions::{close_removed_auctions, import_auctions},
+    },
+    config::Config,
+    AppError, StopSignal,
+};
+
+static USAGE: &str = "\
+usage: raw_html_rendering [options] [command] [--json]
+
+runs the gui when no command is given
+
+options:
+    --config <path>           config file to use instead of the default one
+    --data-dir <path>         directory relative paths in the config are in
+    --last-data <path>        lastData.dat to read the inventory from, relative to the working directory
+    --host <host>             address the gui is served on
+    --port <port>             port the gui is served on
+    --websocket-port <port>   port the gui's websocket is served on
+    --migrate <dir>           copy the files an older version left in `dir` to the data directory
+    --print-url               print the url that opens the gui in a browser, it contains the session token
+
+commands:
+    login <email>     log into warframe.market, the password is read from stdin
+    logout            forget the current account and delete its stored credentials
+    accounts          list the accounts that have logged in
+    switch <name>     switch to another account that has logged in before
+    decrypt [path]    decrypt the configured lastData.dat, or `path`, and print it as json
+    sync              sync the inventory database and import warframe.market auctions
+    list              list the rivens in the inventory database
+    price <oid>       suggest a price for a riven from comparable auctions
+    export [path]     write the rivens and auctions as json to stdout or `path`
+    help              show this message";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Login(String),
+    Logout,
+    Accounts,
+    Switch(String),
+    Decrypt(Option<String>),
+    Sync,
+    List,
+    Price(String),
+    Export(Option<String>),
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CliArgs {
+    pub command: Command,
+    pub json: bool,
+}
+
+pub fn parse_args(args: &[String]) -> Result<CliArgs, AppError> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let mut args = args.iter().filter(|arg| arg.as_str() != "--json");
+    let missing = |what: &str| AppError::new(format!("missing argument: {what}"), "parse_args".into());
+    let command = match args.next().map(|arg| arg.as_str()) {
+        Some("login") => Command::Login(args.next().ok_or_else(|| missing("email"))?.clone()),
+        Some("logout") => Command::Logout,
+        Some("accounts") => Command::Accounts,
+        Some("switch") => Command::Switch(args.next().ok_or_else(|| missing("name"))?.clone()),
+        Some("decrypt") => Command::Decrypt(args.next().cloned()),
+        Some("sync") => Command::Sync,
+        Some("list") => Command::List,
+        Some("price") => Command::Price(args.next().ok_or_else(|| missing("oid"))?.clone()),
+        Some("export") => Command::Export(args.next().cloned()),
+        Some("help") | Some("--help") | Some("-h") | None => Command::Help,
+        Some(other) => {
+            return Err(AppError::new(
+                format!("unknown command: {other}"),
+                "parse_args".into(),
+            ))
+        }
+    };
+    if let Some(extra) = args.next() {
+        return Err(AppError::new(
+            format!("unexpected argument: {extra}"),
+            "parse_args".into(),
+        ));
+    }
+    Ok(CliArgs { command, json })
+}
+
+// everything the gui sets up in `start_server`, minus the server itself
+struct Headless {
+    auth: Arc<Mutex<AuthState>>,
+    wfm: Arc<Mutex<WFMClient>>,
+    qf: Arc<Mutex<QFClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+    // the clients shut down their connections once this is dropped
+    _stop_sender: broadcast::Sender<StopSignal>,
+    config: Arc<Config>,
+}
+
+impl Headless {
+    fn setup(config: Arc<Config>) -> Result<Self, AppError> {
+        let _ = tokio_rustls::rustls::crypto::ring::default_provider().install_default();
+        let (stop_sender, _) = broadcast::channel::<StopSignal>(1);
+
+        let auth_state = auth_state(&config).map_err(|e| e.prop("Headless::setup".into()))?;
+        let auth_state = Arc::new(Mutex::new(auth_state));
+        let response_cache = config.response_cache();
+        let wfm = WFMClient::new(auth_state.clone(), stop_sender.subscribe())
+            .http_config(&config.http)
+            .response_cache(response_cache.clone());
+        let qf = QFClient::new(auth_state.clone(), stop_sender.subscribe())
+            .http_config(&config.http)
+            .response_cache(response_cache);
+
+        let db = InventoryDB::open(&config.paths.database).map_err(|e| {
+            AppError::new(e.to_string(), "Headless::setup: InventoryDB::open".into())
+        })?;
+        Ok(Self {
+            auth: auth_state,
+            wfm: Arc::new(Mutex::new(wfm)),
+            qf: Arc::new(Mutex::new(qf)),
+            db: Arc::new(Mutex::new(Some(db))),
+            _stop_sender: stop_sender,
+            config,
+        })
+    }
+
+    async fn close(self) -> Result<(), AppError> {
+        let db = self.db.lock().await.take();
+        if let Some(db) = db {
+            db.close().map_err(|(_, e)| AppError::new(e.to_string(), "Headless::close".into()))?;
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(args: &[String], config: Arc<Config>) -> Result<(), AppError> {
+    let args = parse_args(args).map_err(|e| e.prop("run".into()))?;
+    let res = match args.command {
+        Command::Help => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        Command::Decrypt(path) => return decrypt(path.as_deref(), &config),
+        Command::Logout => logout(&config),
+        Command::Accounts => accounts(&config),
+        Command::Switch(name) => switch(&config, &name),
+        command => {
+            let headless = Headless::setup(config).map_err(|e| e.prop("run".into()))?;
+            let res = match command {
+                Command::Login(email) => login(&headless, &email).await,
+                Command::Sync => sync(&headless).await,
+                Command::List => list(&headless, args.json).await,
+                Command::Price(oid) => price(&headless, &oid, args.json).await,
+                Command::Export(path) => export(&headless, path.as_deref()).await,
+                Command::Help
+                | Command::Logout
+                | Command::Accounts
+                | Command::Switch(_)
+                | Command::Decrypt(_) => unreachable!(),
+            };
+            headless.close().await?;
+            res
+        }
+    };
+    res.map_err(|e| e.prop("run".into()))
+}
+
+async fn login(headless: &Headless, email: &str) -> Result<(), AppError> {
+    print!("password: ");
+    io::stdout().flush().map_err(|e| AppError::new(e.to_string(), "login: flush".into()))?;
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|e| AppError::new(e.to_string(), "login: read_line".into()))?;
+
+    let (status, id, check_code, ingame_name) = {
+        let mut wfm = headless.wfm.lock().await.clone();
+        wfm.login(email, password.trim_end()).await.map_err(|e| e.prop("login".into()))?
+    };
+    if status.code >= 300 {
+        return Err(AppError::new(
+            format!("{} {}", status.code, status.text),
+            "login".into(),
+        ));
+    }
+    let mut qf = headless.qf.lock().await.clone();
+    qf.login(id, check_code, ingame_name.clone())
+        .await
+        .map_err(|e| e.prop("login".into()))?;
+    println!("Logged in as {ingame_name}");
+    Ok(())
+}
+
+fn auth_state(config: &Config) -> Result<AuthState, AppError> {
+    let auth = AuthState::setup(&config.paths.auth, config.secret_key())
+        .map_err(|e| e.prop("auth_state".into()))?;
+    Ok(auth.profiles_dir(&config.paths.profiles))
+}
+
+fn logout(config: &Config) -> Result<(), AppError> {
+    match auth_state(config) {
+        Ok(mut auth) => {
+            let name = auth.ingame_name.clone();
+            auth.wipe().map_err(|e| e.prop("logout".into()))?;
+            println!("Logged out of {name}");
+        }
+        // can't tell which account it was, the others are left alone
+        Err(e) => {
+            eprintln!("WARNING: Could not read {}: {e}", config.paths.auth.display());
+            AuthState::wipe_stored(&config.paths.auth, &config.paths.profiles, &config.secret_key())
+                .map_err(|e| e.prop("logout".into()))?;
+            println!("Logged out, removed {}", config.paths.auth.display());
+        }
+    }
+    Ok(())
+}
+
+fn accounts(config: &Config) -> Result<(), AppError> {
+    let auth = auth_state(config).map_err(|e| e.prop("accounts".into()))?;
+    let profiles = auth.profiles().map_err(|e| e.prop("accounts".into()))?;
+    profiles.iter().for_each(|name| {
+        let marker = if *name == auth.ingame_name { "*" } else { " " };
+        println!("{marker} {name}");
+    });
+    Ok(())
+}
+
+fn switch(config: &Config, name: &str) -> Result<(), AppError> {
+    let mut auth = auth_state(config).map_err(|e| e.prop("switch".into()))?;
+    auth.switch_profile(name).map_err(|e| e.prop("switch".into()))?;
+    println!("Switched to {name}, run `sync` to import its auctions");
+    Ok(())
+}
+
+fn decrypt(path: Option<&str>, config: &Config) -> Result<(), AppError> {
+    let path = path.map(Path::new).unwrap_or(&config.paths.last_data);
+    let upgrades = decrypt_last_data(path, &config.decrypt)
+        .map_err(|e| AppError::new(e.to_string(), "decrypt: decrypt_last_data".into()))?;
+    let json = to_string_pretty(&upgrades)
+        .map_err(|e| AppError::new(e.to_string(), "decrypt: to_string_pretty".into()))?;
+    println!("{json}");
+    Ok(())
+}
+
+async fn sync(headless: &Headless) -> Result<(), AppError> {
+    if let Err(e) = refresh_sessions(&headless.auth, &headless.wfm, &headless.qf).await {
+        eprintln!("WARNING: Could not refresh session: {e}");
+    }
+    let valid = {
+        let mut wfm = headless.wfm.lock().await.clone();
+        wfm.validate().await.map_err(|e| e.prop("sync".into()))?
+    };
+    if !valid {
+        return Err(AppError::new(
+            "Not logged in, run `login <email>` first".into(),
+            "sync".into(),
+        ));
+    }
+    let lookup = RivenDataLookup::setup(headless.qf.clone(), &headless.config.paths.riven_lookup)
+        .await
+        .map_err(|e| e.prop("sync".into()))?;
+    let (items, deleted) = sync_db(headless.db.clone(), &lookup, &headless.config, None)
+        .await
+        .map_err(|e| AppError::new(format!("{e:?}"), "sync: sync_db".into()))?;
+    println!("Synced {} rivens, removed {}", items.len(), deleted.len());
+    // before importing, which would otherwise forget the removed rivens' listings
+    close_removed_auctions(&items, &deleted, headless.wfm.clone(), headless.db.clone()).await;
+    let (count, unmatched) = import_auctions(headless.wfm.clone(), headless.db.clone())
+        .await
+        .map_err(|e| e.prop("sync".into()))?;
+    println!("Imported {count} auctions from warframe.market");
+    unmatched
+        .iter()
+        .for_each(|auc| println!("Auction {} doesn't match any riven in the inventory", auc.id));
+    Ok(())
+}
+
+async fn list(headless: &Headless, as_json: bool) -> Result<(), AppError> {
+    let (items, auctions) = {
+        let db = headless.db.lock().await;
+        let db = db.as_ref().expect("db must be some");
+        let items = db
+            .select_items()
+            .map_err(|e| AppError::new(e.to_string(), "list: select_items".into()))?;
+        let auctions = db
+            .select_auctions()
+            .map_err(|e| AppError::new(e.to_string(), "list: select_auctions".into()))?;
+        (items, auctions)
+    };
+    if as_json {
+        let json = to_string_pretty(&items)
+            .map_err(|e| AppError::new(e.to_string(), "list: to_string_pretty".into()))?;
+        println!("{json}");
+        return Ok(());
+    }
+    println!("{:<26} {:<32} {:>7}  stats", "oid", "riven", "price");
+    items.iter().for_each(|item| {
+        let price = auctions
+            .iter()
+            .find(|auc| auc.oid == item.oid.as_ref())
+            .and_then(|auc| auc.buyout_price)
+            .map(|price| format!("{price}p"))
+            .unwrap_or_default();
+        let stats: Vec<String> = item
+            .attributes
+            .iter()
+            .map(|attr| format!("{} {}", attr.value, attr.url_name))
+            .collect();
+        println!(
+            "{:<26} {:<32} {:>7}  {}",
+            item.oid,
+            format!("{} {}", item.weapon_name, item.name),
+            price,
+            stats.join(", ")
+        );
+    });
+    Ok(())
+}
+
+async fn price(headless: &Headless, oid: &str, as_json: bool) -> Result<(), AppError> {
+    let item = {
+        let db = headless.db.lock().await;
+        let db = db.as_ref().expect("db must be some");
+        db.select_item(oid)
+            .map_err(|e| AppError::new(e.to_string(), "price: select_item".into()))?
+    };
+    let item = item.ok_or_else(|| AppError::new(format!("No riven with id: {oid}"), "price".into()))?;
+    let suggestion = get_price_suggestion(&item, headless.wfm.clone(), headless.db.clone())
+        .await
+        .map_err(|e| e.prop("price".into()))?;
+    if as_json {
+        println!(
+            "{}",
+            json!({
+                "oid": oid,
+                "low": suggestion.low,
+                "median": suggestion.median,
+                "high": suggestion.high,
+                "samples": suggestion.samples,
+            })
+        );
+    } else if suggestion.samples == 0 {
+        println!("{} {}: no comparable auctions", item.weapon_name, item.name);
+    } else {
+        println!(
+            "{} {}: {}p / {}p / {}p ({} auctions)",
+            item.weapon_name,
+            item.name,
+            suggestion.low,
+            suggestion.median,
+            suggestion.high,
+            suggestion.samples
+        );
+    }
+    Ok(())
+}
+
+async fn export(headless: &Headless, path: Option<&str>) -> Result<(), AppError> {
+    let (items, auctions) = {
+        let db = headless.db.lock().await;
+        let db = db.as_ref().expect("db must be some");
+        let items = db
+            .select_items()
+            .map_err(|e| AppError::new(e.to_string(), "export: select_items".into()))?;
+        let auctions = db
+            .select_auctions()
+            .map_err(|e| AppError::new(e.to_string(), "export: select_auctions".into()))?;
+        (items, auctions)
+    };
+    let json = to_string_pretty(&json!({"rivens": items, "auctions": auctions}))
+        .map_err(|e| AppError::new(e.to_string(), "export: to_string_pretty".into()))?;
+    match path {
+        Some(path) => {
+            let mut file = File::create(path)
+                .map_err(|e| AppError::new(e.to_string(), "export: create".into()))?;
+            file.write_all(json.as_bytes())
+                .map_err(|e| AppError::new(e.to_string(), "export: write_all".into()))?;
+            println!("Exported {} rivens to {path}", items.len());
+        }
+        None => println!("{json}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, CliArgs, Command};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(&args(&["list", "--json"])).unwrap(),
+            CliArgs { command: Command::List, json: true }
+        );
+        assert_eq!(
+            parse_args(&args(&["price", "abc"])).unwrap(),
+            CliArgs { command: Command::Price("abc".into()), json: false }
+        );
+        assert_eq!(
+            parse_args(&args(&["decrypt"])).unwrap().command,
+            Command::Decrypt(None)
+        );
+        assert_eq!(parse_args(&args(&["logout"])).unwrap().command, Command::Logout);
+        assert_eq!(
+            parse_args(&args(&["switch", "tenno"])).unwrap().command,
+            Command::Switch("tenno".into())
+        );
+        assert!(parse_args(&args(&["switch"])).is_err());
+        assert!(parse_args(&args(&["price"])).is_err());
+        assert!(parse_args(&args(&["sync", "extra"])).is_err());
+        assert!(parse_args(&args(&["dance"])).is_err());
+    }
+}
@@ -0,0 +1,582 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    http_client::{cache::ResponseCache, retry::RetryPolicy, secret_store::SecretKey},
+    rate_limiter::RateLimiter,
+    AppError,
+};
+
+static APP_DIR: &str = "raw_html_rendering";
+static CONFIG_FILE: &str = "config.toml";
+
+// where older versions kept their files, relative to the directory given to
+// `--migrate`
+static LEGACY_DATABASE: &str = "inventory_db.sqlite3";
+static LEGACY_AUTH: &str = "auth.json";
+static LEGACY_RIVEN_LOOKUP: &str = "rivenLookupData.json";
+
+// env vars that override the config file, `KEY` and `IV` keep the names they
+// had in `.env` before there was a config file
+static ENV_CONFIG: &str = "RHR_CONFIG";
+static ENV_DATA_DIR: &str = "RHR_DATA_DIR";
+static ENV_HOST: &str = "RHR_HOST";
+static ENV_PORT: &str = "RHR_PORT";
+static ENV_WEBSOCKET_PORT: &str = "RHR_WEBSOCKET_PORT";
+static ENV_LAST_DATA: &str = "RHR_LAST_DATA";
+static ENV_AUTH_PASSPHRASE: &str = "RHR_AUTH_PASSPHRASE";
+static ENV_KEY: &str = "KEY";
+static ENV_IV: &str = "IV";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub paths: PathsConfig,
+    pub decrypt: DecryptConfig,
+    pub watcher: WatcherConfig,
+    pub http: HttpConfig,
+    // only ever comes from the environment so it's never written to disk
+    #[serde(skip)]
+    pub auth_passphrase: Option<String>,
+    // directory an older version ran in, only ever comes from `--migrate`
+    #[serde(skip)]
+    pub migrate_from: Option<PathBuf>,
+    // the browser url has the session token in it, so it's only printed when
+    // asked for with `--print-url`
+    #[serde(skip)]
+    pub print_start_url: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub websocket_port: u16,
+    // how often websocket clients are pinged, ones that don't answer before
+    // the next ping are dropped
+    pub websocket_ping_secs: u64,
+}
+
+// relative paths are relative to `data_dir`, not the working directory. except
+// `last_data`, the game writes it wherever it's installed so it stays relative
+// to the working directory like it was before there was a data directory
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathsConfig {
+    pub data_dir: PathBuf,
+    pub database: PathBuf,
+    pub last_data: PathBuf,
+    pub auth: PathBuf,
+    // key auth.json is encrypted with when there's no passphrase
+    pub auth_key: PathBuf,
+    // copies of auth.json for every account, to switch between them
+    pub profiles: PathBuf,
+    pub riven_lookup: PathBuf,
+    pub http_cache: PathBuf,
+}
+
+// comma separated bytes of the key and iv lastData.dat is encrypted with
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DecryptConfig {
+    pub key: String,
+    pub iv: String,
+}
+
+// how changes to lastData.dat are picked up, `poll` is for file systems that
+// don't report changes, like network drives
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatcherConfig {
+    pub poll: bool,
+    pub poll_interval_ms: u64,
+    pub debounce_ms: u64,
+}
+
+// connections kept to each api. `pipeline_depth` above 1 lets GET requests
+// that are waiting on a busy pool share a connection. failed requests are
+// retried `retries` times, waiting up to twice as long each time. the rate
+// limits are requests per second to each api, routes can be limited further
+// with `[[http.route_rate_limits]]`. when `cache` is on, GET responses of the
+// `cache_routes` are kept and used without asking the api for `ttl_secs`.
+// without any, the lookup data and auction searches of the configured apis are
+// cached
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub wfm_url: String,
+    pub qf_url: String,
+    pub wfm_rate_limit: f64,
+    pub qf_rate_limit: f64,
+    pub connections: usize,
+    pub idle_timeout_secs: u64,
+    pub pipeline_depth: usize,
+    pub retries: u32,
+    pub retry_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    pub cache: bool,
+    pub route_rate_limits: Vec<RouteRateLimit>,
+    pub cache_routes: Option<Vec<CacheRoute>>,
+}
+
+// requests per second to every uri starting with `url`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RouteRateLimit {
+    pub url: String,
+    pub per_sec: f64,
+}
+
+// responses from every uri starting with `url` are used for `ttl_secs`, after
+// that they're revalidated
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CacheRoute {
+    pub url: String,
+    pub ttl_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".into(),
+            port: 8000,
+            websocket_port: 8069,
+            websocket_ping_secs: 30,
+        }
+    }
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: dirs::data_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join(APP_DIR),
+            database: "inventory_db.sqlite3".into(),
+            last_data: "lastData.dat".into(),
+            auth: "auth.json".into(),
+            auth_key: "auth.key".into(),
+            profiles: "profiles".into(),
+            riven_lookup: "rivenLookupData.json".into(),
+            http_cache: "http_cache.sqlite3".into(),
+        }
+    }
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            poll: false,
+            poll_interval_ms: 2000,
+            debounce_ms: 500,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            wfm_url: "https://api.warframe.market/v1".into(),
+            qf_url: "https://api.quantframe.app".into(),
+            wfm_rate_limit: 1.0,
+            qf_rate_limit: 5.0,
+            connections: 4,
+            idle_timeout_secs: 60,
+            pipeline_depth: 1,
+            retries: 3,
+            retry_delay_ms: 250,
+            retry_max_delay_ms: 10_000,
+            cache: true,
+            route_rate_limits: vec![],
+            cache_routes: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries,
+            base_delay: Duration::from_millis(self.retry_delay_ms),
+            max_delay: Duration::from_millis(self.retry_max_delay_ms),
+        }
+    }
+
+    // the rate limiter can't space out requests at a rate of 0 or less
+    fn validate(&self) -> Result<(), AppError> {
+        let limits = [
+            ("wfm_rate_limit", self.wfm_rate_limit),
+            ("qf_rate_limit", self.qf_rate_limit),
+        ];
+        let routes = self
+            .route_rate_limits
+            .iter()
+            .map(|route| (route.url.as_str(), route.per_sec));
+        match limits.into_iter().chain(routes).find(|(_, rate)| !(rate.is_finite() && *rate > 0.0)) {
+            Some((name, rate)) => Err(AppError::new(
+                format!("rate limit for {name} has to be above 0, got {rate}"),
+                "HttpConfig::validate".into(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // the user's own auctions are left out, a stale copy of them would make
+    // importing drop listings that are still up
+    pub fn cache_routes(&self) -> Vec<CacheRoute> {
+        if let Some(routes) = &self.cache_routes {
+            return routes.clone();
+        }
+        let wfm_url = self.wfm_url.trim_end_matches('/');
+        let qf_url = self.qf_url.trim_end_matches('/');
+        vec![
+            CacheRoute {
+                url: format!("{qf_url}/items/riven/raw"),
+                ttl_secs: 24 * 60 * 60,
+            },
+            CacheRoute {
+                url: format!("{wfm_url}/auctions/search"),
+                ttl_secs: 30,
+            },
+        ]
+    }
+
+    // for the api at `url`, with the routes under it
+    pub fn rate_limiter(&self, url: &str, per_sec: f64) -> RateLimiter {
+        let second = Duration::from_secs(1);
+        self.route_rate_limits
+            .iter()
+            .filter(|route| route.url.starts_with(url))
+            .fold(RateLimiter::new(url, per_sec, second), |limiter, route| {
+                limiter.route(&route.url, route.per_sec, second)
+            })
+    }
+}
+
+impl ServerConfig {
+    pub fn url(&self) -> String {
+        format!("http://{}:{}", self.host, self.port)
+    }
+
+    pub fn websocket_url(&self) -> String {
+        format!("ws://{}:{}", self.host, self.websocket_port)
+    }
+}
+
+fn default_config_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR)
+        .join(CONFIG_FILE)
+}
+
+fn parse_port(value: &str, name: &str) -> Result<u16, AppError> {
+    value
+        .parse()
+        .map_err(|_| AppError::new(format!("Invalid port for {name}: {value}"), "parse_port".into()))
+}
+
+impl Config {
+    pub fn from_toml(content: &str) -> Result<Self, AppError> {
+        toml::from_str(content).map_err(|e| AppError::new(e.to_string(), "Config::from_toml".into()))
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), AppError> {
+        if let Some(v) = var(ENV_DATA_DIR) {
+            self.paths.data_dir = v.into();
+        }
+        if let Some(v) = var(ENV_HOST) {
+            self.server.host = v;
+        }
+        if let Some(v) = var(ENV_PORT) {
+            self.server.port = parse_port(&v, ENV_PORT).map_err(|e| e.prop("apply_env".into()))?;
+        }
+        if let Some(v) = var(ENV_WEBSOCKET_PORT) {
+            self.server.websocket_port =
+                parse_port(&v, ENV_WEBSOCKET_PORT).map_err(|e| e.prop("apply_env".into()))?;
+        }
+        if let Some(v) = var(ENV_LAST_DATA) {
+            self.paths.last_data = v.into();
+        }
+        if let Some(v) = var(ENV_AUTH_PASSPHRASE).filter(|v| !v.is_empty()) {
+            self.auth_passphrase = Some(v);
+        }
+        if let Some(v) = var(ENV_KEY) {
+            self.decrypt.key = v;
+        }
+        if let Some(v) = var(ENV_IV) {
+            self.decrypt.iv = v;
+        }
+        Ok(())
+    }
+
+    // takes the global options out of `args` and leaves the rest for the cli
+    fn apply_args(&mut self, args: Vec<String>) -> Result<Vec<String>, AppError> {
+        let mut rest = Vec::with_capacity(args.len());
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next().ok_or_else(|| {
+                    AppError::new(format!("missing value for {arg}"), "apply_args".into())
+                })
+            };
+            match arg.as_str() {
+                "--data-dir" => self.paths.data_dir = value()?.into(),
+                "--host" => self.server.host = value()?,
+                "--port" => self.server.port = parse_port(&value()?, "--port")?,
+                "--websocket-port" => {
+                    self.server.websocket_port = parse_port(&value()?, "--websocket-port")?
+                }
+                "--last-data" => self.paths.last_data = value()?.into(),
+                "--migrate" => self.migrate_from = Some(value()?.into()),
+                "--print-url" => self.print_start_url = true,
+                // already handled before the file was read
+                "--config" => {
+                    value()?;
+                }
+                _ => rest.push(arg),
+            }
+        }
+        Ok(rest)
+    }
+
+    fn resolve_paths(&mut self) {
+        let data_dir = self.paths.data_dir.clone();
+        [
+            &mut self.paths.database,
+            &mut self.paths.auth,
+            &mut self.paths.auth_key,
+            &mut self.paths.profiles,
+            &mut self.paths.riven_lookup,
+            &mut self.paths.http_cache,
+        ]
+        .into_iter()
+        .for_each(|path| {
+            if path.is_relative() {
+                *path = data_dir.join(&path);
+            }
+        });
+    }
+
+    // defaults, then the config file, then env vars, then `args`. returns the
+    // arguments that weren't config overrides
+    pub fn load(args: Vec<String>) -> Result<(Self, Vec<String>), AppError> {
+        let path = args
+            .iter()
+            .position(|arg| arg == "--config")
+            .and_then(|i| args.get(i + 1))
+            .map(PathBuf::from)
+            .or_else(|| env::var(ENV_CONFIG).ok().map(PathBuf::from))
+            .unwrap_or_else(default_config_path);
+
+        let mut config = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| AppError::new(e.to_string(), "Config::load: read_to_string".into()))?;
+            Self::from_toml(&content).map_err(|e| e.prop("Config::load".into()))?
+        } else {
+            let config = Self::default();
+            // leave a file behind to edit, it's fine to run without one
+            if let Err(e) = config.write(&path) {
+                eprintln!("WARNING: Could not write default config: {e}");
+            }
+            config
+        };
+        config
+            .apply_env(|name| env::var(name).ok())
+            .map_err(|e| e.prop("Config::load".into()))?;
+        let rest = config.apply_args(args).map_err(|e| e.prop("Config::load".into()))?;
+        config.http.validate().map_err(|e| e.prop("Config::load".into()))?;
+        config.resolve_paths();
+
+        fs::create_dir_all(&config.paths.data_dir)
+            .map_err(|e| AppError::new(e.to_string(), "Config::load: create_dir_all".into()))?;
+        if let Some(legacy_dir) = &config.migrate_from {
+            config.migrate_legacy_files(legacy_dir);
+        }
+        Ok((config, rest))
+    }
+
+    // older versions kept everything in the working directory. files are copied
+    // over unless there's one in the new place already, the old ones are left
+    // alone. auth.json is encrypted once it's loaded from the new place
+    fn migrate_legacy_files(&self, legacy_dir: &Path) {
+        [
+            (LEGACY_DATABASE, &self.paths.database),
+            (LEGACY_AUTH, &self.paths.auth),
+            (LEGACY_RIVEN_LOOKUP, &self.paths.riven_lookup),
+        ]
+        .into_iter()
+        .map(|(name, path)| (legacy_dir.join(name), path))
+        .filter(|(legacy, path)| legacy.is_file() && legacy != *path)
+        .for_each(|(legacy, path)| {
+            if path.exists() {
+                eprintln!(
+                    "WARNING: {} is left over from an older version, {} is used instead. delete it once it's not needed",
+                    legacy.display(),
+                    path.display()
+                );
+                return;
+            }
+            match fs::copy(&legacy, path) {
+                Ok(_) => eprintln!("INFO: Copied {} to {}", legacy.display(), path.display()),
+                Err(e) => eprintln!("WARNING: Could not copy {} to {}: {e}", legacy.display(), path.display()),
+            }
+        });
+    }
+
+    pub fn secret_key(&self) -> SecretKey {
+        match &self.auth_passphrase {
+            Some(passphrase) => SecretKey::Passphrase(passphrase.clone()),
+            None => SecretKey::KeyFile(self.paths.auth_key.clone()),
+        }
+    }
+
+    // `None` when caching is off or the cache can't be opened, requests work
+    // the same either way
+    pub fn response_cache(&self) -> Option<Arc<ResponseCache>> {
+        if !self.http.cache {
+            return None;
+        }
+        let cache = match ResponseCache::open(&self.paths.http_cache) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("WARNING: Could not open the response cache, requests won't be cached: {e}");
+                return None;
+            }
+        };
+        let cache = self.http.cache_routes().iter().fold(cache, |cache, route| {
+            cache.route(&route.url, Duration::from_secs(route.ttl_secs))
+        });
+        Some(Arc::new(cache))
+    }
+
+    fn write(&self, path: &Path) -> Result<(), AppError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| AppError::new(e.to_string(), "Config::write: create_dir_all".into()))?;
+        }
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| AppError::new(e.to_string(), "Config::write: to_string_pretty".into()))?;
+        let mut file = File::create(path)
+            .map_err(|e| AppError::new(e.to_string(), "Config::write: create".into()))?;
+        file.write_all(content.as_bytes())
+            .map_err(|e| AppError::new(e.to_string(), "Config::write: write_all".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::Config;
+
+    #[test]
+    fn test_config_overrides() {
+        let mut config = Config::from_toml(
+            r#"
+            [server]
+            port = 9000
+
+            [paths]
+            data_dir = "/data"
+            last_data = "/game/lastData.dat"
+
+            [http]
+            connections = 8
+
+            [[http.route_rate_limits]]
+            url = "https://api.warframe.market/v1/auctions/search"
+            per_sec = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.websocket_port, 8069);
+        assert_eq!(config.http.connections, 8);
+        assert_eq!(config.http.pipeline_depth, 1);
+        assert_eq!(config.http.route_rate_limits[0].per_sec, 0.5);
+
+        config
+            .apply_env(|name| match name {
+                "RHR_PORT" => Some("9100".into()),
+                "KEY" => Some("1,2,3".into()),
+                _ => None,
+            })
+            .unwrap();
+        let rest = config
+            .apply_args(vec!["--port".into(), "9200".into(), "list".into(), "--json".into()])
+            .unwrap();
+        config.resolve_paths();
+
+        assert_eq!(rest, vec!["list".to_string(), "--json".to_string()]);
+        assert_eq!(config.server.port, 9200);
+        assert_eq!(config.decrypt.key, "1,2,3");
+        assert_eq!(config.paths.database, PathBuf::from("/data/inventory_db.sqlite3"));
+        assert_eq!(config.paths.last_data, PathBuf::from("/game/lastData.dat"));
+        assert!(config.apply_env(|_| Some("not a port".into())).is_err());
+        assert!(config.http.validate().is_ok());
+
+        config.http.route_rate_limits[0].per_sec = 0.0;
+        assert!(config.http.validate().is_err());
+        config.http.route_rate_limits.clear();
+        config.http.wfm_rate_limit = -1.0;
+        assert!(config.http.validate().is_err());
+
+        // the game's file isn't in the data directory
+        let mut config = Config::default();
+        config.resolve_paths();
+        assert_eq!(config.paths.last_data, PathBuf::from("lastData.dat"));
+    }
+
+    #[test]
+    fn test_migrate_legacy_files() {
+        let legacy = tempfile::tempdir().unwrap();
+        let data = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.paths.data_dir = data.path().into();
+        config.resolve_paths();
+        fs::write(legacy.path().join("auth.json"), "{}").unwrap();
+        fs::write(legacy.path().join("inventory_db.sqlite3"), "old").unwrap();
+        fs::write(&config.paths.database, "new").unwrap();
+
+        config.migrate_legacy_files(legacy.path());
+
+        assert!(config.paths.auth.exists());
+        assert!(legacy.path().join("auth.json").exists());
+        // what's in the new place already is never overwritten
+        assert_eq!(fs::read_to_string(&config.paths.database).unwrap(), "new");
+        assert!(legacy.path().join("inventory_db.sqlite3").exists());
+    }
+
+    #[test]
+    fn test_cache_routes() {
+        let config = Config::from_toml(
+            r#"
+            [http]
+            wfm_url = "http://localhost:8080/v1/"
+            "#,
+        )
+        .unwrap();
+        let routes = config.http.cache_routes();
+        assert!(routes.iter().any(|route| route.url == "http://localhost:8080/v1/auctions/search"));
+        assert!(routes.iter().all(|route| !route.url.contains("/profile/")));
+
+        let config = Config::from_toml(
+            r#"
+            [[http.cache_routes]]
+            url = "https://api.quantframe.app/items/riven/raw"
+            ttl_secs = 60
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.http.cache_routes().len(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    ffi::OsString,
+    future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use notify::{Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time::timeout,
+};
+
+use crate::{config::WatcherConfig, AppError};
+
+// watches the directory the file is in rather than the file itself, the game
+// replaces lastData.dat instead of writing into it which would drop a watch
+// on the old file
+pub struct FileWatcher {
+    receiver: UnboundedReceiver<()>,
+    debounce: Duration,
+    // no events are sent anymore once this is dropped
+    _watcher: Box<dyn Watcher + Send>,
+}
+
+fn watched_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn event_handler(
+    file_name: Option<OsString>,
+    sender: UnboundedSender<()>,
+) -> impl FnMut(notify::Result<Event>) + Send + 'static {
+    move |res| match res {
+        Ok(event) => {
+            let is_write = matches!(
+                event.kind,
+                EventKind::Any | EventKind::Create(_) | EventKind::Modify(_)
+            );
+            let is_file = event
+                .paths
+                .iter()
+                .any(|path| path.file_name() == file_name.as_deref());
+            if is_write && is_file {
+                let _ = sender.send(());
+            }
+        }
+        Err(e) => eprintln!("ERROR: File watcher error: {e}"),
+    }
+}
+
+impl FileWatcher {
+    // uses inotify (or whatever the platform has) unless `config.poll` is set
+    // or the native watcher can't be set up, then polls the file's mtime
+    pub fn new(path: &Path, config: &WatcherConfig) -> Result<Self, AppError> {
+        if !config.poll {
+            match Self::native(path, config) {
+                Ok(v) => return Ok(v),
+                Err(e) => eprintln!("WARNING: Falling back to polling for file changes: {e}"),
+            }
+        }
+        Self::polling(path, config).map_err(|e| e.prop("FileWatcher::new".into()))
+    }
+
+    fn native(path: &Path, config: &WatcherConfig) -> Result<Self, AppError> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut watcher = RecommendedWatcher::new(
+            event_handler(path.file_name().map(|n| n.to_owned()), sender),
+            notify::Config::default(),
+        )
+        .map_err(|e| AppError::new(e.to_string(), "FileWatcher::native: new".into()))?;
+        watcher
+            .watch(&watched_dir(path), RecursiveMode::NonRecursive)
+            .map_err(|e| AppError::new(e.to_string(), "FileWatcher::native: watch".into()))?;
+        Ok(Self {
+            receiver,
+            debounce: Duration::from_millis(config.debounce_ms),
+            _watcher: Box::new(watcher),
+        })
+    }
+
+    fn polling(path: &Path, config: &WatcherConfig) -> Result<Self, AppError> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut watcher = PollWatcher::new(
+            event_handler(path.file_name().map(|n| n.to_owned()), sender),
+            notify::Config::default()
+                .with_poll_interval(Duration::from_millis(config.poll_interval_ms)),
+        )
+        .map_err(|e| AppError::new(e.to_string(), "FileWatcher::polling: new".into()))?;
+        watcher
+            .watch(&watched_dir(path), RecursiveMode::NonRecursive)
+            .map_err(|e| AppError::new(e.to_string(), "FileWatcher::polling: watch".into()))?;
+        Ok(Self {
+            receiver,
+            debounce: Duration::from_millis(config.debounce_ms),
+            _watcher: Box::new(watcher),
+        })
+    }
+
+    // resolves once the file was written to and then left alone for the
+    // debounce duration, the game writes the file in a couple of chunks
+    pub async fn changed(&mut self) {
+        if self.receiver.recv().await.is_none() {
+            return future::pending().await;
+        }
+        while let Ok(Some(())) = timeout(self.debounce, self.receiver.recv()).await {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, time::Duration};
+
+    use tokio::time::timeout;
+
+    use crate::config::WatcherConfig;
+
+    use super::FileWatcher;
+
+    async fn assert_debounced(watcher: &mut FileWatcher, dir: &Path) {
+        let path = dir.join("lastData.dat");
+        // other files in the directory shouldn't count as changes
+        fs::write(dir.join("other.dat"), "other").unwrap();
+        assert!(timeout(Duration::from_millis(400), watcher.changed()).await.is_err());
+
+        for i in 0..3 {
+            fs::write(&path, format!("chunk {i}")).unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(timeout(Duration::from_secs(5), watcher.changed()).await.is_ok());
+        // all three writes were a single change
+        assert!(timeout(Duration::from_millis(400), watcher.changed()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = WatcherConfig {
+            debounce_ms: 100,
+            ..Default::default()
+        };
+        let mut watcher = FileWatcher::new(&dir.path().join("lastData.dat"), &config).unwrap();
+        assert_debounced(&mut watcher, dir.path()).await;
+    }
+
+    #[tokio::test]
+    async fn test_file_watcher_polling() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = WatcherConfig {
+            poll: true,
+            poll_interval_ms: 20,
+            debounce_ms: 100,
+        };
+        let mut watcher = FileWatcher::new(&dir.path().join("lastData.dat"), &config).unwrap();
+        assert_debounced(&mut watcher, dir.path()).await;
+    }
+}
@@ -1,12 +1,17 @@
-use std::env;
-use std::io::{Read, Write};
-use std::string::String;
 use std::sync::Arc;
-use std::{fs::File, path::PathBuf};
+use std::time::Duration;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::AppError;
+use crate::{
+    http_client::secret_store::{remove_file, write_private_file, Envelope, SecretKey},
+    jwt::{token_lifetime, TokenLifetime},
+    AppError,
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuthState {
@@ -17,6 +22,30 @@ pub struct AuthState {
     pub qf_access_token: Arc<str>,
     pub check_code: Arc<str>,
     pub id: Arc<str>,
+    // read from the tokens whenever they're set, `None` if a token doesn't say
+    #[serde(skip)]
+    pub wfm_lifetime: Option<TokenLifetime>,
+    #[serde(skip)]
+    pub qf_lifetime: Option<TokenLifetime>,
+    // a server answered 401 to the token, it's dead no matter what it says
+    #[serde(skip)]
+    pub wfm_rejected: bool,
+    #[serde(skip)]
+    pub qf_rejected: bool,
+    // where `update` writes to
+    #[serde(skip)]
+    path: PathBuf,
+    // every account that logged in keeps a copy here to switch back to, empty
+    // when there's no switching
+    #[serde(skip)]
+    profiles: PathBuf,
+    // what the file is encrypted with
+    #[serde(skip, default = "default_key")]
+    key: SecretKey,
+}
+
+fn default_key() -> SecretKey {
+    SecretKey::KeyFile(PathBuf::new())
 }
 
 impl Default for AuthState {
@@ -27,55 +56,305 @@ impl Default for AuthState {
             check_code: "".into(),
             ingame_name: "".into(),
             qf_access_token: "".into(),
+            wfm_lifetime: None,
+            qf_lifetime: None,
+            wfm_rejected: false,
+            qf_rejected: false,
+            path: PathBuf::new(),
+            profiles: PathBuf::new(),
+            key: default_key(),
         }
     }
 }
 
 impl AuthState {
-    pub fn setup() -> Result<Self, AppError> {
-        let path: PathBuf = env::var("PWD")
-            .map_err(|e| AppError::new(e.to_string().into(), "setup: env::var".into()))?
-            .into();
-        let path = path.join("auth.json");
+    pub fn setup(path: &Path, key: SecretKey) -> Result<Self, AppError> {
         if !path.exists() {
-            let mut file = File::create(path)
-                .map_err(|e| AppError::new(e.to_string().into(), "setup: create".into()))?;
-            let default = AuthState::default();
-            let json = serde_json::to_string_pretty(&default).map_err(|e| {
-                AppError::new(e.to_string().into(), "setup: to_string_pretty".into())
-            })?;
-            file.write_all(json.as_bytes())
-                .map_err(|e| AppError::new(e.to_string().into(), "setup: as_bytes".into()))?;
+            let default = AuthState {
+                path: path.into(),
+                key,
+                ..Default::default()
+            };
+            default.update().map_err(|e| e.prop("setup".into()))?;
             return Ok(default);
         };
-        let mut file = File::open(path)
-            .map_err(|e| AppError::new(e.to_string().into(), "setup: open".into()))?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)
-            .map_err(|e| AppError::new(e.to_string().into(), "setup: read_to_string".into()))?;
-        let final_auth = serde_json::from_str(&content)
-            .map_err(|e| AppError::new(e.to_string().into(), "setup: from_str".into()))?;
-        Ok(final_auth)
+        let (final_auth, plaintext) = AuthState::read(path, &key).map_err(|e| e.prop("setup".into()))?;
+        let auth = AuthState {
+            path: path.into(),
+            key,
+            ..final_auth
+        };
+        if plaintext {
+            eprintln!("INFO: Encrypting {}", path.display());
+            auth.update().map_err(|e| e.prop("setup".into()))?;
+        }
+        Ok(auth)
+    }
+
+    pub fn profiles_dir(mut self, dir: &Path) -> Self {
+        self.profiles = dir.into();
+        self
+    }
+
+    // also tells whether the file still has to be encrypted
+    fn read(path: &Path, key: &SecretKey) -> Result<(Self, bool), AppError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| AppError::new(e.to_string(), "read: read_to_string".into()))?;
+        let (mut auth, plaintext) = match serde_json::from_str::<Envelope>(&content) {
+            Ok(envelope) => {
+                let json = key.decrypt(&envelope).map_err(|e| e.prop("read".into()))?;
+                let auth = serde_json::from_slice::<AuthState>(&json)
+                    .map_err(|e| AppError::new(e.to_string(), "read: from_slice".into()))?;
+                (auth, false)
+            }
+            // written before the file was encrypted
+            Err(_) => {
+                let auth = serde_json::from_str::<AuthState>(&content)
+                    .map_err(|e| AppError::new(e.to_string(), "read: from_str".into()))?;
+                (auth, true)
+            }
+        };
+        auth.wfm_lifetime = token_lifetime(&auth.wfm_access_token);
+        auth.qf_lifetime = token_lifetime(&auth.qf_access_token);
+        Ok((auth, plaintext))
     }
 
     pub fn update(&self) -> Result<(), AppError> {
-        let path: PathBuf = env::var("PWD")
-            .map_err(|e| AppError::new(e.to_string().into(), "setup: env::var".into()))?
-            .into();
-        let path = path.join("auth.json");
-        let mut file = File::create(path)
-            .map_err(|e| AppError::new(e.to_string().into(), "update: create".into()))?;
-        let json = serde_json::to_string_pretty(self)
-            .map_err(|e| AppError::new(e.to_string().into(), "update: to_string_pretty".into()))?;
-        file.write_all(json.as_bytes())
-            .map_err(|e| AppError::new(e.to_string().into(), "update: write_all".into()))?;
-        Ok(())
+        let json = serde_json::to_vec(self)
+            .map_err(|e| AppError::new(e.to_string(), "update: to_vec".into()))?;
+        let envelope = self.key.encrypt(&json).map_err(|e| e.prop("update".into()))?;
+        let content = serde_json::to_string_pretty(&envelope)
+            .map_err(|e| AppError::new(e.to_string(), "update: to_string_pretty".into()))?;
+        write_private_file(&self.path, content.as_bytes()).map_err(|e| e.prop("update".into()))?;
+
+        // keeps the profile's tokens as fresh as the active ones
+        if self.profiles.as_os_str().is_empty() || self.wfm_access_token.is_empty() {
+            return Ok(());
+        }
+        let profile = self.profile_path(&self.ingame_name).map_err(|e| e.prop("update".into()))?;
+        fs::create_dir_all(&self.profiles)
+            .map_err(|e| AppError::new(e.to_string(), "update: create_dir_all".into()))?;
+        write_private_file(&profile, content.as_bytes()).map_err(|e| e.prop("update".into()))
+    }
+
+    // ingame names only use these, anything else could point out of the directory
+    fn profile_path(&self, name: &str) -> Result<PathBuf, AppError> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(AppError::new(
+                format!("Invalid profile name: {name}"),
+                "profile_path".into(),
+            ));
+        }
+        Ok(self.profiles.join(format!("{name}.json")))
+    }
+
+    // names of the accounts that can be switched to, sorted
+    pub fn profiles(&self) -> Result<Vec<Arc<str>>, AppError> {
+        if self.profiles.as_os_str().is_empty() || !self.profiles.exists() {
+            return Ok(vec![]);
+        }
+        let entries = fs::read_dir(&self.profiles)
+            .map_err(|e| AppError::new(e.to_string(), "profiles: read_dir".into()))?;
+        let mut names = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                Some(Arc::from(path.file_stem()?.to_str()?))
+            })
+            .collect::<Vec<Arc<str>>>();
+        names.sort();
+        Ok(names)
+    }
+
+    // replaces the active account with a stored one, the clients pick it up
+    // with their next request since they share this state
+    pub fn switch_profile(&mut self, name: &str) -> Result<(), AppError> {
+        let profile = self.profile_path(name).map_err(|e| e.prop("switch_profile".into()))?;
+        if !profile.exists() {
+            return Err(AppError::new(
+                format!("No account named {name}"),
+                "switch_profile".into(),
+            ));
+        }
+        let (stored, _) = AuthState::read(&profile, &self.key).map_err(|e| e.prop("switch_profile".into()))?;
+        *self = AuthState {
+            path: self.path.clone(),
+            profiles: self.profiles.clone(),
+            key: self.key.clone(),
+            ..stored
+        };
+        self.update().map_err(|e| e.prop("switch_profile".into()))
+    }
+
+    // leaves the account without forgetting it, so another one can log in
+    pub fn sign_out(&mut self) -> Result<(), AppError> {
+        *self = AuthState {
+            path: self.path.clone(),
+            profiles: self.profiles.clone(),
+            key: self.key.clone(),
+            ..Default::default()
+        };
+        self.update().map_err(|e| e.prop("sign_out".into()))
     }
 
     pub fn set(&mut self, new_auth: AuthState) {
         self.id = new_auth.id;
         self.ingame_name = new_auth.ingame_name;
-        self.wfm_access_token = new_auth.wfm_access_token;
+        self.set_wfm_token(new_auth.wfm_access_token);
         self.check_code = new_auth.check_code;
     }
+
+    pub fn set_wfm_token(&mut self, token: Arc<str>) {
+        self.wfm_lifetime = token_lifetime(&token);
+        self.wfm_access_token = token;
+        self.wfm_rejected = false;
+    }
+
+    pub fn set_qf_token(&mut self, token: Arc<str>) {
+        self.qf_lifetime = token_lifetime(&token);
+        self.qf_access_token = token;
+        self.qf_rejected = false;
+    }
+
+    // the user has to log in again for warframe.market to accept requests
+    pub fn wfm_expired(&self) -> bool {
+        self.wfm_access_token.is_empty()
+            || self.wfm_rejected
+            || self.wfm_lifetime.is_some_and(|l| l.expires_within(Duration::ZERO))
+    }
+
+    // still alive but running out within `margin`, a request now gets a fresh one
+    pub fn wfm_expiring(&self, margin: Duration) -> bool {
+        !self.wfm_expired() && self.wfm_lifetime.is_some_and(|l| l.expires_within(margin))
+    }
+
+    // quantframe hands out a new token for the stored check code, so it can
+    // always be replaced as long as there's a warframe.market login
+    pub fn qf_needs_login(&self, margin: Duration) -> bool {
+        !self.check_code.is_empty()
+            && (self.qf_access_token.is_empty()
+                || self.qf_rejected
+                || self.qf_lifetime.is_some_and(|l| l.expires_within(margin)))
+    }
+
+    // forgets the account and deletes its stored secrets. once no account is
+    // left the key they were encrypted with goes too, so old copies of the
+    // files are useless
+    pub fn wipe(&mut self) -> Result<(), AppError> {
+        if !self.profiles.as_os_str().is_empty() && !self.ingame_name.is_empty() {
+            let profile = self.profile_path(&self.ingame_name).map_err(|e| e.prop("wipe".into()))?;
+            remove_file(&profile).map_err(|e| e.prop("wipe".into()))?;
+        }
+        AuthState::wipe_stored(&self.path, &self.profiles, &self.key).map_err(|e| e.prop("wipe".into()))?;
+        *self = AuthState {
+            path: self.path.clone(),
+            profiles: self.profiles.clone(),
+            key: self.key.clone(),
+            ..Default::default()
+        };
+        Ok(())
+    }
+
+    // same as `wipe` but doesn't need the file to be readable, e.g. when the
+    // passphrase was forgotten
+    pub fn wipe_stored(path: &Path, profiles: &Path, key: &SecretKey) -> Result<(), AppError> {
+        remove_file(path).map_err(|e| e.prop("wipe_stored".into()))?;
+        let others = AuthState::default()
+            .profiles_dir(profiles)
+            .profiles()
+            .map_err(|e| e.prop("wipe_stored".into()))?;
+        match key {
+            SecretKey::KeyFile(key_path) if others.is_empty() => {
+                remove_file(key_path).map_err(|e| e.prop("wipe_stored".into()))?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use crate::http_client::secret_store::SecretKey;
+
+    use super::AuthState;
+
+    #[test]
+    fn test_auth_state_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.json");
+        let key = SecretKey::KeyFile(dir.path().join("auth.key"));
+
+        // files from before encryption are picked up and encrypted
+        fs::write(
+            &path,
+            r#"{"ingame_name": "tenno", "wfm_access_token": "secret-jwt", "check_code": "c", "id": "1"}"#,
+        )
+        .unwrap();
+        let mut auth = AuthState::setup(&path, key.clone()).unwrap();
+        assert_eq!(&*auth.wfm_access_token, "secret-jwt");
+        assert!(!fs::read_to_string(&path).unwrap().contains("secret-jwt"));
+
+        auth.qf_access_token = Arc::from("secret-qf");
+        auth.update().unwrap();
+        let auth = AuthState::setup(&path, key.clone()).unwrap();
+        assert_eq!(&*auth.qf_access_token, "secret-qf");
+        assert!(!fs::read_to_string(&path).unwrap().contains("secret-qf"));
+
+        let mut auth = auth;
+        auth.wipe().unwrap();
+        assert!(!path.exists());
+        assert!(!dir.path().join("auth.key").exists());
+        assert_eq!(&*auth.wfm_access_token, "");
+    }
+
+    #[test]
+    fn test_auth_state_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.json");
+        let profiles = dir.path().join("profiles");
+        let key = SecretKey::KeyFile(dir.path().join("auth.key"));
+        let login = |auth: &mut AuthState, name: &str| {
+            let user = AuthState {
+                ingame_name: Arc::from(name),
+                wfm_access_token: Arc::from(format!("jwt-{name}")),
+                ..Default::default()
+            };
+            auth.set(user);
+            auth.update().unwrap();
+        };
+
+        let mut auth = AuthState::setup(&path, key.clone()).unwrap().profiles_dir(&profiles);
+        assert!(auth.profiles().unwrap().is_empty());
+        login(&mut auth, "tenno");
+        auth.sign_out().unwrap();
+        assert_eq!(&*auth.wfm_access_token, "");
+        login(&mut auth, "other_tenno");
+        assert_eq!(auth.profiles().unwrap(), vec![Arc::from("other_tenno"), Arc::from("tenno")]);
+
+        auth.switch_profile("tenno").unwrap();
+        assert_eq!(&*auth.wfm_access_token, "jwt-tenno");
+        let stored = AuthState::setup(&path, key.clone()).unwrap();
+        assert_eq!(&*stored.ingame_name, "tenno");
+        assert!(auth.switch_profile("../auth").is_err());
+        assert!(auth.switch_profile("nobody").is_err());
+
+        // the key stays around until the last account is gone
+        auth.wipe().unwrap();
+        assert_eq!(auth.profiles().unwrap(), vec![Arc::from("other_tenno")]);
+        assert!(dir.path().join("auth.key").exists());
+        auth.switch_profile("other_tenno").unwrap();
+        auth.wipe().unwrap();
+        assert!(auth.profiles().unwrap().is_empty());
+        assert!(!dir.path().join("auth.key").exists());
+    }
 }
@@ -0,0 +1,240 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use time::OffsetDateTime;
+
+use super::client::{Header, RequestBuilder, Response, StatusCode};
+
+static SQL_TABLE_RESPONSES: &str = "CREATE TABLE IF NOT EXISTS responses ( method text, uri text, status integer, headers text, body blob, etag text, last_modified text, stored integer, primary key ( method, uri))";
+static SQL_RESPONSE_INSERT: &str = "INSERT OR REPLACE INTO responses ( method, uri, status, headers, body, etag, last_modified, stored) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
+static SQL_SELECT_RESPONSE: &str = "SELECT status, headers, body, etag, last_modified, stored FROM responses WHERE method = ?1 AND uri = ?2";
+static SQL_RESPONSE_TOUCH: &str = "UPDATE responses SET stored = ?3 WHERE method = ?1 AND uri = ?2";
+
+// a stored response, `fresh` while it's younger than its route's ttl
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub status: u16,
+    headers: Vec<(Arc<str>, Arc<str>)>,
+    body: Vec<u8>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    pub fresh: bool,
+}
+
+impl CachedResponse {
+    pub(super) fn response(&self) -> Response {
+        Response {
+            status: StatusCode {
+                code: self.status,
+                text: "".into(),
+            },
+            headers: self
+                .headers
+                .iter()
+                .map(|(key, val)| Header(key.clone(), val.clone()))
+                .collect(),
+            body: Some(self.body.as_slice().into()),
+            keep_alive: true,
+        }
+    }
+
+    // asks the server to answer with a 304 if what's stored is still current
+    pub fn revalidate(&self, rq: RequestBuilder) -> RequestBuilder {
+        let rq = match &self.etag {
+            Some(etag) => rq.header(Header("If-None-Match".into(), etag.as_str().into())),
+            None => rq,
+        };
+        match &self.last_modified {
+            Some(date) => rq.header(Header("If-Modified-Since".into(), date.as_str().into())),
+            None => rq,
+        }
+    }
+}
+
+// GET responses of the routes that have a ttl, kept across restarts. stale
+// responses are revalidated instead of fetched again, and used as they are
+// when the server can't be reached
+#[derive(Debug)]
+pub struct ResponseCache {
+    connection: Mutex<Connection>,
+    // uri prefix and how long responses under it are used without asking
+    routes: Vec<(Arc<str>, Duration)>,
+}
+
+impl ResponseCache {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, rusqlite::Error> {
+        let connection = Connection::open(path)?;
+        connection.execute(SQL_TABLE_RESPONSES, ())?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+            routes: vec![],
+        })
+    }
+
+    pub fn route(mut self, prefix: &str, ttl: Duration) -> Self {
+        self.routes.push((prefix.into(), ttl));
+        self
+    }
+
+    // the most specific route wins, `None` for requests that aren't cached
+    pub fn ttl(&self, method: &str, uri: &str) -> Option<Duration> {
+        if method != "GET" {
+            return None;
+        }
+        self.routes
+            .iter()
+            .filter(|(prefix, _)| uri.starts_with(prefix.as_ref()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, ttl)| *ttl)
+    }
+
+    pub fn get(&self, method: &str, uri: &str) -> Option<CachedResponse> {
+        let ttl = self.ttl(method, uri)?;
+        let connection = self.connection.lock().unwrap();
+        let row = connection
+            .query_row(SQL_SELECT_RESPONSE, [method, uri], |row| {
+                Ok((
+                    row.get::<_, u16>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Vec<u8>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            })
+            .optional();
+        let (status, headers, body, etag, last_modified, stored) = match row {
+            Ok(v) => v?,
+            Err(e) => {
+                eprintln!("WARNING: Could not read cached response for {uri}: {e}");
+                return None;
+            }
+        };
+        let age = OffsetDateTime::now_utc().unix_timestamp() - stored;
+        Some(CachedResponse {
+            status,
+            headers: serde_json::from_str(&headers).unwrap_or_default(),
+            body,
+            etag,
+            last_modified,
+            fresh: age >= 0 && (age as u64) < ttl.as_secs(),
+        })
+    }
+
+    // only complete answers are kept, and nothing the server asked not to
+    pub(super) fn put(&self, method: &str, uri: &str, response: &Response) {
+        if response.status.code != 200 || self.ttl(method, uri).is_none() {
+            return;
+        }
+        let no_store = response
+            .headers
+            .get("Cache-Control")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("no-store"));
+        if no_store {
+            return;
+        }
+        let headers: Vec<_> = response
+            .headers
+            .0
+            .iter()
+            .filter(|header| !header.0.eq_ignore_ascii_case("Set-Cookie"))
+            .map(|header| (header.0.clone(), header.1.clone()))
+            .collect();
+        let headers = serde_json::to_string(&headers).unwrap_or_default();
+        let body = response.body.as_deref().unwrap_or_default();
+        let connection = self.connection.lock().unwrap();
+        let res = connection.execute(
+            SQL_RESPONSE_INSERT,
+            params![
+                method,
+                uri,
+                response.status.code,
+                headers,
+                body,
+                response.headers.get("ETag").as_deref(),
+                response.headers.get("Last-Modified").as_deref(),
+                OffsetDateTime::now_utc().unix_timestamp(),
+            ],
+        );
+        if let Err(e) = res {
+            eprintln!("WARNING: Could not cache response for {uri}: {e}");
+        }
+    }
+
+    // a 304 means what's stored is good for another ttl
+    pub fn touch(&self, method: &str, uri: &str) {
+        let connection = self.connection.lock().unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if let Err(e) = connection.execute(SQL_RESPONSE_TOUCH, params![method, uri, now]) {
+            eprintln!("WARNING: Could not refresh cached response for {uri}: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::http_client::client::{Header, Headers, Response, StatusCode};
+
+    use super::ResponseCache;
+
+    fn response(code: u16, headers: &[&str], body: &str) -> Response {
+        Response {
+            status: StatusCode {
+                code,
+                text: "".into(),
+            },
+            headers: headers
+                .iter()
+                .map(|header| header.parse::<Header>().unwrap())
+                .collect::<Headers>(),
+            body: Some(body.as_bytes().into()),
+            keep_alive: true,
+        }
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.sqlite3");
+        let cache = ResponseCache::open(&path)
+            .unwrap()
+            .route("https://api/items", Duration::from_secs(3600))
+            .route("https://api/items/live", Duration::ZERO);
+
+        assert_eq!(cache.ttl("POST", "https://api/items"), None);
+        assert_eq!(cache.ttl("GET", "https://api/other"), None);
+        assert_eq!(
+            cache.ttl("GET", "https://api/items/live/1"),
+            Some(Duration::ZERO)
+        );
+
+        let ok = response(200, &["ETag: \"v1\"", "Set-Cookie: JWT=secret"], "[1]");
+        cache.put("GET", "https://api/items", &ok);
+        cache.put("GET", "https://api/items/live", &ok);
+        cache.put("GET", "https://api/items/gone", &response(404, &[], ""));
+        cache.put(
+            "GET",
+            "https://api/items/private",
+            &response(200, &["Cache-Control: private, no-store"], "[2]"),
+        );
+
+        // kept across restarts
+        let cache = ResponseCache::open(&path)
+            .unwrap()
+            .route("https://api/items", Duration::from_secs(3600))
+            .route("https://api/items/live", Duration::ZERO);
+        let cached = cache.get("GET", "https://api/items").unwrap();
+        assert!(cached.fresh);
+        assert_eq!(cached.response().body.as_deref(), Some(&b"[1]"[..]));
+        assert_eq!(cached.response().headers.get("set-cookie"), None);
+        assert!(!cache.get("GET", "https://api/items/live").unwrap().fresh);
+        assert!(cache.get("GET", "https://api/items/gone").is_none());
+        assert!(cache.get("GET", "https://api/items/private").is_none());
+    }
+}
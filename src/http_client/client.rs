@@ -2,31 +2,40 @@ use core::fmt;
 use std::{
     convert::Infallible,
     fmt::Display,
-    io::ErrorKind,
-    ops::{Deref, DerefMut},
+    ops::Deref,
     str::FromStr,
     sync::Arc,
     time::{Duration, SystemTime},
 };
 
+use futures_util::future::BoxFuture;
 use serde_json::Value;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
     net::TcpStream,
-    select,
     sync::{
         broadcast::Receiver as BReceiver,
-        mpsc::{error::SendError as SError, Receiver, Sender},
-        Mutex,
+        mpsc::{error::SendError as SError, Sender},
+        oneshot,
     },
     task::JoinHandle,
 };
-use tokio_rustls::{client::TlsStream, rustls::RootCertStore};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{pki_types::ServerName, RootCertStore},
+};
+
+use crate::{rate_limiter::RateLimiter, AppError, StopSignal};
 
-use crate::{AppError, StopSignal};
+use super::{
+    cache::ResponseCache,
+    pool::{self, Job},
+    response::read_response,
+    retry::RetryPolicy,
+};
 
 #[derive(Debug, PartialEq)]
-pub struct Header(Arc<str>, Arc<str>);
+pub struct Header(pub(super) Arc<str>, pub(super) Arc<str>);
 
 impl FromStr for Header {
     type Err = Infallible;
@@ -39,15 +48,21 @@ impl FromStr for Header {
 }
 
 #[derive(Debug)]
-pub struct Headers(Vec<Header>);
+pub struct Headers(pub(super) Vec<Header>);
 
 impl Headers {
+    // header names are case-insensitive
     pub fn get(&self, key: &str) -> Option<Arc<str>> {
-        if let Some(header) = self.0.iter().find(|v| v.0 == key.into()) {
-            Some(header.1.clone())
-        } else {
-            None
-        }
+        self.0
+            .iter()
+            .find(|v| v.0.eq_ignore_ascii_case(key))
+            .map(|header| header.1.clone())
+    }
+}
+
+impl FromIterator<Header> for Headers {
+    fn from_iter<T: IntoIterator<Item = Header>>(iter: T) -> Self {
+        Headers(iter.into_iter().collect())
     }
 }
 
@@ -129,6 +144,18 @@ impl Display for Method {
     }
 }
 
+impl Method {
+    // no side effects on the server
+    pub fn is_safe(&self) -> bool {
+        matches!(self, Self::GET | Self::HEAD | Self::OPTIONS | Self::TRACE)
+    }
+
+    // sending it twice does the same as sending it once
+    pub fn is_idempotent(&self) -> bool {
+        self.is_safe() || matches!(self, Self::PUT | Self::DELETE)
+    }
+}
+
 impl Clone for Method {
     fn clone(&self) -> Self {
         match self {
@@ -155,85 +182,50 @@ pub struct Request {
 }
 
 impl Request {
-    async fn send(&mut self, stream: &mut TlsStream<TcpStream>) -> Result<Response, SendError> {
+    pub(super) async fn write_to<C: AsyncWrite + Unpin>(&mut self, conn: &mut C) -> Result<(), SendError> {
         let req = self.build_request()?;
         let req = req.as_bytes();
 
-        let mut out = String::new();
-        stream
-            .write_all(req)
+        conn.write_all(req)
             .await
-            .map_err(|e| SendError::IoError(e))?;
-        println!("{} bytes written for `{:?}`.", req.len(), self.uri);
-        let mut reader = BufReader::new(stream);
-        if let Ok(v) = tokio::time::timeout(self.timeout, reader.read_line(&mut out)).await {
-            v.map_err(|e| SendError::IoError(e))?;
+            .map_err(SendError::IoError)?;
+        conn.flush().await.map_err(SendError::IoError)?;
+        Ok(())
+    }
+
+    pub(super) async fn read_from<C: AsyncBufRead + Unpin>(
+        &self,
+        conn: &mut C,
+    ) -> Result<Response, SendError> {
+        // only waiting for the response to start is timed, big bodies can take a while
+        if let Ok(v) = tokio::time::timeout(self.timeout, conn.fill_buf()).await {
+            v.map_err(SendError::IoError)?;
         } else {
             return Err(SendError::RequestTimeout(self.timeout));
         }
-        loop {
-            let mut line = String::new();
-            if reader
-                .read_line(&mut line)
-                .await
-                .map_err(|e| SendError::IoError(e))?
-                < 3
-            {
-                out.push_str(line.as_str());
-                break;
-            }
-            out.push_str(line.as_str());
-        }
+        let head_request = matches!(self.method, Some(Method::HEAD));
+        read_response(conn, head_request).await
+    }
 
-        let mut content = out.split("\r\n");
-
-        if content
-            .find(|&v| v.contains("Transfer-Encoding: chunked"))
-            .is_some()
-        {
-            loop {
-                let mut line = String::new();
-                reader
-                    .read_line(&mut line)
-                    .await
-                    .map_err(|e| SendError::IoError(e))?;
-                if line.trim().is_empty() {
-                    continue;
-                }
-                let size = usize::from_str_radix(line.trim(), 16).unwrap();
-                if size == 0 {
-                    break;
-                }
-                collect_payload_chunk(&mut out, size, &mut reader).await?;
-            }
-        } else if let Some(header) = out.split("\r\n").find(|&v| v.contains("Content-Length")) {
-            let (_, val) = match header.split_once(": ") {
-                Some(v) => v,
-                None => {
-                    return Err(SendError::MalformedResponse((
-                        "from splitting header".into(),
-                        header.into(),
-                    )))
-                }
-            };
+    pub fn method(&self) -> Option<&Method> {
+        self.method.as_ref()
+    }
 
-            let size = val.parse::<usize>().map_err(|_| {
-                SendError::MalformedResponse((
-                    format!("from parsing Content-Length: {}", header).into(),
-                    out.clone().into(),
-                ))
-            })?;
-            collect_payload_chunk(&mut out, size, &mut reader).await?;
-        } else {
-            return Err(SendError::MalformedResponse((
-                "No/Malformed Content-Length/Transfer-Encoding Header".into(),
-                out.into(),
-            )));
-        };
+    pub fn uri(&self) -> Option<&str> {
+        self.uri.as_deref()
+    }
+
+    pub fn body(&self) -> Option<&Value> {
+        self.body.as_ref()
+    }
 
-        let out = out.as_str();
+    // requests that can go out behind others on the same connection
+    pub(super) fn is_safe(&self) -> bool {
+        self.method.as_ref().is_some_and(Method::is_safe)
+    }
 
-        build_response(out)
+    pub(super) fn is_idempotent(&self) -> bool {
+        self.method.as_ref().is_some_and(Method::is_idempotent)
     }
 
     fn build_request(&mut self) -> Result<String, SendError> {
@@ -266,7 +258,8 @@ impl Request {
         let body = body.as_str();
         let content_length = body.as_bytes().len();
 
-        if content_length != 0 {
+        // retries build the same request again
+        if content_length != 0 && self.headers.get("Content-Length").is_none() {
             self.headers.0.push(Header(
                 "Content-Length".into(),
                 format!("{content_length}").into(),
@@ -303,91 +296,20 @@ impl Request {
     }
 }
 
-fn build_response(out: &str) -> Result<Response, SendError> {
-    let (head, body) = match out.split_once("\r\n\r\n") {
-        Some(v) => v,
-        None => {
-            return Err(SendError::MalformedResponse((
-                "from splitting head and body".into(),
-                out.into(),
-            )))
-        }
-    };
-    let mut lines = head.split("\r\n");
-    let status_raw = match lines.next() {
-        Some(v) => v,
-        None => {
-            return Err(SendError::MalformedResponse((
-                "from polling status line".into(),
-                out.into(),
-            )))
-        }
-    };
-    let (_, status_raw) = match status_raw.split_once(" ") {
-        Some(v) => v,
-        None => {
-            return Err(SendError::MalformedResponse((
-                "from splitting status line (version removal)".into(),
-                out.into(),
-            )))
-        }
-    };
-    let (code, text) = match status_raw.split_once(" ") {
-        Some(v) => v,
-        None => {
-            return Err(SendError::MalformedResponse((
-                "from splitting status line (status pieces)".into(),
-                out.into(),
-            )))
-        }
-    };
-    let code = code.parse().map_err(|e| {
-        SendError::MalformedResponse((
-            format!("from parsing status code: {}", e).into(),
-            out.into(),
-        ))
-    })?;
-    let status = StatusCode {
-        code,
-        text: text.into(),
-    };
-
-    let headers: Headers = lines.try_fold(
-        Headers(vec![]),
-        |mut acc, raw_header| -> Result<Headers, SendError> {
-            let (key, val) = match raw_header.split_once(": ") {
-                Some(v) => v,
-                None => {
-                    return Err(SendError::MalformedResponse((
-                        "from splitting header".into(),
-                        raw_header.into(),
-                    )))
-                }
-            };
-            acc.0.push(Header(key.into(), val.into()));
-            Ok(acc)
-        },
-    )?;
-    let body: Option<Arc<str>> = if body != "" { Some(body.into()) } else { None };
-    Ok(Response {
-        status,
-        headers,
-        body,
-    })
-}
-
 #[derive(Debug, Clone)]
 pub struct Response {
-    status: StatusCode,
-    headers: Headers,
-    body: Option<Arc<str>>,
+    pub(super) status: StatusCode,
+    pub(super) headers: Headers,
+    pub(super) body: Option<Arc<[u8]>>,
+    // false when the server is going to close the connection after this
+    pub(super) keep_alive: bool,
 }
 
 impl Response {
     fn status(&self) -> StatusCode {
         self.status.clone()
     }
-    fn body(&self) -> Option<Arc<str>> {
+    fn body(&self) -> Option<Arc<[u8]>> {
         self.body.clone()
     }
     fn headers(&self) -> Headers {
@@ -440,6 +362,7 @@ pub enum SendError {
     HttpNotSupported(Arc<str>),
     Recv,
     ChanSendError(SError<Request>),
+    ConnectFailed(Arc<str>),
 }
 
 impl Display for SendError {
@@ -462,28 +385,13 @@ impl Display for SendError {
             SendError::ChanSendError(e) => {
                 f.write_str(format!("ChanSendError: {}", e.to_string()).as_str())
             }
+            SendError::ConnectFailed(e) => f.write_str(format!("Could not connect: {e}").as_str()),
         }
     }
 }
 
 impl std::error::Error for SendError {}
 
-async fn collect_payload_chunk(
-    out: &mut String,
-    size: usize,
-    reader: &mut BufReader<&mut TlsStream<TcpStream>>,
-) -> Result<(), SendError> {
-    let mut buf = vec![0; size];
-    reader
-        .read_exact(&mut buf)
-        .await
-        .map_err(|e| SendError::IoError(e))?;
-    let buf = String::from_utf8(buf).unwrap();
-    let buf = buf.as_str();
-    out.push_str(buf);
-    Ok(())
-}
-
 impl Into<RequestBuilder> for Request {
     fn into(self) -> RequestBuilder {
         RequestBuilder { inner: self }
@@ -543,10 +451,19 @@ impl RequestBuilder {
 }
 
 #[derive(Debug)]
-struct ClientHandleInner {
-    host: Option<Arc<str>>,
-    port: Option<u16>,
-    timeout: Option<Duration>,
+pub(super) struct ClientHandleInner {
+    pub(super) host: Option<Arc<str>>,
+    pub(super) port: Option<u16>,
+    pub(super) timeout: Option<Duration>,
+    // most connections open to the host at once
+    pub(super) connections: usize,
+    // keep-alive connections unused for this long aren't reused
+    pub(super) idle_timeout: Duration,
+    // most requests written to one connection before reading their responses
+    pub(super) pipeline_depth: usize,
+    pub(super) retry: RetryPolicy,
+    // certificates trusted instead of the usual roots, for a local server
+    pub(super) roots: Option<RootCertStore>,
 }
 
 impl Clone for ClientHandleInner {
@@ -555,19 +472,57 @@ impl Clone for ClientHandleInner {
             host: self.host.clone(),
             port: self.port.clone(),
             timeout: self.timeout.clone(),
+            connections: self.connections,
+            idle_timeout: self.idle_timeout,
+            pipeline_depth: self.pipeline_depth,
+            retry: self.retry.clone(),
+            roots: self.roots.clone(),
         }
     }
 }
 
+pub(super) type Connection = BufReader<TlsStream<TcpStream>>;
+
 #[derive(Debug)]
 pub struct ClientHandle {
     handle: Option<JoinHandle<Result<(), ConnectionError>>>,
-    request_sender: Option<Sender<Request>>,
-    response_receiver: Option<Receiver<Response>>,
+    transport: Option<Arc<dyn Transport>>,
     inner: ClientHandleInner,
     stop_signal: BReceiver<StopSignal>,
 }
 
+// where requests end up, the connection pool unless something else is
+// given to `ClientHandle::transport`
+pub trait Transport: fmt::Debug + Send + Sync {
+    fn send(&self, req: Request) -> BoxFuture<'_, Result<Response, SendError>>;
+}
+
+// hands requests to the pool task, any number can be waiting on it at once
+#[derive(Debug)]
+struct PoolTransport {
+    request_sender: Sender<Job>,
+}
+
+impl Transport for PoolTransport {
+    fn send(&self, req: Request) -> BoxFuture<'_, Result<Response, SendError>> {
+        Box::pin(async move {
+            let (reply, response) = oneshot::channel();
+            self.request_sender
+                .send(Job {
+                    request: req,
+                    reply,
+                    attempt: 0,
+                })
+                .await
+                .map_err(|e| SendError::ChanSendError(SError(e.0.request)))?;
+            match response.await {
+                Ok(v) => v,
+                Err(_) => Err(SendError::Recv),
+            }
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum ConnectionError {
     ConnectionTimeout(Duration),
@@ -578,8 +533,8 @@ pub enum ConnectionError {
     HostNone,
     PortNone,
     TimeoutNone,
+    InvalidHost(Arc<str>),
     // ChanSendError(SError<Response>),
-    SendError(SendError),
 }
 
 impl Default for ClientHandleInner {
@@ -588,6 +543,11 @@ impl Default for ClientHandleInner {
             host: None,
             port: None,
             timeout: None,
+            connections: 4,
+            idle_timeout: Duration::from_secs(60),
+            pipeline_depth: 1,
+            retry: Default::default(),
+            roots: None,
         }
     }
 }
@@ -607,10 +567,10 @@ impl Display for ConnectionError {
             Self::HostNone => f.write_str("Host not instantiated"),
             Self::PortNone => f.write_str("Port not instantiated"),
             Self::TimeoutNone => f.write_str("Timeout not instantiated"),
+            Self::InvalidHost(v) => f.write_str(format!("Invalid host name: {v}").as_str()),
             // Self::ChanSendError(e) => {
             //     f.write_str(format!("ChanSendError: {}", e.to_string()).as_str())
             // }
-            Self::SendError(e) => f.write_str(e.to_string().as_str()),
         }
     }
 }
@@ -621,56 +581,36 @@ impl ClientHandle {
     pub fn new(stop_signal: BReceiver<StopSignal>) -> Self {
         Self {
             handle: None,
-            request_sender: None,
-            response_receiver: None,
+            transport: None,
             inner: Default::default(),
             stop_signal,
         }
     }
-    pub fn start_client(
-        mut self,
-        receiver: Receiver<Request>,
-        sender: Sender<Response>,
-    ) -> Self {
-        self.handle = Some(tokio::task::spawn(handle(
+
+    pub fn start_client(mut self) -> Self {
+        let capacity = self.inner.connections.max(1) * self.inner.pipeline_depth.max(1);
+        let (request_sender, request_receiver) = tokio::sync::mpsc::channel::<Job>(capacity);
+        self.transport = Some(Arc::new(PoolTransport { request_sender }));
+        self.handle = Some(tokio::task::spawn(pool::run(
             self.inner.clone(),
-            receiver,
-            sender,
-            self.stop_signal.resubscribe()
+            request_receiver,
+            self.stop_signal.resubscribe(),
         )));
         self
     }
 
-    async fn send(&mut self, req: Request) -> Result<Response, SendError> {
-        if self.request_sender.is_none() {
-            return Err(SendError::SenderNone);
-        };
-        self.request_sender
-            .as_ref()
-            .unwrap()
-            .send(req)
-            .await
-            .map_err(|e| SendError::ChanSendError(e))?;
-        let mut receiver = self
-            .response_receiver
-            .take()
-            .expect("FATAL: Response Receiver dropped");
-        let res = match receiver.recv().await {
-            Some(v) => v,
-            None => return Err(SendError::Recv),
-        };
-        self.response_receiver = Some(receiver);
-        Ok(res)
-    }
-
-    pub fn send_channel(mut self, sender: Sender<Request>) -> Self {
-        self.request_sender = Some(sender);
+    // sends through `transport` instead of connecting anywhere, so there's
+    // no need for `start_client`
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
         self
     }
 
-    pub fn receive_channel(mut self, receiver: Receiver<Response>) -> Self {
-        self.response_receiver = Some(receiver);
-        self
+    pub(super) async fn send(&self, req: Request) -> Result<Response, SendError> {
+        match self.transport.as_ref() {
+            Some(v) => v.send(req).await,
+            None => Err(SendError::SenderNone),
+        }
     }
 
     pub fn addr(mut self, uri: &str) -> Result<Self, ConnectionError> {
@@ -687,6 +627,17 @@ impl ClientHandle {
             Some((v, _)) => v.into(),
             None => host,
         };
+        // a port in the address replaces the one set with `port`
+        let host = match host.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| ConnectionError::InvalidHost(uri.into()))?;
+                self.inner.port = Some(port);
+                host
+            }
+            None => host,
+        };
         self.inner.host = Some(host.into());
         Ok(self)
     }
@@ -700,118 +651,88 @@ impl ClientHandle {
         self.inner.timeout = Some(timeout);
         self
     }
-}
 
-async fn handle(
-    inner: ClientHandleInner,
-    mut receiver: Receiver<Request>,
-    sender: Sender<Response>,
-    mut stop_signal: BReceiver<StopSignal>,
-) -> Result<(), ConnectionError> {
-    if inner.host.is_none() {
-        return Err(ConnectionError::HostNone).unwrap();
-    }
-    if inner.port.is_none() {
-        return Err(ConnectionError::PortNone).unwrap();
-    }
-    let addr: Arc<str> = format!(
-        "{}:{}",
-        inner.host.clone().unwrap().deref(),
-        inner.port.unwrap()
-    )
-    .into();
-    if inner.timeout.is_none() {
-        return Err(ConnectionError::TimeoutNone).unwrap();
-    }
-
-    println!("Connecting to {addr}");
-    let mut tstream = connect(&inner).await.unwrap();
-    let sender = &sender;
-
-    // nvm i dont like this anymore...
-    // too much indentation...
-    loop {
-        select! {
-        request = receiver.recv() => {
-            let mut request = match request {
-                Some(v) => v,
-                None => return Err(ConnectionError::SendError(SendError::Recv)),
-            };
-            let resp = match request.send(&mut tstream).await {
-                Ok(v) => v,
-                Err(e) => match &e {
-                    SendError::IoError(ie) => match ie.kind() {
-                        ErrorKind::WriteZero => {
-                            println!("Connection Closed for {addr}");
-                            println!("Reconnecting to {addr}");
-                            tstream = connect(&inner).await.unwrap();
-                            request
-                                .send(&mut tstream)
-                                .await
-                                .map_err(|e| ConnectionError::SendError(e))
-                        }
-                        _ => panic!("{e}"),
-                    },
-                    _ => panic!("{e}"),
-                }?,
-            };
-            sender.send(resp).await.expect("hello?");
-            println!("sent response through channel");
-        }
-            _ = stop_signal.recv() => {
-                drop(tstream);
-                println!("Connection Closed for {addr}");
-                break Ok(());
-            },
-        }
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.inner.connections = connections.max(1);
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.inner.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn pipeline_depth(mut self, pipeline_depth: usize) -> Self {
+        self.inner.pipeline_depth = pipeline_depth.max(1);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.inner.retry = retry;
+        self
+    }
+
+    #[cfg(test)]
+    pub(super) fn root_certificates(mut self, roots: RootCertStore) -> Self {
+        self.inner.roots = Some(roots);
+        self
     }
 }
 
-async fn connect(inner: &ClientHandleInner) -> Result<TlsStream<TcpStream>, ConnectionError> {
-    let root_store = RootCertStore {
-        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+pub(super) async fn connect(inner: &ClientHandleInner) -> Result<Connection, ConnectionError> {
+    let root_store = match inner.roots.clone() {
+        Some(v) => v,
+        None => RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+        },
     };
     let config = tokio_rustls::rustls::ClientConfig::builder()
         .with_root_certificates(root_store)
         .with_no_client_auth();
-    let host_str = inner.host.clone().unwrap();
-    let host = host_str.deref().to_string().try_into().unwrap();
-    let addr = format!("{}:{}", host_str, inner.port.unwrap());
-    if let Ok(stream) =
-        tokio::time::timeout(inner.timeout.unwrap(), tokio::net::TcpStream::connect(addr)).await
-    {
-        let stream = stream.map_err(|e| ConnectionError::IoError(e))?;
+    let host_str = inner.host.clone().ok_or(ConnectionError::HostNone)?;
+    let port = inner.port.ok_or(ConnectionError::PortNone)?;
+    let timeout = inner.timeout.ok_or(ConnectionError::TimeoutNone)?;
+    let host = ServerName::try_from(host_str.deref().to_string())
+        .map_err(|_| ConnectionError::InvalidHost(host_str.clone()))?;
+    let addr = format!("{}:{}", host_str, port);
+    if let Ok(stream) = tokio::time::timeout(timeout, tokio::net::TcpStream::connect(addr)).await {
+        let stream = stream.map_err(ConnectionError::IoError)?;
         let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
-        let resp = connector
+        let stream = connector
             .connect(host, stream)
             .await
-            .map_err(|e| ConnectionError::IoError(e));
-        resp
+            .map_err(ConnectionError::IoError)?;
+        Ok(BufReader::new(stream))
     } else {
-        return Err(ConnectionError::ConnectionTimeout(inner.timeout.unwrap()));
+        Err(ConnectionError::ConnectionTimeout(timeout))
     }
 }
 
-pub type ArcClientHandle = Arc<Mutex<ClientHandle>>;
+pub type ArcClientHandle = Arc<ClientHandle>;
+
+// 429s are sent again this often, unless the server wants a longer break
+const RATE_LIMITED_RETRIES: u32 = 3;
+const MAX_RATE_LIMITED_WAIT: Duration = Duration::from_secs(60);
 
 pub trait HttpClient {
     async fn sender_fn(
         &mut self,
         rq: RequestBuilder,
     ) -> Result<(ArcClientHandle, RequestBuilder), AppError>;
-    async fn rate_limit(&self);
+    // waited on before every request, and told about every response
+    fn rate_limiter(&self) -> Option<&RateLimiter>;
+    fn response_cache(&self) -> Option<Arc<ResponseCache>> {
+        None
+    }
+    // sees every response before it's parsed, e.g. to pick up new tokens
+    async fn after_response(&mut self, _status: &StatusCode, _headers: &Headers) {}
     async fn send_request(&mut self, rq: Request) -> Result<ApiResult, AppError> {
         let (client_handle, rq) = self
             .sender_fn(rq.into())
             .await
             .map_err(|e| e.prop("send_request".into()))?;
         let rq = rq.build();
-        self.rate_limit().await;
-
-        let start = SystemTime::now();
 
-        let mut client_handle_mutex = client_handle.lock().await;
-        let client_handle = client_handle_mutex.deref_mut();
         let method = match rq.method.clone() {
             Some(v) => v.to_string(),
             None => "NILMETHOD".to_string(),
@@ -820,50 +741,102 @@ pub trait HttpClient {
             Some(v) => v,
             None => "NILURI".into(),
         };
-        let response = client_handle
-            .send(rq)
-            .await
-            .map_err(|e| AppError::new(e.to_string(), "send_request".into()))?;
-        drop(client_handle_mutex);
-
-        let status = response.status();
-        println!(
-            "{} {} {} {} in {:.2}s",
-            method,
-            uri,
-            status.code,
-            &status.text,
-            SystemTime::now()
-                .duration_since(start)
-                .unwrap()
-                .as_secs_f32()
-        );
-        if status.code == 429 {
-            self.rate_limit().await;
+        let cache = self.response_cache();
+        let cached = cache.as_ref().and_then(|cache| cache.get(&method, &uri));
+        if let Some(cached) = cached.as_ref().filter(|cached| cached.fresh) {
+            eprintln!("{} {} {} from cache", method, uri, cached.status);
+            return api_result(cached.response());
         }
-        let headers = response.headers();
-        let content = response.body().unwrap_or_default();
+        let rq = match &cached {
+            Some(cached) => cached.revalidate(rq.into()).build(),
+            None => rq,
+        };
 
-        if content == "".into() {
-            return Ok(ApiResult {
-                res: (None, headers),
-                status,
-            });
-        }
-        let response = serde_json::Value::from_str(&content).map_err(|e| {
-            AppError::new(e.to_string(), String::from("send_request: Value::from_str"))
-        });
-        if response.is_err() {
-            println!("Response body: {content}");
-        }
-        if status.code >= 400 {
-            println!("Response body: {content}");
-        }
-        let response = response?;
+        let mut limited = 0;
+        let (response, status) = loop {
+            if let Some(limiter) = self.rate_limiter() {
+                limiter.acquire(&uri).await;
+            }
+            let start = SystemTime::now();
+            let response = match client_handle.send(rq.clone()).await {
+                Ok(v) => v,
+                // old data beats none when the api can't be reached
+                Err(e) => match &cached {
+                    Some(cached) => {
+                        eprintln!("WARNING: {method} {uri} failed, using the cached response: {e}");
+                        return api_result(cached.response());
+                    }
+                    None => return Err(AppError::new(e.to_string(), "send_request".into())),
+                },
+            };
+
+            let status = response.status();
+            eprintln!(
+                "{} {} {} {} in {:.2}s",
+                method,
+                uri,
+                status.code,
+                &status.text,
+                SystemTime::now()
+                    .duration_since(start)
+                    .unwrap()
+                    .as_secs_f32()
+            );
+            let wait = self
+                .rate_limiter()
+                .and_then(|limiter| limiter.observe(&uri, &status, &response.headers()));
+            // the limiter holds the next try back for as long as the server asked
+            match wait {
+                Some(wait) if limited < RATE_LIMITED_RETRIES && wait <= MAX_RATE_LIMITED_WAIT => {
+                    limited += 1;
+                    eprintln!(
+                        "WARNING: {uri} was rate limited, trying again in {:.2}s",
+                        wait.as_secs_f32()
+                    );
+                }
+                _ => break (response, status),
+            }
+        };
+        self.after_response(&status, &response.headers()).await;
+        let response = match (&cache, cached) {
+            (Some(cache), Some(cached)) if status.code == 304 => {
+                cache.touch(&method, &uri);
+                cached.response()
+            }
+            (Some(cache), _) => {
+                cache.put(&method, &uri, &response);
+                response
+            }
+            _ => response,
+        };
+        api_result(response)
+    }
+}
 
-        Ok(ApiResult {
-            res: (Some(response), headers),
+fn api_result(response: Response) -> Result<ApiResult, AppError> {
+    let status = response.status();
+    let headers = response.headers();
+    let content = response.body().unwrap_or_default();
+
+    if content.is_empty() {
+        return Ok(ApiResult {
+            res: (None, headers),
             status,
-        })
+        });
+    }
+    let response = serde_json::from_slice::<Value>(&content)
+        .map_err(|e| AppError::new(e.to_string(), String::from("send_request: from_slice")));
+    let content = String::from_utf8_lossy(&content);
+    if response.is_err() {
+        eprintln!("Response body: {content}");
     }
+    if status.code >= 400 {
+        eprintln!("Response body: {content}");
+    }
+    let response = response?;
+
+    Ok(ApiResult {
+        res: (Some(response), headers),
+        status,
+    })
 }
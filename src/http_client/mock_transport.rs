@@ -0,0 +1,123 @@
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::client::{Header, Headers, Method, Request, Response, SendError, StatusCode, Transport};
+
+// a recorded response, `path` is everything after the host. a path without
+// a query matches requests with any query. fixtures for the same request are
+// answered in order, the last one over and over
+#[derive(Clone, Debug, Deserialize)]
+pub struct Fixture {
+    pub method: String,
+    pub path: String,
+    #[serde(default = "ok")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub body: Option<Value>,
+}
+
+fn ok() -> u16 {
+    200
+}
+
+// what a request looked like when it was sent
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recorded {
+    pub method: String,
+    pub path: String,
+    pub body: Option<Value>,
+}
+
+// answers from fixtures instead of the network and keeps every request it
+// got. requests without a fixture get a 404
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    fixtures: Vec<Fixture>,
+    requests: Mutex<Vec<Recorded>>,
+}
+
+// `/v1/profile?x=1` out of `https://host/v1/profile?x=1`
+fn target(uri: &str) -> &str {
+    let rest = uri.split_once("://").map(|(_, rest)| rest).unwrap_or(uri);
+    rest.find('/').map(|i| &rest[i..]).unwrap_or("/")
+}
+
+impl MockTransport {
+    pub fn new(fixtures: Vec<Fixture>) -> Arc<Self> {
+        Arc::new(Self {
+            fixtures,
+            requests: Mutex::new(vec![]),
+        })
+    }
+
+    // a json array of fixtures
+    pub fn from_json(fixtures: Value) -> Arc<Self> {
+        Self::new(serde_json::from_value(fixtures).expect("fixtures should deserialize"))
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn respond(&self, req: &Request) -> Response {
+        let method = req.method().map(Method::to_string).unwrap_or_default();
+        let target = target(req.uri().unwrap_or_default());
+        let path = target
+            .split_once('?')
+            .map(|(path, _)| path)
+            .unwrap_or(target);
+        let matches = |fixture: &&Fixture| {
+            fixture.method == method && (fixture.path == target || fixture.path == path)
+        };
+        let mut requests = self.requests.lock().unwrap();
+        let seen = requests
+            .iter()
+            .filter(|seen| seen.method == method && seen.path == target)
+            .count();
+        requests.push(Recorded {
+            method: method.clone(),
+            path: target.into(),
+            body: req.body().cloned(),
+        });
+        drop(requests);
+
+        let fixtures = self.fixtures.iter().filter(matches).count();
+        let fixture = self
+            .fixtures
+            .iter()
+            .filter(matches)
+            .nth(seen.min(fixtures.saturating_sub(1)));
+        let (status, headers, body) = match fixture {
+            Some(fixture) => (
+                fixture.status,
+                fixture
+                    .headers
+                    .iter()
+                    .map(|(key, val)| Header(key.as_str().into(), val.as_str().into()))
+                    .collect(),
+                fixture.body.as_ref().map(|body| body.to_string()),
+            ),
+            None => (404, vec![], None),
+        };
+        Response {
+            status: StatusCode {
+                code: status,
+                text: "".into(),
+            },
+            headers: Headers(headers),
+            body: body.map(|body| body.into_bytes().into()),
+            keep_alive: true,
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, req: Request) -> BoxFuture<'_, Result<Response, SendError>> {
+        Box::pin(async move { Ok(self.respond(&req)) })
+    }
+}
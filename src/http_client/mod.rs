@@ -1,4 +1,12 @@
+pub mod cache;
 pub mod client;
+pub mod pool;
+pub mod response;
+pub mod retry;
 pub mod wfm_client;
 pub mod auth_state;
 pub mod qf_client;
+pub mod secret_store;
+pub mod session_refresh;
+#[cfg(test)]
+pub mod mock_transport;
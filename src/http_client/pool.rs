@@ -0,0 +1,489 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rand::rngs::OsRng;
+use tokio::{
+    io::{AsyncBufRead, AsyncWrite},
+    select,
+    sync::{
+        broadcast::Receiver as BReceiver, mpsc::Receiver, oneshot, OwnedSemaphorePermit, Semaphore,
+    },
+    time::sleep,
+};
+
+use crate::StopSignal;
+
+use super::{
+    client::{
+        connect, ClientHandleInner, Connection, ConnectionError, Request, Response, SendError,
+    },
+    retry::{is_closed, RetryPolicy},
+};
+
+// a request and where its response goes
+#[derive(Debug)]
+pub(super) struct Job {
+    pub(super) request: Request,
+    pub(super) reply: oneshot::Sender<Result<Response, SendError>>,
+    // tries that failed so far
+    pub(super) attempt: u32,
+}
+
+impl Job {
+    fn answer(self, res: Result<Response, SendError>) {
+        // the caller stopped waiting, nothing to do about that
+        let _ = self.reply.send(res);
+    }
+}
+
+struct IdleConnection {
+    conn: Connection,
+    since: Instant,
+}
+
+// keep-alive connections that aren't serving a request right now
+struct IdlePool {
+    connections: Mutex<Vec<IdleConnection>>,
+    timeout: Duration,
+    max: usize,
+}
+
+impl IdlePool {
+    fn take(&self) -> Option<Connection> {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        // servers drop idle connections on their end sooner or later
+        connections.retain(|idle| idle.since.elapsed() < self.timeout);
+        connections.pop().map(|idle| idle.conn)
+    }
+
+    fn put(&self, conn: Connection) {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        if connections.len() < self.max {
+            connections.push(IdleConnection {
+                conn,
+                since: Instant::now(),
+            });
+        }
+    }
+}
+
+// hands jobs to up to `connections` tasks at once. jobs that queue up while
+// every connection is busy are pipelined onto the next free one, as long as
+// they're safe to send again should the connection close halfway through
+pub(super) async fn run(
+    inner: ClientHandleInner,
+    mut receiver: Receiver<Job>,
+    mut stop_signal: BReceiver<StopSignal>,
+) -> Result<(), ConnectionError> {
+    let host = inner.host.clone().ok_or(ConnectionError::HostNone)?;
+    let port = inner.port.ok_or(ConnectionError::PortNone)?;
+    inner.timeout.ok_or(ConnectionError::TimeoutNone)?;
+    let addr = format!("{host}:{port}");
+
+    let idle = Arc::new(IdlePool {
+        connections: Mutex::new(vec![]),
+        timeout: inner.idle_timeout,
+        max: inner.connections,
+    });
+    let slots = Arc::new(Semaphore::new(inner.connections));
+    let inner = Arc::new(inner);
+    let mut held: Option<Job> = None;
+    eprintln!("Connection pool started for {addr}");
+    loop {
+        let job = match held.take() {
+            Some(job) => job,
+            None => select! {
+                job = receiver.recv() => match job {
+                    Some(v) => v,
+                    None => break,
+                },
+                _ = stop_signal.recv() => break,
+            },
+        };
+        let permit = select! {
+            permit = slots.clone().acquire_owned() => permit.expect("semaphore is never closed"),
+            _ = stop_signal.recv() => break,
+        };
+        let mut batch = VecDeque::from([job]);
+        while batch[0].request.is_safe() && batch.len() < inner.pipeline_depth {
+            match receiver.try_recv() {
+                Ok(job) if job.request.is_safe() => batch.push_back(job),
+                Ok(job) => {
+                    held = Some(job);
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        tokio::task::spawn(serve(batch, inner.clone(), idle.clone(), permit));
+    }
+    eprintln!("Connection Closed for {addr}");
+    Ok(())
+}
+
+async fn serve(
+    mut batch: VecDeque<Job>,
+    inner: Arc<ClientHandleInner>,
+    idle: Arc<IdlePool>,
+    _permit: OwnedSemaphorePermit,
+) {
+    let policy = &inner.retry;
+    while !batch.is_empty() {
+        let (mut conn, reused) = match idle.take() {
+            Some(conn) => (conn, true),
+            None => match connect(&inner).await {
+                Ok(conn) => (conn, false),
+                Err(e) => {
+                    let attempt = batch[0].attempt;
+                    if !policy.retries_connect(attempt, &e) {
+                        let e: Arc<str> = e.to_string().into();
+                        batch
+                            .into_iter()
+                            .for_each(|job| job.answer(Err(SendError::ConnectFailed(e.clone()))));
+                        return;
+                    }
+                    eprintln!("WARNING: Could not connect: {e}, trying again");
+                    batch.iter_mut().for_each(|job| job.attempt += 1);
+                    sleep(policy.delay(attempt, &mut OsRng)).await;
+                    continue;
+                }
+            },
+        };
+        let (rest, keep_alive, failed) = exchange(&mut conn, batch, reused, policy).await;
+        batch = rest;
+        if keep_alive {
+            idle.put(conn);
+        }
+        if let Some(attempt) = failed {
+            sleep(policy.delay(attempt, &mut OsRng)).await;
+        }
+    }
+}
+
+// answers the job with the error, or puts it back to go out again
+fn fail(
+    mut job: Job,
+    e: SendError,
+    policy: &RetryPolicy,
+    batch: &mut VecDeque<Job>,
+) -> Option<u32> {
+    if !policy.retries_send(job.attempt, &job.request, &e) {
+        job.answer(Err(e));
+        return None;
+    }
+    eprintln!("WARNING: Request failed: {e}, trying again");
+    let attempt = job.attempt;
+    job.attempt += 1;
+    batch.push_front(job);
+    Some(attempt)
+}
+
+// writes the whole batch before reading any response and answers the jobs in
+// order. returns the jobs that still need a connection, whether this one can
+// be reused, and the attempt of a failed job to back off for
+async fn exchange<C: AsyncBufRead + AsyncWrite + Unpin>(
+    conn: &mut C,
+    mut batch: VecDeque<Job>,
+    reused: bool,
+    policy: &RetryPolicy,
+) -> (VecDeque<Job>, bool, Option<u32>) {
+    let mut written = 0;
+    for job in batch.iter_mut() {
+        match job.request.write_to(conn).await {
+            Ok(()) => written += 1,
+            Err(e) if written == 0 && !(reused && is_closed(&e)) => {
+                let job = batch.pop_front().expect("batch is not empty");
+                let failed = fail(job, e, policy, &mut batch);
+                return (batch, false, failed);
+            }
+            // a connection that sat idle was closed by the server, everything
+            // that didn't go out yet gets another one
+            Err(_) => break,
+        }
+    }
+
+    let mut answered = 0;
+    while answered < written {
+        let job = batch.pop_front().expect("written jobs are in the batch");
+        match job.request.read_from(conn).await {
+            Ok(res) => {
+                let keep_alive = res.keep_alive;
+                job.answer(Ok(res));
+                answered += 1;
+                if !keep_alive {
+                    return (batch, false, None);
+                }
+            }
+            Err(e) if reused && answered == 0 && job.request.is_idempotent() && is_closed(&e) => {
+                batch.push_front(job);
+                return (batch, false, None);
+            }
+            Err(e) => {
+                let failed = fail(job, e, policy, &mut batch);
+                return (batch, false, failed);
+            }
+        }
+    }
+    let keep_alive = batch.is_empty();
+    (batch, keep_alive, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+    use rcgen::CertifiedKey;
+    use tokio::{
+        io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream},
+        net::TcpListener,
+        sync::{broadcast, oneshot},
+        task::JoinHandle,
+    };
+    use tokio_rustls::{
+        rustls::{
+            pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+            RootCertStore, ServerConfig,
+        },
+        TlsAcceptor,
+    };
+
+    use super::{exchange, Job};
+    use crate::{
+        http_client::{
+            client::{ClientHandle, Method, RequestBuilder, Response, SendError},
+            retry::RetryPolicy,
+        },
+        StopSignal,
+    };
+
+    fn job(method: Method, path: &str) -> (Job, oneshot::Receiver<Result<Response, SendError>>) {
+        let (reply, response) = oneshot::channel();
+        let request = RequestBuilder::new()
+            .method(method)
+            .uri(&format!("https://api.warframe.market{path}"))
+            .build();
+        let job = Job {
+            request,
+            reply,
+            attempt: 0,
+        };
+        (job, response)
+    }
+
+    // reads `count` request heads, then writes `responses` in one go
+    async fn serve(server: DuplexStream, count: usize, responses: &'static [u8]) -> Vec<String> {
+        let mut server = BufReader::new(server);
+        let mut request_lines = vec![];
+        for _ in 0..count {
+            let mut line = String::new();
+            server.read_line(&mut line).await.unwrap();
+            request_lines.push(line.trim_end().to_string());
+            loop {
+                let mut line = String::new();
+                server.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+            }
+        }
+        server.write_all(responses).await.unwrap();
+        request_lines
+    }
+
+    #[tokio::test]
+    async fn test_exchange_pipelined() {
+        let policy = RetryPolicy::default();
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(serve(
+            server,
+            2,
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\naHTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\nb",
+        ));
+        let (first, first_res) = job(Method::GET, "/a");
+        let (second, second_res) = job(Method::GET, "/b");
+        let mut conn = BufReader::new(client);
+        let (rest, keep_alive, _) =
+            exchange(&mut conn, VecDeque::from([first, second]), false, &policy).await;
+
+        assert!(rest.is_empty());
+        assert!(keep_alive);
+        assert_eq!(
+            first_res.await.unwrap().unwrap().body.as_deref(),
+            Some(&b"a"[..])
+        );
+        assert_eq!(
+            second_res.await.unwrap().unwrap().body.as_deref(),
+            Some(&b"b"[..])
+        );
+        assert_eq!(
+            server.await.unwrap(),
+            vec![
+                "GET https://api.warframe.market/a HTTP/1.1",
+                "GET https://api.warframe.market/b HTTP/1.1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exchange_connection_close() {
+        let policy = RetryPolicy::default();
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(serve(
+            server,
+            2,
+            b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 1\r\n\r\na",
+        ));
+        let (first, first_res) = job(Method::GET, "/a");
+        let (second, _second_res) = job(Method::GET, "/b");
+        let mut conn = BufReader::new(client);
+        let (rest, keep_alive, _) =
+            exchange(&mut conn, VecDeque::from([first, second]), false, &policy).await;
+
+        // the second one goes out again on a new connection
+        assert_eq!(rest.len(), 1);
+        assert!(!keep_alive);
+        assert!(first_res.await.unwrap().is_ok());
+        server.await.unwrap();
+    }
+
+    // the server closed the connection while it sat in the pool, and drops
+    // whatever comes in after that
+    #[tokio::test]
+    async fn test_exchange_stale_connection() {
+        let policy = RetryPolicy::default();
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(serve(server, 1, b""));
+        let (get, _get_res) = job(Method::GET, "/a");
+        let mut conn = BufReader::new(client);
+        let (rest, keep_alive, failed) =
+            exchange(&mut conn, VecDeque::from([get]), true, &policy).await;
+        server.await.unwrap();
+        assert_eq!(rest.len(), 1);
+        assert!(!keep_alive);
+        // no need to wait before trying a new connection
+        assert_eq!(failed, None);
+
+        // a post might have been processed already, so it fails instead
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(serve(server, 1, b""));
+        let (post, post_res) = job(Method::POST, "/a");
+        let mut conn = BufReader::new(client);
+        let (rest, _, _) = exchange(&mut conn, VecDeque::from([post]), true, &policy).await;
+        server.await.unwrap();
+        assert!(rest.is_empty());
+        assert!(post_res.await.unwrap().is_err());
+    }
+
+    enum Behaviour {
+        // reads the request and hangs up without answering
+        HangUp,
+        Respond(&'static [u8]),
+    }
+
+    // a tls server on localhost that treats the nth connection it accepts
+    // the way the nth behaviour says
+    async fn stand_in(behaviours: Vec<Behaviour>) -> (u16, RootCertStore, JoinHandle<()>) {
+        let _ = tokio_rustls::rustls::crypto::ring::default_provider().install_default();
+        let CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.der().clone()], key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = tokio::spawn(async move {
+            for behaviour in behaviours {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = acceptor.accept(stream).await.unwrap();
+                let mut stream = BufReader::new(stream);
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                if let Behaviour::Respond(response) = behaviour {
+                    stream.write_all(response).await.unwrap();
+                    stream.shutdown().await.unwrap();
+                }
+            }
+        });
+        (port, roots, server)
+    }
+
+    fn client(
+        port: u16,
+        roots: RootCertStore,
+        stop: &broadcast::Sender<StopSignal>,
+    ) -> ClientHandle {
+        ClientHandle::new(stop.subscribe())
+            .addr("https://localhost/")
+            .unwrap()
+            .port(port)
+            .timeout(Duration::from_secs(2))
+            .retry(RetryPolicy {
+                retries: 2,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(5),
+            })
+            .root_certificates(roots)
+            .start_client()
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_hang_up() {
+        let (port, roots, server) = stand_in(vec![
+            Behaviour::HangUp,
+            Behaviour::Respond(
+                b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 2\r\n\r\nok",
+            ),
+            Behaviour::HangUp,
+        ])
+        .await;
+        let (stop, _) = broadcast::channel(1);
+        let client = client(port, roots, &stop);
+
+        let get = RequestBuilder::new()
+            .method(Method::GET)
+            .uri("https://localhost/a")
+            .build();
+        let res = client.send(get).await.unwrap();
+        assert_eq!(res.body.as_deref(), Some(&b"ok"[..]));
+
+        // the server might have acted on a post before hanging up
+        let post = RequestBuilder::new()
+            .method(Method::POST)
+            .uri("https://localhost/a")
+            .build();
+        assert!(client.send(post).await.is_err());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retry_connect() {
+        // nothing listens on the port once the listener is gone
+        let (port, roots, server) = stand_in(vec![]).await;
+        server.await.unwrap();
+        let (stop, _) = broadcast::channel(1);
+        let client = client(port, roots, &stop);
+
+        let get = RequestBuilder::new()
+            .method(Method::GET)
+            .uri("https://localhost/a")
+            .build();
+        match client.send(get).await {
+            Err(SendError::ConnectFailed(_)) => {}
+            res => panic!("expected a connection error, got {res:?}"),
+        }
+    }
+}
@@ -4,24 +4,49 @@ use std::{
     time::Duration,
 };
 
+use once_cell::sync::OnceCell;
 use serde_json::json;
 use tokio::sync::{broadcast::Receiver as BReceiver, Mutex};
 
-use crate::{block_in_place, AppError, StopSignal};
+use crate::{
+    block_in_place, config::HttpConfig, rate_limiter::RateLimiter, AppError, StopSignal,
+};
 
 use super::{
     auth_state::AuthState,
-    client::{ArcClientHandle, ClientHandle, HttpClient, Request, RequestBuilder, Response},
+    cache::ResponseCache,
+    client::{
+        ArcClientHandle, ClientHandle, Headers, HttpClient, RequestBuilder, StatusCode,
+        Transport,
+    },
 };
 
+// clones share the connection pool and the rate limiter
 #[derive(Debug)]
 pub struct QFClient {
     pub endpoint: String,
+    limiter: Arc<RateLimiter>,
     auth: Arc<Mutex<AuthState>>,
-    client_handle: Option<ArcClientHandle>,
+    client_handle: Arc<OnceCell<ArcClientHandle>>,
+    cache: Option<Arc<ResponseCache>>,
+    http: HttpConfig,
     stop_signal: BReceiver<StopSignal>,
 }
 
+impl Clone for QFClient {
+    fn clone(&self) -> Self {
+        Self {
+            endpoint: self.endpoint.clone(),
+            limiter: self.limiter.clone(),
+            auth: self.auth.clone(),
+            client_handle: self.client_handle.clone(),
+            cache: self.cache.clone(),
+            http: self.http.clone(),
+            stop_signal: self.stop_signal.resubscribe(),
+        }
+    }
+}
+
 impl HttpClient for QFClient {
     async fn sender_fn(
         &mut self,
@@ -39,39 +64,71 @@ impl HttpClient for QFClient {
             rq
         };
         drop(auth_mutex);
-        let client_handle = if let Some(handle) = self.client_handle.clone() {
-            handle
-        } else {
-            let (request_sender, request_receiver) = tokio::sync::mpsc::channel::<Request>(1);
-            let (respones_sender, response_receiver) = tokio::sync::mpsc::channel::<Response>(1);
-            let stop_signal = self.stop_signal.resubscribe();
-            let handle = ClientHandle::new(stop_signal.resubscribe())
+        let client_handle = self.client_handle.get_or_try_init(|| {
+            let handle = ClientHandle::new(self.stop_signal.resubscribe())
                 .port(443)
-                .addr("https://api.quantframe.app/")
+                .addr(&self.endpoint)
                 .map_err(|e| AppError::new(e.to_string(), "send_request".to_string()))?
                 .timeout(Duration::from_secs(5))
-                .send_channel(request_sender)
-                .receive_channel(response_receiver)
-                .start_client(request_receiver, respones_sender);
-            let handle = Arc::new(Mutex::new(handle));
-            self.client_handle = Some(handle.clone());
-            handle
-        };
-        Ok((client_handle, rq))
+                .connections(self.http.connections)
+                .idle_timeout(Duration::from_secs(self.http.idle_timeout_secs))
+                .pipeline_depth(self.http.pipeline_depth)
+                .retry(self.http.retry_policy())
+                .start_client();
+            Ok::<_, AppError>(Arc::new(handle))
+        })?;
+        Ok((client_handle.clone(), rq))
+    }
+
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        Some(&self.limiter)
     }
 
-    async fn rate_limit(&self) {}
+    fn response_cache(&self) -> Option<Arc<ResponseCache>> {
+        self.cache.clone()
+    }
+
+    async fn after_response(&mut self, status: &StatusCode, _headers: &Headers) {
+        if status.code == 401 {
+            eprintln!("WARNING: quantframe rejected the token");
+            self.auth.lock().await.qf_rejected = true;
+        }
+    }
 }
 
 impl QFClient {
     pub fn new(auth: Arc<Mutex<AuthState>>, stop_signal: BReceiver<StopSignal>) -> Self {
+        let endpoint = String::from("https://api.quantframe.app");
         Self {
-            endpoint: String::from("https://api.quantframe.app/"),
+            limiter: Arc::new(RateLimiter::new(&endpoint, 5.0, Duration::from_secs(1))),
+            endpoint,
             auth,
-            client_handle: None,
+            client_handle: Default::default(),
+            cache: None,
+            http: Default::default(),
             stop_signal,
         }
     }
+
+    pub fn http_config(mut self, http: &HttpConfig) -> Self {
+        self.endpoint = http.qf_url.trim_end_matches('/').into();
+        self.limiter = Arc::new(http.rate_limiter(&self.endpoint, http.qf_rate_limit));
+        self.http = http.clone();
+        self
+    }
+
+    pub fn response_cache(mut self, cache: Option<Arc<ResponseCache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    // for talking to something other than the real api, see `ClientHandle::transport`
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        let handle = ClientHandle::new(self.stop_signal.resubscribe()).transport(transport);
+        self.client_handle = Arc::new(OnceCell::with_value(Arc::new(handle)));
+        self
+    }
+
     pub async fn login(
         &mut self,
         id: Arc<str>,
@@ -80,7 +137,7 @@ impl QFClient {
     ) -> Result<(), AppError> {
         let req = RequestBuilder::new()
             .method(super::client::Method::POST)
-            .uri(format!("{}auth/login", self.endpoint).as_str())
+            .uri(format!("{}/auth/login", self.endpoint).as_str())
             .header("Device: thingamajig".parse().unwrap())
             .header("Content-Type: application/json".parse().unwrap())
             .header("Accept: */*".parse().unwrap())
@@ -97,10 +154,21 @@ impl QFClient {
         let token = value.as_str().expect("token should be a string");
         let mut auth = self.auth.lock().await;
         let auth = auth.deref_mut();
-        auth.qf_access_token = token.into();
+        auth.set_qf_token(token.into());
         auth.update().map_err(|e| e.prop("login".into()))?;
         Ok(())
     }
+
+    // logs in with what the last warframe.market login stored
+    pub async fn relogin(&mut self) -> Result<(), AppError> {
+        let (id, check_code, ingame_name) = {
+            let auth = self.auth.lock().await;
+            (auth.id.clone(), auth.check_code.clone(), auth.ingame_name.clone())
+        };
+        self.login(id, check_code, ingame_name)
+            .await
+            .map_err(|e| e.prop("relogin".into()))
+    }
 }
 
 #[cfg(test)]
@@ -108,30 +176,49 @@ mod tests {
 
     use std::sync::Arc;
 
+    use serde_json::json;
     use tokio::sync::{broadcast, Mutex};
 
     use crate::{
-        block_in_place,
         http_client::{
-            auth_state::AuthState,
-            client::{HttpClient, Method, RequestBuilder},
+            auth_state::AuthState, mock_transport::MockTransport, secret_store::SecretKey,
         },
+        rivens::inventory::riven_lookop::RivenDataLookup,
     };
 
     use super::QFClient;
 
-    #[test]
-    fn test_qfclient() {
-        let auth = AuthState::setup().unwrap();
+    // `login` blocks in place, which needs the multi threaded runtime
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_qfclient() {
+        let dir = tempfile::tempdir().unwrap();
+        let auth = AuthState::setup(
+            &dir.path().join("auth.json"),
+            SecretKey::KeyFile(dir.path().join("auth.key")),
+        )
+        .unwrap();
         let auth = Arc::new(Mutex::new(auth));
+        let mock = MockTransport::from_json(json!([
+            {"method": "POST", "path": "/auth/login", "body": {"token": "qf-token"}},
+            {
+                "method": "GET",
+                "path": "/items/riven/raw",
+                "body": {"weapons": [], "rivens_attributes": [], "available_attributes": []},
+            },
+        ]));
         let (stop_sender, _) = broadcast::channel(1);
-        let mut client = QFClient::new(auth, stop_sender.subscribe());
-        let _ = block_in_place!(async move {
-            let req = RequestBuilder::new()
-                .method(Method::GET)
-                .uri(client.endpoint.as_str())
-                .build();
-            client.send_request(req).await
-        });
+        let mut client = QFClient::new(auth.clone(), stop_sender.subscribe()).transport(mock.clone());
+
+        client.login("1d".into(), "c0de".into(), "toopsi".into()).await.unwrap();
+        assert_eq!(&*auth.lock().await.qf_access_token, "qf-token");
+        assert_eq!(mock.requests()[0].body.as_ref().unwrap()["ingame_name"], "toopsi");
+
+        // fetched once, then read from the file
+        let path = dir.path().join("lookup.json");
+        let client = Arc::new(Mutex::new(client));
+        let lookup = RivenDataLookup::setup(client.clone(), &path).await.unwrap();
+        assert!(lookup.weapons.unwrap().is_empty());
+        RivenDataLookup::setup(client, &path).await.unwrap();
+        assert_eq!(mock.requests().len(), 2);
     }
 }
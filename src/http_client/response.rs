@@ -0,0 +1,533 @@
+use std::io::{self, ErrorKind};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use super::client::{Header, Headers, Response, SendError, StatusCode};
+
+// anything bigger than this isn't coming from the apis we talk to
+static MAX_HEAD_LEN: usize = 64 * 1024;
+static MAX_BODY_LEN: usize = 64 * 1024 * 1024;
+
+// how the end of the body is found, RFC 9112 section 6.3
+#[derive(Debug, PartialEq)]
+enum Framing {
+    Empty,
+    Length(usize),
+    Chunked,
+    UntilClose,
+}
+
+fn malformed(from: &str, raw: &[u8]) -> SendError {
+    SendError::MalformedResponse((from.into(), String::from_utf8_lossy(raw).into()))
+}
+
+fn closed(while_reading: &str) -> SendError {
+    SendError::IoError(io::Error::new(
+        ErrorKind::UnexpectedEof,
+        format!("connection closed while reading {while_reading}"),
+    ))
+}
+
+// reads a single response off the connection. `head_request` responses never
+// have a body, whatever their headers say
+pub async fn read_response<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    head_request: bool,
+) -> Result<Response, SendError> {
+    loop {
+        let mut budget = MAX_HEAD_LEN;
+        let (minor_version, status) = read_status_line(reader, &mut budget).await?;
+        let mut headers = Headers(vec![]);
+        read_fields(reader, &mut budget, &mut headers).await?;
+
+        // 100 Continue and friends come before the actual response
+        if (100..200).contains(&status.code) && status.code != 101 {
+            continue;
+        }
+
+        let framing = framing(&status, &headers, head_request)?;
+        let body = match framing {
+            Framing::Empty => vec![],
+            Framing::Length(len) => {
+                let mut body = vec![0; len];
+                reader
+                    .read_exact(&mut body)
+                    .await
+                    .map_err(SendError::IoError)?;
+                body
+            }
+            Framing::Chunked => read_chunked(reader, &mut headers).await?,
+            Framing::UntilClose => read_until_close(reader).await?,
+        };
+        let keep_alive = framing != Framing::UntilClose && keep_alive(minor_version, &headers);
+        return Ok(Response {
+            status,
+            headers,
+            body: (!body.is_empty()).then(|| body.into()),
+            keep_alive,
+        });
+    }
+}
+
+// a line without its line ending, a lone `\n` is accepted too. `budget` is
+// what's left of the size limit and shrinks with every line
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    budget: &mut usize,
+) -> Result<Vec<u8>, SendError> {
+    let mut line = Vec::new();
+    let read = (&mut *reader)
+        .take(*budget as u64)
+        .read_until(b'\n', &mut line)
+        .await
+        .map_err(SendError::IoError)?;
+    if line.last() != Some(&b'\n') {
+        if read == *budget {
+            return Err(malformed("response head too large", &line));
+        }
+        return Err(closed("the response head"));
+    }
+    *budget -= read;
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(line)
+}
+
+async fn read_status_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    budget: &mut usize,
+) -> Result<(u8, StatusCode), SendError> {
+    // empty lines before the status line are to be ignored
+    let mut line = read_line(reader, budget).await?;
+    while line.is_empty() {
+        line = read_line(reader, budget).await?;
+    }
+    parse_status_line(&line)
+}
+
+fn parse_status_line(line: &[u8]) -> Result<(u8, StatusCode), SendError> {
+    let mut parts = line.splitn(3, |&b| b == b' ');
+    let version = parts.next().unwrap_or_default();
+    let minor_version = match version.strip_prefix(b"HTTP/1.") {
+        Some([minor]) if minor.is_ascii_digit() => minor - b'0',
+        _ => return Err(malformed("unsupported http version", line)),
+    };
+    let code = match parts.next() {
+        Some(code) if code.len() == 3 && code.iter().all(u8::is_ascii_digit) => code
+            .iter()
+            .fold(0u16, |acc, digit| acc * 10 + u16::from(digit - b'0')),
+        _ => return Err(malformed("invalid status code", line)),
+    };
+    // the reason phrase is optional and only meant for humans
+    let text = String::from_utf8_lossy(parts.next().unwrap_or_default());
+    Ok((
+        minor_version,
+        StatusCode {
+            code,
+            text: text.trim().into(),
+        },
+    ))
+}
+
+// header or trailer fields up to and including the empty line after them
+async fn read_fields<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    budget: &mut usize,
+    headers: &mut Headers,
+) -> Result<(), SendError> {
+    loop {
+        let line = read_line(reader, budget).await?;
+        match line.first() {
+            None => return Ok(()),
+            // obsolete line folding continues the previous value
+            Some(b' ') | Some(b'\t') => {
+                let last = headers
+                    .0
+                    .last_mut()
+                    .ok_or_else(|| malformed("folded line without a header", &line))?;
+                let more = String::from_utf8_lossy(&line);
+                last.1 = format!("{} {}", last.1, more.trim_matches([' ', '\t'])).into();
+            }
+            Some(_) => {
+                let colon = line
+                    .iter()
+                    .position(|&b| b == b':')
+                    .ok_or_else(|| malformed("header without a colon", &line))?;
+                let name = &line[..colon];
+                if name.is_empty()
+                    || name
+                        .iter()
+                        .any(|b| b.is_ascii_whitespace() || !b.is_ascii())
+                {
+                    return Err(malformed("invalid header name", &line));
+                }
+                let value = String::from_utf8_lossy(&line[colon + 1..]);
+                headers.0.push(Header(
+                    String::from_utf8_lossy(name).into(),
+                    value.trim_matches([' ', '\t']).into(),
+                ));
+            }
+        }
+    }
+}
+
+// every value of a header as one comma separated list
+fn list_values<'a>(headers: &'a Headers, name: &'a str) -> impl Iterator<Item = &'a str> {
+    headers
+        .0
+        .iter()
+        .filter(move |header| header.0.eq_ignore_ascii_case(name))
+        .flat_map(|header| header.1.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn framing(
+    status: &StatusCode,
+    headers: &Headers,
+    head_request: bool,
+) -> Result<Framing, SendError> {
+    if head_request || status.code < 200 || status.code == 204 || status.code == 304 {
+        return Ok(Framing::Empty);
+    }
+    // transfer encoding wins over content length, anything not ending in
+    // chunked can only be delimited by the connection closing
+    if let Some(last) = list_values(headers, "Transfer-Encoding").last() {
+        return Ok(match last.eq_ignore_ascii_case("chunked") {
+            true => Framing::Chunked,
+            false => Framing::UntilClose,
+        });
+    }
+    let mut length = None;
+    for value in list_values(headers, "Content-Length") {
+        let parsed = match value.bytes().all(|b| b.is_ascii_digit()) {
+            true => value.parse::<usize>().ok(),
+            false => None,
+        };
+        let parsed = parsed.ok_or_else(|| malformed("invalid Content-Length", value.as_bytes()))?;
+        if length.is_some_and(|length| length != parsed) {
+            return Err(malformed(
+                "conflicting Content-Length headers",
+                value.as_bytes(),
+            ));
+        }
+        length = Some(parsed);
+    }
+    match length {
+        Some(length) if length > MAX_BODY_LEN => {
+            Err(malformed("body too large", length.to_string().as_bytes()))
+        }
+        Some(length) => Ok(Framing::Length(length)),
+        None => Ok(Framing::UntilClose),
+    }
+}
+
+// trailer fields are added to `headers`
+async fn read_chunked<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    headers: &mut Headers,
+) -> Result<Vec<u8>, SendError> {
+    let mut body = Vec::new();
+    loop {
+        let mut budget = MAX_HEAD_LEN;
+        let line = read_line(reader, &mut budget).await?;
+        // chunk extensions after the `;` aren't used for anything
+        let size = line.split(|&b| b == b';').next().unwrap_or_default();
+        let size = size.trim_ascii();
+        let size = match !size.is_empty() && size.iter().all(u8::is_ascii_hexdigit) {
+            true => usize::from_str_radix(&String::from_utf8_lossy(size), 16).ok(),
+            false => None,
+        };
+        let size = size.ok_or_else(|| malformed("invalid chunk size", &line))?;
+        if size == 0 {
+            break;
+        }
+        let start = body.len();
+        let end = start
+            .checked_add(size)
+            .filter(|&end| end <= MAX_BODY_LEN)
+            .ok_or_else(|| malformed("body too large", &line))?;
+        body.resize(end, 0);
+        reader
+            .read_exact(&mut body[start..])
+            .await
+            .map_err(SendError::IoError)?;
+        let rest = read_line(reader, &mut budget).await?;
+        if !rest.is_empty() {
+            return Err(malformed("chunk longer than its size", &rest));
+        }
+    }
+    let mut budget = MAX_HEAD_LEN;
+    read_fields(reader, &mut budget, headers).await?;
+    Ok(body)
+}
+
+// servers that don't send tls' close_notify end the body with an unexpected
+// eof, which is just the end of the body here
+async fn read_until_close<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, SendError> {
+    let mut body = Vec::new();
+    let mut buf = [0; 8192];
+    loop {
+        let read = match reader.read(&mut buf).await {
+            Ok(0) => return Ok(body),
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(body),
+            Err(e) => return Err(SendError::IoError(e)),
+        };
+        if body.len() + read > MAX_BODY_LEN {
+            return Err(malformed("body too large", &[]));
+        }
+        body.extend_from_slice(&buf[..read]);
+    }
+}
+
+// http/1.0 closes unless asked not to, http/1.1 the other way around
+fn keep_alive(minor_version: u8, headers: &Headers) -> bool {
+    let mut options = list_values(headers, "Connection");
+    match minor_version {
+        0 => options.any(|option| option.eq_ignore_ascii_case("keep-alive")),
+        _ => !options.any(|option| option.eq_ignore_ascii_case("close")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use tokio::io::BufReader;
+
+    use super::read_response;
+    use crate::http_client::client::{Response, SendError};
+
+    struct Fixture {
+        name: &'static str,
+        raw: &'static [u8],
+        head_request: bool,
+        // status, body, keep alive
+        expected: Option<(u16, &'static [u8], bool)>,
+    }
+
+    static FIXTURES: &[Fixture] = &[
+        Fixture {
+            name: "content length",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 11\r\n\r\n{\"a\": true}",
+            head_request: false,
+            expected: Some((200, b"{\"a\": true}", true)),
+        },
+        Fixture {
+            name: "chunked with extensions and trailers",
+            raw: b"HTTP/1.1 200 OK\r\ntransfer-encoding: gzip, Chunked\r\n\r\n4;ext=1\r\nWiki\r\n5\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\n",
+            head_request: false,
+            expected: Some((200, b"Wikipedia in\r\n\r\nchunks.", true)),
+        },
+        Fixture {
+            name: "chunk size in upper case hex",
+            raw: b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1A\r\nabcdefghijklmnopqrstuvwxyz\r\n0\r\n\r\n",
+            head_request: false,
+            expected: Some((200, b"abcdefghijklmnopqrstuvwxyz", true)),
+        },
+        Fixture {
+            name: "read until close",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nno length at all",
+            head_request: false,
+            expected: Some((200, b"no length at all", false)),
+        },
+        Fixture {
+            name: "no content",
+            raw: b"HTTP/1.1 204 No Content\r\nContent-Length: 5\r\n\r\n",
+            head_request: false,
+            expected: Some((204, b"", true)),
+        },
+        Fixture {
+            name: "not modified",
+            raw: b"HTTP/1.1 304 Not Modified\r\nETag: \"abc\"\r\n\r\n",
+            head_request: false,
+            expected: Some((304, b"", true)),
+        },
+        Fixture {
+            name: "head request",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Length: 1234\r\n\r\n",
+            head_request: true,
+            expected: Some((200, b"", true)),
+        },
+        Fixture {
+            name: "connection close",
+            raw: b"HTTP/1.1 200 OK\r\nConnection: Close\r\nContent-Length: 2\r\n\r\nok",
+            head_request: false,
+            expected: Some((200, b"ok", false)),
+        },
+        Fixture {
+            name: "http/1.0 keep alive",
+            raw: b"HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 2\r\n\r\nok",
+            head_request: false,
+            expected: Some((200, b"ok", true)),
+        },
+        Fixture {
+            name: "http/1.0",
+            raw: b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nok",
+            head_request: false,
+            expected: Some((200, b"ok", false)),
+        },
+        Fixture {
+            name: "continue before the response",
+            raw: b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n",
+            head_request: false,
+            expected: Some((201, b"", true)),
+        },
+        Fixture {
+            name: "bare line feeds, no reason and a folded header",
+            raw: b"\r\nHTTP/1.1 404\nX-Long: a\n  b\nContent-Length: 3\n\nnop",
+            head_request: false,
+            expected: Some((404, b"nop", true)),
+        },
+        Fixture {
+            name: "binary body",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n\xff\x00\xfe\x01",
+            head_request: false,
+            expected: Some((200, b"\xff\x00\xfe\x01", true)),
+        },
+        Fixture {
+            name: "repeated equal lengths",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Length: 2, 2\r\nContent-Length: 2\r\n\r\nok",
+            head_request: false,
+            expected: Some((200, b"ok", true)),
+        },
+        Fixture {
+            name: "conflicting lengths",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nok!",
+            head_request: false,
+            expected: None,
+        },
+        Fixture {
+            name: "signed length",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Length: +2\r\n\r\nok",
+            head_request: false,
+            expected: None,
+        },
+        Fixture {
+            name: "truncated body",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort",
+            head_request: false,
+            expected: None,
+        },
+        Fixture {
+            name: "truncated chunks",
+            raw: b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab",
+            head_request: false,
+            expected: None,
+        },
+        Fixture {
+            name: "invalid chunk size",
+            raw: b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nab\r\n0\r\n\r\n",
+            head_request: false,
+            expected: None,
+        },
+        Fixture {
+            name: "huge chunk size",
+            raw: b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffffff\r\n",
+            head_request: false,
+            expected: None,
+        },
+        Fixture {
+            name: "space before colon",
+            raw: b"HTTP/1.1 200 OK\r\nContent-Length : 2\r\n\r\nok",
+            head_request: false,
+            expected: None,
+        },
+        Fixture {
+            name: "not http",
+            raw: b"SSH-2.0-OpenSSH_9.6\r\n\r\n",
+            head_request: false,
+            expected: None,
+        },
+        Fixture {
+            name: "empty",
+            raw: b"",
+            head_request: false,
+            expected: None,
+        },
+    ];
+
+    // a 1 byte buffer makes every read return a single byte, like a slow
+    // connection would
+    async fn parse(raw: &[u8], head_request: bool, capacity: usize) -> Result<Response, SendError> {
+        let mut reader = BufReader::with_capacity(capacity, raw);
+        read_response(&mut reader, head_request).await
+    }
+
+    #[tokio::test]
+    async fn test_read_response_fixtures() {
+        for fixture in FIXTURES {
+            for capacity in [1, 7, 8192] {
+                let res = parse(fixture.raw, fixture.head_request, capacity).await;
+                match (fixture.expected, res) {
+                    (Some((code, body, keep_alive)), Ok(res)) => {
+                        assert_eq!(res.status.code, code, "{}", fixture.name);
+                        assert_eq!(
+                            res.body.as_deref().unwrap_or_default(),
+                            body,
+                            "{}",
+                            fixture.name
+                        );
+                        assert_eq!(res.keep_alive, keep_alive, "{}", fixture.name);
+                    }
+                    (None, Err(_)) => {}
+                    (expected, res) => {
+                        panic!("{}: expected {expected:?}, got {res:?}", fixture.name)
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_response_headers() {
+        let raw = b"HTTP/1.1 200 OK\r\nset-cookie: JWT=abc; Path=/\r\nX-Long: a\r\n\tb\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-Trailer: yes\r\n\r\n";
+        let res = parse(raw, false, 8192).await.unwrap();
+        assert_eq!(
+            res.headers.get("Set-Cookie").as_deref(),
+            Some("JWT=abc; Path=/")
+        );
+        assert_eq!(res.headers.get("x-long").as_deref(), Some("a b"));
+        assert_eq!(res.headers.get("X-Trailer").as_deref(), Some("yes"));
+        assert_eq!(res.status.text.as_ref(), "OK");
+        assert!(res.body.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_response_keeps_the_next_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\naHTTP/1.1 202 Accepted\r\nContent-Length: 1\r\n\r\nb";
+        let mut reader = BufReader::new(&raw[..]);
+        let first = read_response(&mut reader, false).await.unwrap();
+        let second = read_response(&mut reader, false).await.unwrap();
+        assert_eq!(first.body.as_deref(), Some(&b"a"[..]));
+        assert_eq!(second.status.code, 202);
+        assert_eq!(second.body.as_deref(), Some(&b"b"[..]));
+    }
+
+    // mangled fixtures have to come back as errors or responses, never panics
+    #[tokio::test]
+    async fn test_read_response_fuzz() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for _ in 0..2000 {
+            let fixture = &FIXTURES[rng.gen_range(0..FIXTURES.len())];
+            let mut raw = fixture.raw.to_vec();
+            for _ in 0..rng.gen_range(1..4) {
+                if raw.is_empty() {
+                    break;
+                }
+                let at = rng.gen_range(0..raw.len());
+                match rng.gen_range(0..4) {
+                    0 => raw[at] = rng.gen(),
+                    1 => raw.truncate(at),
+                    2 => {
+                        raw.remove(at);
+                    }
+                    _ => raw.insert(at, *b"\r\n:; 0f".get(rng.gen_range(0..7)).unwrap()),
+                }
+            }
+            let _ = parse(&raw, fixture.head_request, rng.gen_range(1..64)).await;
+        }
+    }
+}
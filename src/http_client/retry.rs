@@ -0,0 +1,117 @@
+use std::{io::ErrorKind, time::Duration};
+
+use rand::Rng;
+
+use super::client::{ConnectionError, Request, SendError};
+
+// when a request that failed on the way is tried again, and how long to wait
+// before that
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    // tries after the first one
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+// the connection broke, as opposed to the server answering with nonsense
+pub(super) fn is_closed(e: &SendError) -> bool {
+    match e {
+        SendError::IoError(e) => matches!(
+            e.kind(),
+            ErrorKind::UnexpectedEof
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::WriteZero
+        ),
+        _ => false,
+    }
+}
+
+impl RetryPolicy {
+    // "full jitter": anywhere between nothing and the doubled delay, so
+    // clients that failed together don't all come back at once
+    pub fn delay(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        cap.mul_f64(rng.gen::<f64>())
+    }
+
+    // a request that never reached the server can always go out again,
+    // one that might have only if sending it twice does no harm
+    pub(super) fn retries_send(&self, attempt: u32, request: &Request, e: &SendError) -> bool {
+        attempt < self.retries
+            && (is_closed(e) || matches!(e, SendError::RequestTimeout(_)))
+            && request.is_idempotent()
+    }
+
+    pub(super) fn retries_connect(&self, attempt: u32, e: &ConnectionError) -> bool {
+        attempt < self.retries
+            && matches!(
+                e,
+                ConnectionError::IoError(_) | ConnectionError::ConnectionTimeout(_)
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::RetryPolicy;
+    use crate::http_client::client::{ConnectionError, Method, RequestBuilder, SendError};
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        for attempt in 0..10 {
+            let cap = Duration::from_millis(100 * 2u64.pow(attempt)).min(Duration::from_secs(1));
+            for _ in 0..100 {
+                assert!(policy.delay(attempt, &mut rng) <= cap);
+            }
+        }
+        // doesn't overflow for silly attempt counts
+        assert!(policy.delay(u32::MAX, &mut rng) <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_retry_rules() {
+        let policy = RetryPolicy::default();
+        let get = RequestBuilder::new().method(Method::GET).build();
+        let post = RequestBuilder::new().method(Method::POST).build();
+        let reset = || SendError::IoError(std::io::ErrorKind::ConnectionReset.into());
+        let timeout = SendError::RequestTimeout(Duration::from_secs(5));
+        let malformed = SendError::MalformedResponse(("".into(), "".into()));
+
+        assert!(policy.retries_send(0, &get, &reset()));
+        assert!(policy.retries_send(2, &get, &timeout));
+        assert!(!policy.retries_send(3, &get, &reset()));
+        assert!(!policy.retries_send(0, &post, &reset()));
+        assert!(!policy.retries_send(0, &get, &malformed));
+
+        let refused = ConnectionError::IoError(std::io::ErrorKind::ConnectionRefused.into());
+        assert!(policy.retries_connect(0, &refused));
+        assert!(!policy.retries_connect(3, &refused));
+        assert!(!policy.retries_connect(0, &ConnectionError::HostNone));
+    }
+}
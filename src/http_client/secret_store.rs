@@ -0,0 +1,255 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+
+static KEY_LEN: usize = 32;
+static SALT_LEN: usize = 16;
+static PBKDF2_ITERATIONS: u32 = 600_000;
+static AAD: &[u8] = b"raw_html_rendering auth";
+
+// where the key secrets are encrypted with comes from, a passphrase when the
+// user gave one or a random key in a file only they can read otherwise
+#[derive(Clone, Debug)]
+pub enum SecretKey {
+    Passphrase(String),
+    KeyFile(PathBuf),
+}
+
+// what ends up on disk, everything but the version is hex
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    version: u8,
+    kdf: String,
+    #[serde(default)]
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, AppError> {
+    if !s.len().is_multiple_of(2) {
+        return Err(AppError::new("Odd length hex".into(), "from_hex".into()));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|e| AppError::new(e.to_string(), "from_hex".into()))
+        })
+        .collect()
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>, AppError> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| AppError::new("No randomness available".into(), "random_bytes".into()))?;
+    Ok(bytes)
+}
+
+// writes to a temporary file next to `path` and renames it over `path`, so a
+// crash can't leave half a file behind. only the owner can read it
+pub fn write_private_file(path: &Path, content: &[u8]) -> Result<(), AppError> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(&tmp)
+        .map_err(|e| AppError::new(e.to_string(), "write_private_file: open".into()))?;
+    // `mode` only applies when the file is created
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(|e| AppError::new(e.to_string(), "write_private_file: set_permissions".into()))?;
+    }
+    file.write_all(content)
+        .map_err(|e| AppError::new(e.to_string(), "write_private_file: write_all".into()))?;
+    file.sync_all()
+        .map_err(|e| AppError::new(e.to_string(), "write_private_file: sync_all".into()))?;
+    fs::rename(&tmp, path)
+        .map_err(|e| AppError::new(e.to_string(), "write_private_file: rename".into()))
+}
+
+impl SecretKey {
+    fn kdf(&self) -> &'static str {
+        match self {
+            SecretKey::Passphrase(_) => "pbkdf2-sha256",
+            SecretKey::KeyFile(_) => "keyfile",
+        }
+    }
+
+    // creates the key file the first time it's needed
+    fn derive(&self, salt: &[u8]) -> Result<Vec<u8>, AppError> {
+        match self {
+            SecretKey::Passphrase(passphrase) => {
+                let mut key = vec![0u8; KEY_LEN];
+                pbkdf2::derive(
+                    pbkdf2::PBKDF2_HMAC_SHA256,
+                    NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+                    salt,
+                    passphrase.as_bytes(),
+                    &mut key,
+                );
+                Ok(key)
+            }
+            SecretKey::KeyFile(path) if path.exists() => {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| AppError::new(e.to_string(), "SecretKey::derive: read_to_string".into()))?;
+                let key = from_hex(content.trim()).map_err(|e| e.prop("SecretKey::derive".into()))?;
+                if key.len() != KEY_LEN {
+                    return Err(AppError::new(
+                        format!("{} doesn't hold a valid key", path.display()),
+                        "SecretKey::derive".into(),
+                    ));
+                }
+                Ok(key)
+            }
+            SecretKey::KeyFile(path) => {
+                let key = random_bytes(KEY_LEN).map_err(|e| e.prop("SecretKey::derive".into()))?;
+                write_private_file(path, to_hex(&key).as_bytes())
+                    .map_err(|e| e.prop("SecretKey::derive".into()))?;
+                Ok(key)
+            }
+        }
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Result<LessSafeKey, AppError> {
+        let key = self.derive(salt).map_err(|e| e.prop("SecretKey::cipher".into()))?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_| AppError::new("Invalid key".into(), "SecretKey::cipher".into()))?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Envelope, AppError> {
+        let salt = match self {
+            SecretKey::Passphrase(_) => random_bytes(SALT_LEN),
+            SecretKey::KeyFile(_) => Ok(Vec::new()),
+        }
+        .map_err(|e| e.prop("SecretKey::encrypt".into()))?;
+        let cipher = self.cipher(&salt).map_err(|e| e.prop("SecretKey::encrypt".into()))?;
+        let nonce = random_bytes(NONCE_LEN).map_err(|e| e.prop("SecretKey::encrypt".into()))?;
+
+        let mut in_out = plaintext.to_vec();
+        cipher
+            .seal_in_place_append_tag(
+                Nonce::try_assume_unique_for_key(&nonce).unwrap(),
+                Aad::from(AAD),
+                &mut in_out,
+            )
+            .map_err(|_| AppError::new("Could not encrypt".into(), "SecretKey::encrypt".into()))?;
+        Ok(Envelope {
+            version: 1,
+            kdf: self.kdf().into(),
+            salt: to_hex(&salt),
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&in_out),
+        })
+    }
+
+    pub fn decrypt(&self, envelope: &Envelope) -> Result<Vec<u8>, AppError> {
+        if envelope.version != 1 || envelope.kdf != self.kdf() {
+            return Err(AppError::new(
+                format!(
+                    "Secrets were stored with {} v{}, not {}",
+                    envelope.kdf,
+                    envelope.version,
+                    self.kdf()
+                ),
+                "SecretKey::decrypt".into(),
+            ));
+        }
+        let salt = from_hex(&envelope.salt).map_err(|e| e.prop("SecretKey::decrypt".into()))?;
+        let nonce = from_hex(&envelope.nonce).map_err(|e| e.prop("SecretKey::decrypt".into()))?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce)
+            .map_err(|_| AppError::new("Invalid nonce".into(), "SecretKey::decrypt".into()))?;
+        let mut in_out =
+            from_hex(&envelope.ciphertext).map_err(|e| e.prop("SecretKey::decrypt".into()))?;
+        let cipher = self.cipher(&salt).map_err(|e| e.prop("SecretKey::decrypt".into()))?;
+        let plaintext = cipher
+            .open_in_place(nonce, Aad::from(AAD), &mut in_out)
+            .map_err(|_| {
+                AppError::new(
+                    "Could not decrypt, wrong passphrase or key file?".into(),
+                    "SecretKey::decrypt".into(),
+                )
+            })?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+// removes the file, ignoring that it might not exist
+pub fn remove_file(path: &Path) -> Result<(), AppError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(AppError::new(e.to_string(), "remove_file".into()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{write_private_file, SecretKey};
+
+    #[test]
+    fn test_secret_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = SecretKey::KeyFile(dir.path().join("auth.key"));
+        let envelope = key_file.encrypt(b"jwt").unwrap();
+        assert!(!envelope.ciphertext.contains(&super::to_hex(b"jwt")));
+        assert_eq!(key_file.decrypt(&envelope).unwrap(), b"jwt");
+        // a different key file can't read it
+        let other = SecretKey::KeyFile(dir.path().join("other.key"));
+        assert!(other.decrypt(&envelope).is_err());
+
+        let passphrase = SecretKey::Passphrase("hunter2".into());
+        let envelope = passphrase.encrypt(b"jwt").unwrap();
+        assert_eq!(passphrase.decrypt(&envelope).unwrap(), b"jwt");
+        assert!(SecretKey::Passphrase("hunter3".into()).decrypt(&envelope).is_err());
+        assert!(key_file.decrypt(&envelope).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("auth.json");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private_file(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!dir.path().join("auth.json.tmp").exists());
+    }
+}
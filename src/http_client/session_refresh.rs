@@ -0,0 +1,60 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    select,
+    sync::{broadcast::Receiver as BReceiver, Mutex},
+    time::{interval, MissedTickBehavior},
+};
+
+use crate::{AppError, StopSignal};
+
+use super::{auth_state::AuthState, qf_client::QFClient, wfm_client::WFMClient};
+
+static CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// tokens are replaced once they have less than this left
+static REFRESH_MARGIN: Duration = Duration::from_secs(10 * 60);
+
+// replaces tokens before they run out, or after a server rejected them. a
+// warframe.market session that can't be saved needs the user to log in again,
+// the ui asks for that through `/session_status`
+pub async fn keep_sessions_alive(
+    auth: Arc<Mutex<AuthState>>,
+    wfm: Arc<Mutex<WFMClient>>,
+    qf: Arc<Mutex<QFClient>>,
+    mut stop_signal: BReceiver<StopSignal>,
+) {
+    let mut check = interval(CHECK_INTERVAL);
+    check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        select! {
+            _ = check.tick() => {
+                if let Err(e) = refresh_sessions(&auth, &wfm, &qf).await {
+                    eprintln!("ERROR: Could not refresh session: {e}");
+                }
+            }
+            _ = stop_signal.recv() => break,
+        }
+    }
+}
+
+pub async fn refresh_sessions(
+    auth: &Mutex<AuthState>,
+    wfm: &Mutex<WFMClient>,
+    qf: &Mutex<QFClient>,
+) -> Result<(), AppError> {
+    let (wfm_expiring, qf_needs_login) = {
+        let auth = auth.lock().await;
+        (auth.wfm_expiring(REFRESH_MARGIN), auth.qf_needs_login(REFRESH_MARGIN))
+    };
+    if wfm_expiring {
+        eprintln!("INFO: Refreshing warframe.market session");
+        let mut wfm = wfm.lock().await.clone();
+        wfm.refresh().await.map_err(|e| e.prop("refresh_sessions".into()))?;
+    }
+    if qf_needs_login {
+        eprintln!("INFO: Logging into quantframe again");
+        let mut qf = qf.lock().await.clone();
+        qf.relogin().await.map_err(|e| e.prop("refresh_sessions".into()))?;
+    }
+    Ok(())
+}
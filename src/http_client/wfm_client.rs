@@ -4,35 +4,62 @@ use std::{
     time::Duration,
 };
 
+use once_cell::sync::OnceCell;
+use serde_json::{from_value, json, Value};
 use tokio::sync::{Mutex, broadcast::Receiver as BReceiver};
 
-use crate::{jwt::jwt_is_valid, rate_limiter::RateLimiter, AppError, StopSignal};
+use crate::{
+    config::HttpConfig,
+    jwt::jwt_is_valid,
+    rate_limiter::RateLimiter,
+    rivens::{
+        inventory::{convert_raw_inventory::Item, database::database::Auction},
+        wfm_auctions::WFMAuction,
+    },
+    AppError, StopSignal,
+};
 
 use super::{
     auth_state::AuthState,
-    client::{ClientHandle, HttpClient, Method, Request, RequestBuilder, Response, StatusCode},
+    cache::ResponseCache,
+    client::{
+        ApiResult, ArcClientHandle, ClientHandle, Headers, HttpClient, Method, RequestBuilder,
+        StatusCode, StatusError, Transport,
+    },
 };
 
-type ArcClientHandle = Arc<Mutex<ClientHandle>>;
-
+// clones share the connection pool and the rate limiter, so they can send
+// requests at the same time without holding a lock on one client
 #[derive(Debug)]
 pub struct WFMClient {
     endpoint: String,
-    limiter: Arc<Mutex<RateLimiter>>,
+    limiter: Arc<RateLimiter>,
     auth: Arc<Mutex<AuthState>>,
-    client_handle: Option<ArcClientHandle>,
+    client_handle: Arc<OnceCell<ArcClientHandle>>,
+    cache: Option<Arc<ResponseCache>>,
+    http: HttpConfig,
     stop_signal: BReceiver<StopSignal>
 }
 
+impl Clone for WFMClient {
+    fn clone(&self) -> Self {
+        Self {
+            endpoint: self.endpoint.clone(),
+            limiter: self.limiter.clone(),
+            auth: self.auth.clone(),
+            client_handle: self.client_handle.clone(),
+            cache: self.cache.clone(),
+            http: self.http.clone(),
+            stop_signal: self.stop_signal.resubscribe(),
+        }
+    }
+}
+
 impl HttpClient for WFMClient {
     async fn sender_fn(
         &mut self,
         rq: RequestBuilder,
     ) -> Result<(ArcClientHandle, RequestBuilder), AppError> {
-        let mut limiter_mutex = self.limiter.lock().await;
-        let limiter = limiter_mutex.deref_mut();
-        limiter.wait_for_token().await;
-        drop(limiter_mutex);
         let auth_mutex = self.auth.lock().await; // WHY DEADLOCK ?????????????????????????????????????
         let auth = auth_mutex.deref();
         let rq = rq.header(
@@ -41,44 +68,98 @@ impl HttpClient for WFMClient {
                 .expect("infallible"),
         );
         drop(auth_mutex);
-        let (request_sender, request_receiver) = tokio::sync::mpsc::channel::<Request>(1);
-        let (respones_sender, response_receiver) = tokio::sync::mpsc::channel::<Response>(1);
-        let client_handle = if let Some(handle) = self.client_handle.clone() {
-            handle
-        } else {
+        let client_handle = self.client_handle.get_or_try_init(|| {
             let handle = ClientHandle::new(self.stop_signal.resubscribe())
                 .port(443)
-                .addr("https://api.warframe.market/")
+                .addr(&self.endpoint)
                 .map_err(|e| AppError::new(e.to_string(), "send_request".to_string()))?
                 .timeout(Duration::from_secs(5))
-                .send_channel(request_sender)
-                .receive_channel(response_receiver)
-                .start_client(request_receiver, respones_sender);
-            let handle = Arc::new(Mutex::new(handle));
-            self.client_handle = Some(handle.clone());
-            handle
-        };
-        Ok((client_handle, rq))
+                .connections(self.http.connections)
+                .idle_timeout(Duration::from_secs(self.http.idle_timeout_secs))
+                .pipeline_depth(self.http.pipeline_depth)
+                .retry(self.http.retry_policy())
+                .start_client();
+            Ok::<_, AppError>(Arc::new(handle))
+        })?;
+        Ok((client_handle.clone(), rq))
+    }
+
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        Some(&self.limiter)
     }
 
-    async fn rate_limit(&self) {
-        let mut limiter = self.limiter.lock().await;
-        let limiter = limiter.deref_mut();
-        limiter.add_delay(1.0);
+    fn response_cache(&self) -> Option<Arc<ResponseCache>> {
+        self.cache.clone()
+    }
+
+    // warframe.market sends a new jwt cookie along with responses to signed in
+    // requests, keeping it is what keeps the session alive
+    async fn after_response(&mut self, status: &StatusCode, headers: &Headers) {
+        let mut auth = self.auth.lock().await;
+        if auth.wfm_access_token.is_empty() {
+            return;
+        }
+        if status.code == 401 {
+            eprintln!("WARNING: warframe.market rejected the session");
+            auth.wfm_rejected = true;
+            return;
+        }
+        let token = match headers.get("Set-Cookie").and_then(|cookie| jwt_cookie(&cookie)) {
+            Some(v) if status.code < 300 && v != auth.wfm_access_token => v,
+            _ => return,
+        };
+        auth.set_wfm_token(token);
+        if let Err(e) = auth.update() {
+            eprintln!("ERROR: Could not store refreshed token: {e}");
+        }
     }
 }
 
+// the value of a `JWT=...; Path=/; ...` cookie
+fn jwt_cookie(cookie: &str) -> Option<Arc<str>> {
+    let value = cookie.strip_prefix("JWT=")?;
+    let value = value.split_once(';').map(|(v, _)| v).unwrap_or(value);
+    (!value.is_empty()).then(|| value.into())
+}
+
 impl WFMClient {
     pub fn new(auth: Arc<Mutex<AuthState>>, stop_signal: BReceiver<StopSignal>) -> Self {
+        let endpoint = String::from("https://api.warframe.market/v1");
         WFMClient {
-            endpoint: String::from("https://api.warframe.market/v1"),
-            limiter: Arc::new(Mutex::new(RateLimiter::new(1.0, Duration::new(1, 0)))),
+            limiter: Arc::new(RateLimiter::new(&endpoint, 1.0, Duration::from_secs(1))),
+            endpoint,
             auth,
-            client_handle: None,
+            client_handle: Default::default(),
+            cache: None,
+            http: Default::default(),
             stop_signal,
         }
     }
 
+    pub fn http_config(mut self, http: &HttpConfig) -> Self {
+        self.endpoint = http.wfm_url.trim_end_matches('/').into();
+        self.limiter = Arc::new(http.rate_limiter(&self.endpoint, http.wfm_rate_limit));
+        self.http = http.clone();
+        self
+    }
+
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Arc::new(limiter);
+        self
+    }
+
+    pub fn response_cache(mut self, cache: Option<Arc<ResponseCache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    // for talking to something other than the real api, see `ClientHandle::transport`
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        let handle = ClientHandle::new(self.stop_signal.resubscribe()).transport(transport);
+        self.client_handle = Arc::new(OnceCell::with_value(Arc::new(handle)));
+        self
+    }
+
     pub async fn login(
         &mut self,
         email: &str,
@@ -94,12 +175,9 @@ impl WFMClient {
             Err(e) => return Err(AppError::new(e.to_string(), String::from("login: "))),
         };
         let (val, headers) = response.res;
-        let token: Arc<str>;
-        if let Some(cookie_header) = headers.get("Set-Cookie") {
-            let cookies = &cookie_header;
-            token = cookies[4..].split_once(';').unwrap_or(("", "")).0.into();
-        } else {
-            panic!("No access token returned!");
+        let token = match headers.get("Set-Cookie").and_then(|cookie| jwt_cookie(&cookie)) {
+            Some(v) => v,
+            None => panic!("No access token returned!"),
         };
         let mut user = AuthState::default();
         if response.status.code < 300 {
@@ -109,12 +187,14 @@ impl WFMClient {
                 user = serde_json::from_str(data.as_str())
                     .map_err(|e| AppError::new(e.to_string(), String::from("login: from_str")))?;
                 user.wfm_access_token = token;
-                user.update().map_err(|e| e.prop("login: ".into()))?;
             }
         }
         let mut auth = self.auth.lock().await;
         let auth = auth.deref_mut();
         auth.set(user);
+        if response.status.code < 300 {
+            auth.update().map_err(|e| e.prop("login: ".into()))?;
+        }
         Ok((
             response.status,
             auth.id.clone(),
@@ -123,18 +203,38 @@ impl WFMClient {
         ))
     }
 
+    // any signed in request gets a new cookie, the profile is the cheapest one
+    pub async fn refresh(&mut self) -> Result<(), AppError> {
+        let req = RequestBuilder::new()
+            .method(Method::GET)
+            .uri(format!("{}/profile", self.endpoint).as_str())
+            .build();
+        let res = self.send_request(req).await.map_err(|e| e.prop("refresh".into()))?;
+        if res.status.code >= 300 {
+            return Err(AppError::new(
+                format!("{} {}", res.status.code, res.status.text),
+                "refresh".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn session_expired(&self) -> bool {
+        self.auth.lock().await.wfm_expired()
+    }
+
     pub async fn validate(&mut self) -> Result<bool, AppError> {
         let auth_mutex = self.auth.lock().await;
         let auth = auth_mutex.deref();
         let valid_jwt = if !auth.wfm_access_token.is_empty() {
-            println!("jwt found, validating");
+            eprintln!("jwt found, validating");
             jwt_is_valid(auth.wfm_access_token.deref()).map_err(|e| e.prop("validate".into()))?
         } else {
-            println!("WARNING: No JWT Found");
+            eprintln!("WARNING: No JWT Found");
             return Ok(false);
         };
         if !valid_jwt {
-            println!("jwt not valid");
+            eprintln!("jwt not valid");
             return Ok(false);
         }
         drop(auth_mutex);
@@ -176,36 +276,357 @@ impl WFMClient {
         }
         Ok(is_valid)
     }
+
+    pub async fn create_auction(
+        &mut self,
+        item: &Item,
+        price: u32,
+        visible: bool,
+        note: &str,
+    ) -> Result<Auction, AppError> {
+        let attributes: Vec<Value> = item
+            .attributes
+            .iter()
+            .map(|attr| {
+                json!({
+                    "positive": attr.positive,
+                    "value": attr.value,
+                    "url_name": attr.url_name,
+                })
+            })
+            .collect();
+        let body = json!({
+            "note": note,
+            "starting_price": price,
+            "buyout_price": price,
+            "minimal_reputation": 0,
+            "visible": visible,
+            "private": false,
+            "item": {
+                "type": "riven",
+                "attributes": attributes,
+                "name": item.name.to_lowercase(),
+                "mastery_level": item.mastery_level,
+                "re_rolls": item.re_rolls,
+                "weapon_url_name": item.weapon_url_name,
+                "polarity": item.polarity,
+                "mod_rank": item.mod_rank,
+            },
+        });
+        let req = RequestBuilder::new()
+            .method(Method::POST)
+            .uri(format!("{}/auctions/create", self.endpoint).as_str())
+            .body(body)
+            .build();
+        let res = self
+            .send_request(req)
+            .await
+            .map_err(|e| e.prop("create_auction".into()))?;
+        auction_from_response(res, &item.oid).map_err(|e| e.prop("create_auction".into()))
+    }
+
+    pub async fn update_auction(
+        &mut self,
+        wfm_id: &str,
+        oid: &str,
+        price: u32,
+        visible: bool,
+        note: &str,
+    ) -> Result<Auction, AppError> {
+        let body = json!({
+            "note": note,
+            "starting_price": price,
+            "buyout_price": price,
+            "minimal_reputation": 0,
+            "visible": visible,
+            "private": false,
+        });
+        let req = RequestBuilder::new()
+            .method(Method::PUT)
+            .uri(format!("{}/auctions/entry/{}", self.endpoint, wfm_id).as_str())
+            .body(body)
+            .build();
+        let res = self
+            .send_request(req)
+            .await
+            .map_err(|e| e.prop("update_auction".into()))?;
+        auction_from_response(res, oid).map_err(|e| e.prop("update_auction".into()))
+    }
+
+    pub async fn get_user_auctions(&mut self) -> Result<Vec<WFMAuction>, AppError> {
+        let ingame_name = {
+            let auth = self.auth.lock().await;
+            auth.ingame_name.clone()
+        };
+        let req = RequestBuilder::new()
+            .method(Method::GET)
+            .uri(format!("{}/profile/{}/auctions", self.endpoint, ingame_name).as_str())
+            .build();
+        let res = self
+            .send_request(req)
+            .await
+            .map_err(|e| e.prop("get_user_auctions".into()))?;
+        auctions_from_response(res).map_err(|e| e.prop("get_user_auctions".into()))
+    }
+
+    pub async fn search_auctions(
+        &mut self,
+        weapon_url_name: &str,
+    ) -> Result<Vec<WFMAuction>, AppError> {
+        let req = RequestBuilder::new()
+            .method(Method::GET)
+            .uri(
+                format!(
+                    "{}/auctions/search?type=riven&weapon_url_name={}&sort_by=price_asc",
+                    self.endpoint, weapon_url_name
+                )
+                .as_str(),
+            )
+            .build();
+        let res = self
+            .send_request(req)
+            .await
+            .map_err(|e| e.prop("search_auctions".into()))?;
+        auctions_from_response(res).map_err(|e| e.prop("search_auctions".into()))
+    }
+
+    pub async fn close_auction(&mut self, wfm_id: &str) -> Result<(), AppError> {
+        let req = RequestBuilder::new()
+            .method(Method::PUT)
+            .uri(format!("{}/auctions/entry/{}/close", self.endpoint, wfm_id).as_str())
+            .build();
+        let res = self
+            .send_request(req)
+            .await
+            .map_err(|e| e.prop("close_auction".into()))?;
+        // an auction that doesn't exist anymore doesn't need closing
+        if res.status.code >= 300 && res.status.code != 404 {
+            let err = StatusError { status: res.status };
+            return Err(AppError::new(err.to_string(), "close_auction".into()));
+        }
+        Ok(())
+    }
+}
+
+fn auctions_from_response(res: ApiResult) -> Result<Vec<WFMAuction>, AppError> {
+    if res.status.code >= 300 {
+        let err = StatusError { status: res.status };
+        return Err(AppError::new(err.to_string(), "auctions_from_response".into()));
+    }
+    let (body, _) = res.res;
+    let mut body = match body {
+        Some(v) => v,
+        None => {
+            return Err(AppError::new(
+                String::from("No response body associated with response"),
+                String::from("auctions_from_response"),
+            ))
+        }
+    };
+    from_value::<Vec<WFMAuction>>(body["payload"]["auctions"].take()).map_err(|e| {
+        AppError::new(
+            e.to_string(),
+            String::from("auctions_from_response: from_value::<Vec<WFMAuction>>"),
+        )
+    })
+}
+
+fn auction_from_response(res: ApiResult, oid: &str) -> Result<Auction, AppError> {
+    if res.status.code >= 300 {
+        let err = StatusError { status: res.status };
+        return Err(AppError::new(err.to_string(), "auction_from_response".into()));
+    }
+    let (body, _) = res.res;
+    let mut body = match body {
+        Some(v) => v,
+        None => {
+            return Err(AppError::new(
+                String::from("No response body associated with response"),
+                String::from("auction_from_response"),
+            ))
+        }
+    };
+    let mut auction = from_value::<Auction>(body["payload"]["auction"].take()).map_err(|e| {
+        AppError::new(
+            e.to_string(),
+            String::from("auction_from_response: from_value::<Auction>"),
+        )
+    })?;
+    auction.oid = oid.into();
+    Ok(auction)
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::sync::Arc;
+    use std::{sync::Arc, time::Duration};
 
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
     use tokio::sync::{broadcast, Mutex};
 
     use crate::{
-        block_in_place,
         http_client::{
             auth_state::AuthState,
-            client::{HttpClient, Method, RequestBuilder},
-            wfm_client::WFMClient,
+            cache::ResponseCache,
+            mock_transport::{MockTransport, Recorded},
+            secret_store::SecretKey,
+            wfm_client::{jwt_cookie, WFMClient},
         },
+        rate_limiter::{MockClock, RateLimiter},
     };
 
-    #[test]
-    fn test_wfmclient() {
-        let auth = AuthState::setup().unwrap();
-        let auth = Arc::new(Mutex::new(auth));
-        let (send_stop, _) = broadcast::channel(1);
-        let mut client = WFMClient::new(auth, send_stop.subscribe());
-        let _req = block_in_place!(async move {
-            let req = RequestBuilder::new()
-                .method(Method::GET)
-                .uri(format!("{}/profile/toopsi", client.endpoint).as_str())
-                .build();
-            client.send_request(req).await
+    fn token() -> String {
+        let claims = json!({
+            "sid": "guhh",
+            "exp": 100000000000i64,
+            "iat": 1719973822,
+            "iss": "jwt",
+            "aud": "jwt",
+            "auth_type": "cookie",
+            "secure": true,
+            "login_ua": "Rusty Rivens",
+            "login_ip": "numbershaha",
+            "jwt_identity": "hi",
         });
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(b"key")).unwrap()
+    }
+
+    fn client(
+        dir: &tempfile::TempDir,
+        mock: Arc<MockTransport>,
+        clock: Arc<MockClock>,
+    ) -> WFMClient {
+        let auth = AuthState::setup(
+            &dir.path().join("auth.json"),
+            SecretKey::KeyFile(dir.path().join("auth.key")),
+        )
+        .unwrap();
+        let (send_stop, _) = broadcast::channel(1);
+        WFMClient::new(Arc::new(Mutex::new(auth)), send_stop.subscribe())
+            .transport(mock)
+            .rate_limiter(RateLimiter::with_clock(
+                "https://api.warframe.market/v1",
+                1.0,
+                Duration::from_secs(1),
+                clock,
+            ))
+    }
+
+    #[tokio::test]
+    async fn test_wfmclient() {
+        let dir = tempfile::tempdir().unwrap();
+        let token = token();
+        let mock = MockTransport::from_json(json!([
+            {
+                "method": "POST",
+                "path": "/v1/auth/signin",
+                "headers": [["Set-Cookie", format!("JWT={token}; Domain=.warframe.market; Path=/")]],
+                "body": {"payload": {"user": {"ingame_name": "toopsi", "check_code": "c0de", "id": "1d"}}},
+            },
+            {
+                "method": "GET",
+                "path": "/v1/profile",
+                "body": {"profile": {"anonymous": false, "verification": true}},
+            },
+        ]));
+        let mut client = client(&dir, mock.clone(), MockClock::new());
+
+        let (status, id, check_code, ingame_name) =
+            client.login("tenno@example.com", "hunter2").await.unwrap();
+        assert_eq!(status.code, 200);
+        assert_eq!((&*id, &*check_code, &*ingame_name), ("1d", "c0de", "toopsi"));
+        assert_eq!(&*client.auth.lock().await.wfm_access_token, token.as_str());
+        assert!(client.validate().await.unwrap());
+
+        assert_eq!(
+            mock.requests(),
+            vec![
+                Recorded {
+                    method: "POST".into(),
+                    path: "/v1/auth/signin".into(),
+                    body: Some(json!({"email": "tenno@example.com", "password": "hunter2"})),
+                },
+                Recorded {
+                    method: "GET".into(),
+                    path: "/v1/profile".into(),
+                    body: None,
+                },
+            ]
+        );
+
+        // the session is stored for the next start
+        let stored = AuthState::setup(
+            &dir.path().join("auth.json"),
+            SecretKey::KeyFile(dir.path().join("auth.key")),
+        )
+        .unwrap();
+        assert_eq!(&*stored.ingame_name, "toopsi");
+    }
+
+    #[tokio::test]
+    async fn test_wfmclient_auctions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mock = MockTransport::from_json(json!([
+            {"method": "GET", "path": "/v1/auctions/search", "status": 429, "headers": [["Retry-After", "2"]]},
+            {"method": "GET", "path": "/v1/auctions/search", "body": {"payload": {"auctions": []}}},
+            {"method": "PUT", "path": "/v1/auctions/entry/gone/close", "status": 404},
+            {"method": "PUT", "path": "/v1/auctions/entry/broken/close", "status": 500},
+        ]));
+        let clock = MockClock::new();
+        let mut client = client(&dir, mock.clone(), clock.clone());
+
+        // rate limited once, then sent again when the server said to
+        assert!(client.search_auctions("soma").await.unwrap().is_empty());
+        assert_eq!(
+            mock.requests()[1].path,
+            "/v1/auctions/search?type=riven&weapon_url_name=soma&sort_by=price_asc"
+        );
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(2)]);
+        // unknown routes are a 404, which counts as closed
+        client.close_auction("gone").await.unwrap();
+        assert!(client.close_auction("broken").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wfmclient_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let mock = MockTransport::from_json(json!([
+            {
+                "method": "GET",
+                "path": "/v1/auctions/search",
+                "headers": [["ETag", "\"v1\""]],
+                "body": {"payload": {"auctions": []}},
+            },
+            {"method": "GET", "path": "/v1/auctions/search", "status": 304},
+        ]));
+        let search = "https://api.warframe.market/v1/auctions/search";
+        let cache = |ttl| {
+            let cache = ResponseCache::open(dir.path().join("cache.sqlite3")).unwrap();
+            Some(Arc::new(cache.route(search, ttl)))
+        };
+        let mut client =
+            client(&dir, mock.clone(), MockClock::new()).response_cache(cache(Duration::ZERO));
+
+        // the 304 comes without a body, the stored one is used instead
+        assert!(client.search_auctions("soma").await.unwrap().is_empty());
+        assert!(client.search_auctions("soma").await.unwrap().is_empty());
+        assert_eq!(mock.requests().len(), 2);
+
+        // fresh responses don't go out at all
+        let mut client = client.response_cache(cache(Duration::from_secs(3600)));
+        assert!(client.search_auctions("soma").await.unwrap().is_empty());
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_jwt_cookie() {
+        assert_eq!(
+            jwt_cookie("JWT=abc.def.ghi; Domain=.warframe.market; Path=/").as_deref(),
+            Some("abc.def.ghi")
+        );
+        assert_eq!(jwt_cookie("JWT=abc").as_deref(), Some("abc"));
+        assert_eq!(jwt_cookie("JWT=; Path=/"), None);
+        assert_eq!(jwt_cookie("session=abc; Path=/"), None);
     }
 }
@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use ascii::AsciiString;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tiny_http::{Method, Request, StatusCode};
+use tokio::sync::Mutex;
+
+use crate::{
+    block_in_place,
+    rivens::{
+        inventory::{
+            convert_raw_inventory::Item,
+            database::{
+                database::{Auction, InventoryDB},
+                inventory_sync::SYNC_STATUS,
+            },
+        },
+        reroll::grade_riven,
+    },
+    server::riven_lookup,
+    AppError,
+};
+
+// an inventory riven with everything the gui shows next to it
+#[derive(Clone, Debug, Serialize)]
+pub struct RivenEntry {
+    #[serde(flatten)]
+    pub item: Item,
+    pub auction: Option<Auction>,
+    pub blacklisted: bool,
+    pub grade: Option<&'static str>,
+}
+
+// query parameters of `/api/v1/rivens`, every one that's set has to match
+#[derive(Debug, Default, Deserialize)]
+pub struct RivenFilter {
+    pub weapon: Option<String>,
+    pub stat: Option<String>,
+    pub grade: Option<String>,
+    pub listed: Option<bool>,
+    pub blacklisted: Option<bool>,
+    pub min_re_rolls: Option<i32>,
+    pub max_re_rolls: Option<i32>,
+}
+
+impl RivenFilter {
+    pub fn matches(&self, entry: &RivenEntry) -> bool {
+        let item = &entry.item;
+        self.weapon.as_ref().is_none_or(|w| item.weapon_url_name.as_ref() == w)
+            && self.stat.as_ref().is_none_or(|s| {
+                item.attributes.iter().any(|attr| &attr.url_name == s)
+            })
+            && self.grade.as_ref().is_none_or(|g| entry.grade == Some(g.as_str()))
+            && self.listed.is_none_or(|l| entry.auction.is_some() == l)
+            && self.blacklisted.is_none_or(|b| entry.blacklisted == b)
+            && self.min_re_rolls.is_none_or(|n| item.re_rolls >= n)
+            && self.max_re_rolls.is_none_or(|n| item.re_rolls <= n)
+    }
+}
+
+fn respond_json<T: Serialize>(rq: Request, status: u16, value: &T) -> Result<(), AppError> {
+    let body = serde_json::to_string(value)
+        .map_err(|e| AppError::new(e.to_string(), "respond_json: to_string".into()))?;
+    rq.respond(
+        tiny_http::Response::from_string(body)
+            .with_header(tiny_http::Header {
+                field: "Content-Type".parse().unwrap(),
+                value: AsciiString::from_ascii("application/json").unwrap(),
+            })
+            .with_status_code(StatusCode(status)),
+    )
+    .map_err(|e| AppError::new(e.to_string(), "respond_json: respond".into()))
+}
+
+fn respond_error(rq: Request, status: u16, err: &str) -> Result<(), AppError> {
+    respond_json(rq, status, &json!({ "error": err }))
+}
+
+async fn select_entries(db: Arc<Mutex<Option<InventoryDB>>>) -> Result<Vec<RivenEntry>, AppError> {
+    let db = db.lock().await;
+    let db = match db.as_ref() {
+        Some(v) => v,
+        None => return Err(AppError::new("Database is closed".into(), "select_entries".into())),
+    };
+    let map_err = |e: rusqlite::Error| AppError::new(e.to_string(), "select_entries".into());
+    let items = db.select_items().map_err(map_err)?;
+    let mut auctions = db.select_auctions().map_err(map_err)?;
+    let blacklist = db.select_blacklist().map_err(map_err)?;
+    let rules = db.select_roll_rules().map_err(map_err)?;
+
+    Ok(items
+        .into_iter()
+        .map(|item| {
+            let auction = auctions
+                .iter()
+                .position(|auction| *auction.oid == *item.oid)
+                .map(|i| auctions.swap_remove(i));
+            RivenEntry {
+                blacklisted: blacklist.contains(&item.oid),
+                grade: grade_riven(&item, &rules).map(|grade| grade.as_str()),
+                auction,
+                item,
+            }
+        })
+        .collect())
+}
+
+async fn select_auctions(db: Arc<Mutex<Option<InventoryDB>>>) -> Result<Vec<Auction>, AppError> {
+    let db = db.lock().await;
+    let db = match db.as_ref() {
+        Some(v) => v,
+        None => return Err(AppError::new("Database is closed".into(), "select_auctions".into())),
+    };
+    db.select_auctions()
+        .map_err(|e| AppError::new(e.to_string(), "select_auctions".into()))
+}
+
+async fn select_blacklist(db: Arc<Mutex<Option<InventoryDB>>>) -> Result<Vec<Arc<str>>, AppError> {
+    let db = db.lock().await;
+    let db = match db.as_ref() {
+        Some(v) => v,
+        None => return Err(AppError::new("Database is closed".into(), "select_blacklist".into())),
+    };
+    db.select_blacklist()
+        .map_err(|e| AppError::new(e.to_string(), "select_blacklist".into()))
+}
+
+fn uri_rivens(
+    rq: Request,
+    oid: Option<&str>,
+    query: &str,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let filter = match serde_urlencoded::from_str::<RivenFilter>(query) {
+        Ok(v) => v,
+        Err(e) => return respond_error(rq, 400, &e.to_string()),
+    };
+    let entries = block_in_place!(select_entries(db)).map_err(|e| e.prop("uri_rivens".into()))?;
+    match oid {
+        Some(oid) => match entries.iter().find(|entry| &*entry.item.oid == oid) {
+            Some(entry) => respond_json(rq, 200, entry),
+            None => respond_error(rq, 404, "riven not found"),
+        },
+        None => {
+            let entries: Vec<&RivenEntry> =
+                entries.iter().filter(|entry| filter.matches(entry)).collect();
+            respond_json(rq, 200, &entries)
+        }
+    }
+}
+
+fn uri_auctions(
+    rq: Request,
+    oid: Option<&str>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let auctions =
+        block_in_place!(select_auctions(db)).map_err(|e| e.prop("uri_auctions".into()))?;
+    match oid {
+        Some(oid) => match auctions.iter().find(|auction| auction.oid == oid) {
+            Some(auction) => respond_json(rq, 200, auction),
+            None => respond_error(rq, 404, "auction not found"),
+        },
+        None => respond_json(rq, 200, &auctions),
+    }
+}
+
+fn uri_lookup(rq: Request, what: Option<&str>) -> Result<(), AppError> {
+    let lookup = match riven_lookup() {
+        Some(v) => v,
+        None => return respond_error(rq, 503, "riven data isn't loaded yet"),
+    };
+    match what {
+        None => respond_json(rq, 200, &*lookup),
+        Some("weapons") => respond_json(rq, 200, &lookup.weapons),
+        Some("attributes") => respond_json(rq, 200, &lookup.available_attributes),
+        Some(_) => respond_error(rq, 404, "unknown lookup data"),
+    }
+}
+
+// `/api/v1/...`, read only json versions of what the htmx pages show
+pub fn uri_api_v1(
+    rq: Request,
+    uri: &str,
+    query: &str,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    if *rq.method() != Method::Get {
+        return respond_error(rq, 405, "only GET is supported");
+    }
+    let mut parts = uri.split('/').filter(|part| !part.is_empty());
+    let (root, id) = (parts.next(), parts.next());
+    if parts.next().is_some() {
+        return respond_error(rq, 404, "not found");
+    }
+    match root {
+        Some("rivens") => uri_rivens(rq, id, query, db).map_err(|e| e.prop("uri_api_v1".into())),
+        Some("auctions") => uri_auctions(rq, id, db).map_err(|e| e.prop("uri_api_v1".into())),
+        Some("blacklist") if id.is_none() => {
+            let blacklist = block_in_place!(select_blacklist(db))
+                .map_err(|e| e.prop("uri_api_v1".into()))?;
+            respond_json(rq, 200, &blacklist)
+        }
+        Some("sync") if id.is_none() => {
+            let status = match SYNC_STATUS.lock() {
+                Ok(v) => v.clone(),
+                Err(e) => e.into_inner().clone(),
+            };
+            respond_json(rq, 200, &status)
+        }
+        Some("lookup") => uri_lookup(rq, id).map_err(|e| e.prop("uri_api_v1".into())),
+        _ => respond_error(rq, 404, "not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rivens::inventory::{
+        convert_raw_inventory::{Attribute, Item, Units},
+        database::database::Auction,
+    };
+
+    use super::{RivenEntry, RivenFilter};
+
+    #[test]
+    fn test_riven_filter() {
+        let entry = RivenEntry {
+            item: Item {
+                weapon_url_name: "skana".into(),
+                re_rolls: 3,
+                attributes: vec![Attribute {
+                    value: 100.0,
+                    positive: true,
+                    url_name: "critical_chance".into(),
+                    units: Units::Percent,
+                    short_string: "CC".into(),
+                    roll: None,
+                }],
+                ..Default::default()
+            },
+            auction: Some(Auction::default()),
+            blacklisted: false,
+            grade: Some("god_roll"),
+        };
+        let filter = |query: &str| serde_urlencoded::from_str::<RivenFilter>(query).unwrap();
+
+        assert!(filter("").matches(&entry));
+        assert!(filter("weapon=skana&stat=critical_chance&listed=true").matches(&entry));
+        assert!(filter("grade=god_roll&min_re_rolls=3&max_re_rolls=3").matches(&entry));
+        assert!(!filter("weapon=braton").matches(&entry));
+        assert!(!filter("stat=multishot").matches(&entry));
+        assert!(!filter("listed=false").matches(&entry));
+        assert!(!filter("blacklisted=true").matches(&entry));
+        assert!(!filter("max_re_rolls=2").matches(&entry));
+        assert!(serde_urlencoded::from_str::<RivenFilter>("listed=maybe").is_err());
+    }
+}
@@ -1,7 +1,10 @@
-use std::rc::Rc;
+use std::{rc::Rc, time::Duration};
 
-use jsonwebtoken::{decode, errors::ErrorKind, Algorithm, DecodingKey, TokenData, Validation};
+use jsonwebtoken::{
+    decode, decode_header, errors::ErrorKind, Algorithm, DecodingKey, TokenData, Validation,
+};
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 
 use crate::AppError;
 
@@ -20,6 +23,35 @@ struct Claims {
     jwt_identity: Rc<str>,
 }
 
+// when a token was issued and when it runs out, in unix seconds
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct TokenLifetime {
+    #[serde(default)]
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl TokenLifetime {
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        now + margin.as_secs() as i64 >= self.exp
+    }
+}
+
+// reads `exp`/`iat` without checking anything else, so it works for expired
+// tokens and ones from other issuers. `None` when it isn't a jwt with an `exp`
+pub fn token_lifetime(token: &str) -> Option<TokenLifetime> {
+    let header = decode_header(token).ok()?;
+    let mut validation = Validation::new(header.alg);
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.set_required_spec_claims(&["exp"]);
+    decode::<TokenLifetime>(token, &DecodingKey::from_secret(&[]), &validation)
+        .ok()
+        .map(|data| data.claims)
+}
+
 pub fn jwt_is_valid(jwt: &str) -> Result<bool, AppError> {
     match validate_jwt(jwt, None) {
         Ok(_) => Ok(true),
@@ -55,10 +87,12 @@ fn validate_jwt(
 #[cfg(test)]
 mod tests {
 
+    use std::time::Duration;
+
     use time::OffsetDateTime;
     use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 
-    use crate::jwt::{validate_jwt, Claims};
+    use crate::jwt::{token_lifetime, validate_jwt, Claims, TokenLifetime};
 
     // fn stress_test() -> io::Result<()> {
     //     let now_tot = SystemTime::now();
@@ -154,4 +188,35 @@ mod tests {
         assert!(jwt_expired.is_err());
         jwt_expired.unwrap();
     }
+
+    #[test]
+    fn test_token_lifetime() {
+        let key = b"oooo sppokyu key fkjsdn";
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let claims = Claims {
+            sid: "guhh".into(),
+            exp: now + 60,
+            iat: now,
+            iss: "jwt".into(),
+            aud: "jwt".into(),
+            auth_type: "coookie".into(),
+            secure: true,
+            login_ua: "Rusty Rivens".into(),
+            login_ip: "numbershaha".into(),
+            jwt_identity: "hi".into(),
+        };
+        let token = encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(key)).unwrap();
+        let lifetime = token_lifetime(&token).unwrap();
+        assert_eq!(lifetime, TokenLifetime { iat: now, exp: now + 60 });
+        assert!(!lifetime.expires_within(Duration::from_secs(30)));
+        assert!(lifetime.expires_within(Duration::from_secs(90)));
+
+        // expired tokens still have a lifetime
+        let expired = Claims { exp: now - 60, ..claims };
+        let token = encode(&Header::new(Algorithm::HS256), &expired, &EncodingKey::from_secret(key)).unwrap();
+        assert!(token_lifetime(&token).unwrap().expires_within(Duration::ZERO));
+
+        assert_eq!(token_lifetime("not a jwt"), None);
+        assert_eq!(token_lifetime(""), None);
+    }
 }
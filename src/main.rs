@@ -1,9 +1,12 @@
-use std::{error::Error, fmt::{self, Display}, panic, process, sync::Arc, thread, time::Duration};
+use std::{env, error::Error, fmt::{self, Display}, panic, process, sync::Arc, thread, time::Duration};
 
 
 use once_cell::sync::OnceCell;
 use serde::Deserialize;
+use config::Config;
+use dotenv::dotenv;
 use server::start_server;
+use session::Session;
 use tao::{
     event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
@@ -15,12 +18,17 @@ use wry::WebViewBuilder;
 mod pages;
 mod resources;
 mod server;
+mod session;
 mod rate_limiter;
 mod jwt;
 mod file_consts;
 mod rivens;
 mod riven_data_store;
 mod api_operations;
+mod cli;
+mod config;
+mod file_watcher;
+mod json_api;
 mod websocket;
 mod http_client;
 
@@ -70,8 +78,38 @@ static STOP_SENDER: OnceCell<broadcast::Sender<StopSignal>> = OnceCell::new();
 
 #[tokio::main]
 async fn main() -> wry::Result<()> {
+    // `.env` is optional now, anything in it overrides the config file
+    let _ = dotenv();
+    let (config, args) = match Config::load(env::args().skip(1).collect()) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("ERROR: Could not load config: {e}");
+            process::exit(1)
+        }
+    };
+    let config = Arc::new(config);
+
+    // any arguments left run the headless cli instead of the gui
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args, config).await {
+            eprintln!("ERROR: {e}");
+            process::exit(1)
+        }
+        return Ok(());
+    }
+
+    let session = Arc::new(Session::new());
+    if config.print_start_url {
+        eprintln!(
+            "INFO: Open {} to use the ui from a browser as well",
+            session.start_url(&config.server.url())
+        );
+    } else {
+        eprintln!("INFO: Run with --print-url to use the ui from a browser as well");
+    }
+
     let (stop_sender, _) = broadcast::channel::<StopSignal>(1);
-    tokio::task::spawn(start_server(stop_sender.subscribe()));
+    tokio::task::spawn(start_server(stop_sender.subscribe(), config.clone(), session.clone()));
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
@@ -105,17 +143,17 @@ async fn main() -> wry::Result<()> {
     };
 
     let _webview = builder
-        .with_url("http://127.0.0.1:8000")
+        .with_url(session.start_url(&config.server.url()))
         .with_drag_drop_handler(|e| {
             match e {
                 wry::DragDropEvent::Enter { paths, position } => {
-                    println!("DragEnter: {position:?} {paths:?} ")
+                    eprintln!("DragEnter: {position:?} {paths:?} ")
                 }
-                wry::DragDropEvent::Over { position } => println!("DragOver: {position:?} "),
+                wry::DragDropEvent::Over { position } => eprintln!("DragOver: {position:?} "),
                 wry::DragDropEvent::Drop { paths, position } => {
-                    println!("DragDrop: {position:?} {paths:?} ")
+                    eprintln!("DragDrop: {position:?} {paths:?} ")
                 }
-                wry::DragDropEvent::Leave => println!("DragLeave"),
+                wry::DragDropEvent::Leave => eprintln!("DragLeave"),
                 _ => {}
             }
 
@@ -133,7 +171,7 @@ async fn main() -> wry::Result<()> {
         } = event
         {
             stop_sender.send(StopSignal).unwrap();
-            println!("stop signal sent");
+            eprintln!("stop signal sent");
             *control_flow = ControlFlow::Exit
         }
     });
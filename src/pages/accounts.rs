@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use ascii::AsciiString;
+use maud::html;
+use tiny_http::Request;
+use tokio::sync::Mutex;
+
+use crate::{block_in_place, http_client::auth_state::AuthState, AppError};
+
+pub fn uri_accounts(rq: Request, auth: Arc<Mutex<AuthState>>) -> Result<(), AppError> {
+    let (current, profiles) = block_in_place!(async {
+        let auth = auth.lock().await;
+        (auth.ingame_name.clone(), auth.profiles())
+    });
+    let profiles = profiles.map_err(|e| e.prop("uri_accounts".into()))?;
+
+    let pagecontent = html! {
+        div id="screen" style="justify-content: center;" {
+            div class="row" {
+                button class="cellbutton" hx-get="/home" hx-target="#screen" hx-swap="outerHTML" {"Back"}
+                button class="cellbutton" hx-post="/api/add_account" {"Add Account"}
+                button
+                    class="cellbutton"
+                    hx-post="/api/logout"
+                    hx-confirm=(format!("Log out of {current} and forget it?")) {"Log Out"}
+            }
+            div id="accounts-table" class="row" {
+                @for name in &profiles {
+                    div class="cell" {
+                        div class="celltitle" {(name)}
+                        div class="cellfooterdiv" {
+                            @if *name == current {
+                                b {"Logged in"}
+                            } @else {
+                                button
+                                    class="cellbutton"
+                                    hx-post=(format!("/api/switch_account/{name}")) {"Switch"}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    rq.respond(
+        tiny_http::Response::from_string(pagecontent.into_string()).with_header(
+            tiny_http::Header {
+                field: "Content-Type".parse().unwrap(),
+                value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
+            },
+        ),
+    )
+    .map_err(|e| AppError::new(e.to_string(), "uri_accounts".to_string()))
+}
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use ascii::AsciiString;
+use maud::html;
+use tiny_http::Request;
+use tokio::sync::Mutex;
+
+use crate::{
+    block_in_place,
+    rivens::inventory::{convert_raw_inventory::Item, database::database::InventoryDB},
+    websocket::construct_stats,
+    AppError,
+};
+
+async fn select_blacklisted_items(
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<Vec<Item>, AppError> {
+    let db = db.lock().await;
+    let db = match db.as_ref() {
+        Some(v) => v,
+        None => {
+            return Err(AppError::new(
+                "Database is closed".into(),
+                "select_blacklisted_items".into(),
+            ))
+        }
+    };
+    let blacklist = db.select_blacklist().map_err(|e| {
+        AppError::new(e.to_string(), "select_blacklisted_items: select_blacklist".into())
+    })?;
+    blacklist.iter().try_fold(vec![], |mut acc, oid| {
+        let item = db.select_item(oid).map_err(|e| {
+            AppError::new(e.to_string(), "select_blacklisted_items: select_item".into())
+        })?;
+        if let Some(item) = item {
+            acc.push(item);
+        }
+        Ok(acc)
+    })
+}
+
+pub fn uri_blacklist(rq: Request, db: Arc<Mutex<Option<InventoryDB>>>) -> Result<(), AppError> {
+    let items = block_in_place!(select_blacklisted_items(db))
+        .map_err(|e| e.prop("uri_blacklist".into()))?;
+
+    let pagecontent = html! {
+        div id="screen" style="justify-content: center;" {
+            div class="row" {
+                button class="cellbutton" hx-get="/home" hx-target="#screen" hx-swap="outerHTML" {"Back"}
+            }
+            div id="blacklist-table" class="row" {
+                @for item in &items {
+                    div class="cell" id=(format!("a{}", item.oid)) {
+                        div class="celltitle" {
+                            (format!("{} {}", item.weapon_name, item.name))
+                        }
+                        hr style="width: 100%";
+                        div style="flex-grow: 1"{
+                            (construct_stats(&item.attributes))
+                        }
+                        div class="cellfooterdiv" {
+                            div style="float: left;" {
+                                button
+                                    class="cellbutton"
+                                    hx-delete=(format!("/api/unblacklist_riven/{}", item.oid))
+                                    hx-target=(format!("#a{}", item.oid))
+                                    hx-swap="outerHTML" {"Unblacklist"}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    rq.respond(
+        tiny_http::Response::from_string(pagecontent.into_string()).with_header(
+            tiny_http::Header {
+                field: "Content-Type".parse().unwrap(),
+                value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
+            },
+        ),
+    )
+    .map_err(|e| AppError::new(e.to_string(), "uri_blacklist".to_string()))
+}
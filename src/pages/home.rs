@@ -2,17 +2,25 @@ use ascii::AsciiString;
 use maud::{html, PreEscaped, DOCTYPE};
 use std::{
     io::{self},
-    ops::DerefMut,
     sync::Arc,
 };
 use tiny_http::{Request, Response, StatusCode};
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 
 use crate::{
     block_in_place,
+    config::Config,
     http_client::{qf_client::QFClient, wfm_client::WFMClient},
-    rivens::inventory::riven_lookop::RivenDataLookup,
-    server::RIVEN_LOOKUP,
+    rivens::{
+        inventory::{
+            convert_raw_inventory::Item,
+            database::database::{Auction, InventoryDB, PriceSuggestion},
+            riven_lookop::RivenDataLookup,
+        },
+        price_suggestion::get_price_suggestion,
+    },
+    server::{riven_lookup, set_riven_lookup},
+    session::Session,
     AppError,
 };
 
@@ -20,57 +28,44 @@ pub fn uri_main(
     rq: Request,
     wfm: Arc<Mutex<WFMClient>>,
     qf: Arc<Mutex<QFClient>>,
-    logged_in: &mut Option<bool>,
+    config: &Config,
+    session: &Session,
+    logged_in: &watch::Sender<Option<bool>>,
 ) -> Result<(), AppError> {
-    let pagecontent = if logged_in.is_some() {
-        html! {
-            (DOCTYPE)
-            head {
-                (PreEscaped("<script src=\"/htmx.min.js\"></script>"))
-                (PreEscaped("<link rel=\"stylesheet\" href=\"/styles.css\" />"))
-                (PreEscaped("<script src=\"https://unpkg.com/htmx.org@1.9.12/dist/ext/ws.js\"></script>"))
-                body {
-                    div hx-get="/home" hx-swap="outerHTML" hx-trigger="load";
-                };
-            }
-        }
-    } else {
-        let valid = block_in_place!(async move {
-            let mut wfm = wfm.lock().await;
-            let wfm = wfm.deref_mut();
-            wfm.validate().await
-        })
-        .map_err(|e| e.prop("uri_main".into()))?;
-        if valid {
-            *logged_in = Some(true);
-            let lookup = block_in_place!(async { RivenDataLookup::setup(qf).await })
-                .expect("FATAL: Could not retrieve riven lookup data");
-            RIVEN_LOOKUP
-                .set(lookup)
-                .expect("FATAL: Could not store riven lookup data in memory");
-            html! {
-                (DOCTYPE)
-                head {
-                    (PreEscaped("<script src=\"/htmx.min.js\"></script>"))
-                    (PreEscaped("<link rel=\"stylesheet\" href=\"/styles.css\" />"))
-                    (PreEscaped("<script src=\"https://unpkg.com/htmx.org@1.9.12/dist/ext/ws.js\"></script>"))
-                    body {
-                        div hx-get="/home" hx-swap="outerHTML" hx-trigger="load";
-                    };
-                }
-            }
-        } else {
-            html! {
-                (DOCTYPE)
-                head {
-                    (PreEscaped("<script src=\"/htmx.min.js\"></script>"))
-                    (PreEscaped("<link rel=\"stylesheet\" href=\"/styles.css\" />"))
-                    (PreEscaped("<script src=\"https://unpkg.com/htmx.org@1.9.12/dist/ext/ws.js\"></script>"))
-                    body {
-                        div hx-get="/login" hx-swap="outerHTML" hx-trigger="load";
-                    };
+    let known = *logged_in.borrow();
+    let valid = match known {
+        Some(v) => v,
+        None => {
+            let valid = block_in_place!(async move {
+                let mut wfm = wfm.lock().await.clone();
+                wfm.validate().await
+            })
+            .map_err(|e| e.prop("uri_main".into()))?;
+            if valid {
+                // another request might have loaded it already
+                if riven_lookup().is_none() {
+                    let lookup = block_in_place!(async { RivenDataLookup::setup(qf, &config.paths.riven_lookup).await })
+                        .map_err(|e| e.prop("uri_main".into()))?;
+                    set_riven_lookup(lookup);
                 }
+                // the lookup data has to be there before anything waiting on this
+                // starts syncing
+                logged_in.send_replace(Some(true));
             }
+            valid
+        }
+    };
+    let screen = if valid { "/home" } else { "/login" };
+    let pagecontent = html! {
+        (DOCTYPE)
+        head {
+            (PreEscaped("<script src=\"/htmx.min.js\"></script>"))
+            (PreEscaped("<link rel=\"stylesheet\" href=\"/styles.css\" />"))
+            (PreEscaped("<script src=\"https://unpkg.com/htmx.org@1.9.12/dist/ext/ws.js\"></script>"))
+            body hx-headers=(session.csrf_hx_headers()) {
+                div hx-get=(screen) hx-swap="outerHTML" hx-trigger="load";
+                div id="session-status" hx-get="/session_status" hx-trigger="every 30s, LoginSuccess from:body";
+            };
         }
     };
 
@@ -85,10 +80,22 @@ pub fn uri_main(
     .map_err(|e| AppError::new(e.to_string(), "uri_main".to_string()))
 }
 
-pub fn uri_home(rq: Request) -> io::Result<()> {
+pub fn uri_home(rq: Request, config: &Config) -> io::Result<()> {
     let pagecontent = html! {
     div id="screen" style="justify-content: center;" {
-        div hx-ext="ws" ws-connect="ws://localhost:8069"
+        div class="row" {
+            button class="cellbutton" hx-get="/blacklist" hx-target="#screen" hx-swap="outerHTML" {"Blacklist"}
+            button class="cellbutton" hx-get="/reroll" hx-target="#screen" hx-swap="outerHTML" {"Roll Rules"}
+            button class="cellbutton" hx-get="/accounts" hx-target="#screen" hx-swap="outerHTML" {"Accounts"}
+            button
+                class="cellbutton"
+                hx-post="/bulk_edit_open"
+                hx-include=".riven-select:checked"
+                hx-target="#screen"
+                hx-swap="beforeend" {"Bulk Edit"}
+        }
+        div id="sync-error" {}
+        div hx-ext="ws" ws-connect=(config.server.websocket_url())
             div id="riven-table" class="row" {
             }
         }
@@ -107,8 +114,53 @@ pub fn uri_edit_cancel(rq: Request) -> io::Result<()> {
     rq.respond(Response::empty(200))
 }
 
-pub fn uri_edit_open(rq: Request, oid: &str) -> io::Result<()> {
-    let title = format!("Edit <RIVENNAME>");
+async fn select_edit_data(
+    oid: &str,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(Item, Option<Auction>, Option<PriceSuggestion>), AppError> {
+    let (item, auction) = {
+        let db = db.lock().await;
+        let db = match db.as_ref() {
+            Some(v) => v,
+            None => return Err(AppError::new("Database is closed".into(), "select_edit_data".into())),
+        };
+        let item = db
+            .select_item(oid)
+            .map_err(|e| AppError::new(e.to_string(), "select_edit_data: select_item".into()))?
+            .ok_or_else(|| AppError::new(format!("No riven with id: {oid}"), "select_edit_data".into()))?;
+        let auction = db
+            .select_auction(oid)
+            .map_err(|e| AppError::new(e.to_string(), "select_edit_data: select_auction".into()))?;
+        (item, auction)
+    };
+    // the dialog still opens when warframe.market can't be reached
+    let suggestion = match get_price_suggestion(&item, wfm, db).await {
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!("WARNING: Could not get a price suggestion: {}", e.prop("select_edit_data".into()));
+            None
+        }
+    };
+    Ok((item, auction, suggestion))
+}
+
+pub fn uri_edit_open(
+    rq: Request,
+    oid: &str,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let (item, auction, suggestion) = block_in_place!(select_edit_data(oid, wfm, db))
+        .map_err(|e| e.prop("uri_edit_open".into()))?;
+    let suggestion = suggestion.filter(|s| s.samples > 0);
+
+    let title = format!("Edit {} {}", item.weapon_name, item.name);
+    let price = auction
+        .and_then(|auc| auc.buyout_price)
+        .or(suggestion.as_ref().map(|s| s.median))
+        .unwrap_or(10)
+        .max(10);
     let post_url = format!("/api/update_single_riven/{oid}");
     let blacklist_url = format!("/api/blacklist_riven/{oid}");
 
@@ -130,9 +182,17 @@ pub fn uri_edit_open(rq: Request, oid: &str) -> io::Result<()> {
                                     type="number"
                                     min="10"
                                     max="100000"
-                                    value="10"
+                                    value=(price)
                                     name="price";
                             }
+                            @if let Some(suggestion) = &suggestion {
+                                div style="padding-top: 5px; padding-left: 13px; font-size: 0.8em;" {
+                                    (format!(
+                                        "Suggested: {}p / {}p / {}p ({} auctions)",
+                                        suggestion.low, suggestion.median, suggestion.high, suggestion.samples
+                                    ))
+                                }
+                            }
                             div style="display: flex; flex-wrap: wrap; padding-top: 15px" {
                                 label for="visible-toggle" style="padding-right: 13px; padding-left: 13px;" {"Visible"}
                                 label class="switch" {
@@ -162,7 +222,89 @@ pub fn uri_edit_open(rq: Request, oid: &str) -> io::Result<()> {
                                 {"Save"}
 
                             button class="cellbutton" hx-delete="/edit_cancel" hx-target="#edit_screen" hx-swap="outerHTML swap:.08s" {"Cancel"}
-                            button class="cellbutton" hx-delete=(blacklist_url) hx-target="#edit_screen" style="float: right; margin-right: 13px" {"Blacklist"}
+                            button class="cellbutton" hx-delete=(blacklist_url) hx-target="#edit_screen" hx-swap="outerHTML swap:.08s" style="float: right; margin-right: 13px" {"Blacklist"}
+                        }
+                    }
+                }
+            }
+        }
+    };
+    rq.respond(
+        tiny_http::Response::from_string(pagecontent.into_string()).with_header(
+            tiny_http::Header {
+                field: "Content-Type".parse().unwrap(),
+                value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
+            },
+        ),
+    )
+    .map_err(|e| AppError::new(e.to_string(), "uri_edit_open".into()))
+}
+
+pub fn uri_bulk_edit_open(rq: Request, body: Option<&str>) -> io::Result<()> {
+    let oids: Vec<String> = serde_urlencoded::from_str::<Vec<(String, String)>>(body.unwrap_or(""))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(key, _)| key == "oid")
+        .map(|(_, oid)| oid)
+        .collect();
+    if oids.is_empty() {
+        return rq.respond(Response::empty(200));
+    }
+    let title = format!("Edit {} Rivens", oids.len());
+
+    let pagecontent = html! {
+        div id="edit_screen" style="display: block;" {
+            div class="row_overlay" {
+                div id="edit_screen_gui" {
+                    form hx-post="/api/update_mult_riven" hx-target="#edit_screen_gui" hx-swap="innerHTML" {
+                        @for oid in &oids {
+                            input type="hidden" name="oid" value=(oid);
+                        }
+                        div style="flex-grow: 1;" {
+                            div class="celltitle" {
+                                (title)
+                            }
+                            hr {}
+                            div {
+                                label for="price-input" style="padding-right: 13px; padding-left: 13px;" {"Price"}
+                                input
+                                    id="price-input"
+                                    style="font-size: 0.8em;"
+                                    type="number"
+                                    min="10"
+                                    max="100000"
+                                    placeholder="Current"
+                                    name="price";
+                            }
+                            div style="display: flex; flex-wrap: wrap; padding-top: 15px" {
+                                label for="visible-toggle" style="padding-right: 13px; padding-left: 13px;" {"Visible"}
+                                label class="switch" {
+                                    input
+                                        id="visible-toggle"
+                                        type="checkbox"
+                                        checked
+                                        name="visible";
+                                    span class="slider";
+                                }
+                            }
+                            div style="display: flex; flex-direction: column;" {
+                                textarea
+                                    type="text"
+                                    name="description"
+                                    placeholder="Description (Optional, {weapon} and {name} are filled in per riven)"
+                                    rows="4"
+                                    resize="none"
+                                    maxlength="200" {""}
+                            }
+                        }
+                        div style="padding-bottom: 13px;" {
+                            button
+                                class="cellbutton"
+                                type="submit"
+                                style="background-color: #7bdaff;"
+                                {"Save All"}
+
+                            button class="cellbutton" hx-delete="/edit_cancel" hx-target="#edit_screen" hx-swap="outerHTML swap:.08s" {"Cancel"}
                         }
                     }
                 }
@@ -189,6 +331,26 @@ pub fn uri_unauthorized(rq: Request) -> io::Result<()> {
         }
     };
 
+    rq.respond(
+        tiny_http::Response::from_string(pagecontent.into_string())
+            .with_header(tiny_http::Header {
+                field: "Content-Type".parse().unwrap(),
+                value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
+            })
+            .with_status_code(StatusCode(401)),
+    )
+}
+
+pub fn uri_forbidden(rq: Request) -> io::Result<()> {
+    let pagecontent = html! {
+        (DOCTYPE)
+        body {
+            h2 {
+                "403 Forbidden"
+            }
+        }
+    };
+
     rq.respond(
         tiny_http::Response::from_string(pagecontent.into_string())
             .with_header(tiny_http::Header {
@@ -1,9 +1,69 @@
-use std::io::{self};
+use std::{
+    io::{self},
+    sync::Arc,
+};
 
 use ascii::AsciiString;
-use maud::html;
-use tiny_http::Request;
+use maud::{html, Markup};
+use tiny_http::{Request, Response};
+use tokio::sync::{watch, Mutex};
 
+use crate::{
+    block_in_place, http_client::wfm_client::WFMClient, pages::home::uri_unauthorized,
+    session::Session, AppError,
+};
+
+// exchanges the token from the start url for the session cookie
+pub fn uri_session(rq: Request, query: &str, session: &Session) -> io::Result<()> {
+    let token = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "token")
+        .map(|(_, value)| value);
+    if !token.is_some_and(|token| session.check_token(token)) {
+        return uri_unauthorized(rq);
+    }
+    rq.respond(
+        Response::empty(303)
+            .with_header(tiny_http::Header {
+                field: "Set-Cookie".parse().unwrap(),
+                value: AsciiString::from_ascii(session.set_cookie()).unwrap(),
+            })
+            .with_header(tiny_http::Header {
+                field: "Location".parse().unwrap(),
+                value: AsciiString::from_ascii("/").unwrap(),
+            }),
+    )
+}
+
+// shared by the login page and the prompt shown when the session runs out,
+// `/api/login` triggers `LoginSuccess` for both to move on
+fn login_form() -> Markup {
+    html! {
+        div class="container" {
+            form hx-put="/api/login" hx-target="#login_failed" {
+                div class="row" {
+                    input
+                        id="email-input"
+                        type="email"
+                        name="email"
+                        placeholder="Email";
+                }
+                div class="row" {
+                    input
+                        id="password-input"
+                        type="password"
+                        name="password"
+                        placeholder="Password";
+                }
+                div class="row" {
+                    button type="submit" {"Login"}
+                }
+            }
+            p id="login_failed" style="text-align: center; color: red;" {b {""}}
+        }
+    }
+}
 
 pub fn uri_login(rq: Request) -> io::Result<()> {
     let pagecontent = html! {
@@ -11,32 +71,40 @@ pub fn uri_login(rq: Request) -> io::Result<()> {
             div class="row" {
                 img src="/logo.svg" class="logo";
             }
-            div class="container" {
-                form hx-put="/api/login" hx-target="#login_failed" {
-                    div class="row" {
-                        input
-                            id="email-input"
-                            type="email"
-                            name="email"
-                            placeholder="Email";
-                    }
-                        div class="row" {
-                            input
-                                id="password-input"
-                                type="password"
-                                name="password"
-                                placeholder="Password";
-                        }
-                        div class="row" {
-                            button type="submit" {"Login"}
-                        }
-                }
-                p id="login_failed" style="text-align: center; color: red;" {b {""}}
+            (login_form())
+        }
+    };
+    rq.respond(tiny_http::Response::from_string(pagecontent.into_string()).with_header(tiny_http::Header {
+        field: "Content-Type".parse().unwrap(),
+        value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
+    }))
+}
+
+// polled by every page, empty unless a session from this run has expired
+pub fn uri_session_status(
+    rq: Request,
+    wfm: Arc<Mutex<WFMClient>>,
+    logged_in: &watch::Sender<Option<bool>>,
+) -> Result<(), AppError> {
+    let was_logged_in = *logged_in.borrow() == Some(true);
+    let expired = was_logged_in
+        && block_in_place!(async {
+            let wfm = wfm.lock().await.clone();
+            wfm.session_expired().await
+        });
+    let pagecontent = if expired {
+        html! {
+            div id="relogin" class="container" {
+                p style="text-align: center;" {b {"Your warframe.market session ran out, please log in again"}}
+                (login_form())
             }
         }
+    } else {
+        html! {""}
     };
     rq.respond(tiny_http::Response::from_string(pagecontent.into_string()).with_header(tiny_http::Header {
         field: "Content-Type".parse().unwrap(),
         value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
     }))
+    .map_err(|e| AppError::new(e.to_string(), "uri_session_status".into()))
 }
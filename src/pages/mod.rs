@@ -1,2 +1,5 @@
+pub mod accounts;
+pub mod blacklist;
 pub mod home;
 pub mod login;
+pub mod reroll;
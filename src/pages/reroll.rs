@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use ascii::AsciiString;
+use maud::html;
+use tiny_http::Request;
+use tokio::sync::Mutex;
+
+use crate::{
+    block_in_place,
+    rivens::{
+        inventory::database::database::InventoryDB,
+        reroll::{expected_kuva, RollRule, WeaponRolls},
+    },
+    server::riven_lookup,
+    AppError,
+};
+
+async fn select_roll_rules(db: Arc<Mutex<Option<InventoryDB>>>) -> Result<Vec<RollRule>, AppError> {
+    let db = db.lock().await;
+    let db = match db.as_ref() {
+        Some(v) => v,
+        None => return Err(AppError::new("Database is closed".into(), "select_roll_rules".into())),
+    };
+    db.select_roll_rules()
+        .map_err(|e| AppError::new(e.to_string(), "select_roll_rules: select_roll_rules".into()))
+}
+
+// odds, kuva and the best case range of each wanted stat for the rule
+fn rule_summary(rule: &RollRule) -> Vec<String> {
+    let lookup = match riven_lookup() {
+        Some(v) => v,
+        None => return vec!["Riven data isn't loaded yet".into()],
+    };
+    let rolls = match WeaponRolls::from_lookup(&lookup, &rule.weapon_url_name) {
+        Ok(v) => v,
+        Err(e) => return vec![e.err.to_string()],
+    };
+    let odds = rolls.roll_odds(rule);
+    let mut summary = vec![format!("Odds per reroll: {:.2}%", odds * 100.0)];
+    summary.push(match expected_kuva(0, odds) {
+        Some(kuva) => format!("Expected kuva from an unrolled riven: {}", kuva.round()),
+        None => "Can't be rolled".into(),
+    });
+    // two positives with a curse gives the positives their highest values
+    rolls
+        .upgrades
+        .iter()
+        .filter(|upgr| rule.positives.contains(&upgr.url_name))
+        .for_each(|upgr| {
+            if let Some((low, high)) = rolls.value_range(&upgr.url_name, 2, true, true) {
+                summary.push(format!("{}: {low} - {high}", upgr.short_string));
+            }
+        });
+    summary
+}
+
+pub fn uri_reroll(
+    rq: Request,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+    notice: Option<&str>,
+) -> Result<(), AppError> {
+    let rules = block_in_place!(select_roll_rules(db)).map_err(|e| e.prop("uri_reroll".into()))?;
+
+    let pagecontent = html! {
+        div id="screen" style="justify-content: center;" {
+            div class="row" {
+                button class="cellbutton" hx-get="/home" hx-target="#screen" hx-swap="outerHTML" {"Back"}
+            }
+            div class="row" {
+                form class="cell" hx-post="/api/save_roll_rule" hx-target="#screen" hx-swap="outerHTML" {
+                    div class="celltitle" {"New Roll Rule"}
+                    hr style="width: 100%";
+                    div style="display: flex; flex-direction: column; flex-grow: 1;" {
+                        input type="text" name="weapon_url_name" placeholder="Weapon (e.g. skana)" required;
+                        select name="grade" {
+                            option value="god_roll" {"God Roll"}
+                            option value="decent" {"Decent"}
+                        }
+                        input type="text" name="positives" placeholder="Wanted positives, comma separated";
+                        input type="text" name="negatives" placeholder="Accepted curses, empty for any";
+                    }
+                    @if let Some(notice) = notice {
+                        p style="margin: 10px; color: red;" {(notice)}
+                    }
+                    div class="cellfooterdiv" {
+                        button class="cellbutton" type="submit" style="background-color: #7bdaff;" {"Save"}
+                    }
+                }
+            }
+            div id="roll-rule-table" class="row" {
+                @for rule in &rules {
+                    @let id = format!("r{}-{}", rule.weapon_url_name, rule.grade.as_str());
+                    div class="cell" id=(id) {
+                        div class="celltitle" {
+                            (format!("{} {}", rule.weapon_url_name, rule.grade.label()))
+                        }
+                        hr style="width: 100%";
+                        div style="flex-grow: 1" {
+                            p style="text-align: center; margin: 10px;" {
+                                "Positives: " (rule.positives.join(", "))
+                            }
+                            p style="text-align: center; margin: 10px;" {
+                                "Curses: "
+                                @if rule.negatives.is_empty() { "Any" } @else { (rule.negatives.join(", ")) }
+                            }
+                            @for line in rule_summary(rule) {
+                                p style="text-align: center; margin: 10px; font-size: 0.8em;" {(line)}
+                            }
+                        }
+                        div class="cellfooterdiv" {
+                            div style="float: left;" {
+                                button
+                                    class="cellbutton"
+                                    hx-delete=(format!("/api/delete_roll_rule/{}/{}", rule.weapon_url_name, rule.grade.as_str()))
+                                    hx-target=(format!("#{id}"))
+                                    hx-swap="outerHTML" {"Delete"}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    rq.respond(
+        tiny_http::Response::from_string(pagecontent.into_string()).with_header(
+            tiny_http::Header {
+                field: "Content-Type".parse().unwrap(),
+                value: AsciiString::from_ascii("text/html; charset=utf8").unwrap(),
+            },
+        ),
+    )
+    .map_err(|e| AppError::new(e.to_string(), "uri_reroll".to_string()))
+}
@@ -1,61 +1,421 @@
 use std::{
-    thread::sleep,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
+use futures_util::future::BoxFuture;
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+
+use crate::http_client::client::{Headers, StatusCode};
+
+// where the limiter gets the time from, so tests don't have to wait for real
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()>;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+// `rate` requests every `per`, with bursts of up to `rate` requests
 #[derive(Clone, Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    // how long one token takes to come back
+    interval: Duration,
+    last: Instant,
+    // set by the server telling us to back off
+    paused_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, per: Duration, now: Instant) -> Self {
+        let capacity = rate.max(1.0);
+        Self {
+            capacity,
+            tokens: capacity,
+            interval: per.div_f64(rate),
+            last: now,
+            paused_until: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() / self.interval.as_secs_f64()).min(self.capacity);
+        self.last = self.last.max(now);
+    }
+
+    // how long until a token can be taken, zero when one can be now
+    fn wait(&mut self, now: Instant) -> Duration {
+        if let Some(until) = self.paused_until.filter(|until| *until > now) {
+            return until - now;
+        }
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            self.interval.mul_f64(1.0 - self.tokens)
+        }
+    }
+
+    // the pause ends with a single token, so requests trickle back in
+    // instead of bursting the moment it's over
+    fn pause(&mut self, now: Instant, duration: Duration) {
+        let until = now + duration;
+        if self.paused_until.is_some_and(|paused| paused >= until) {
+            return;
+        }
+        self.paused_until = Some(until);
+        self.tokens = 1.0;
+        self.last = until;
+    }
+}
+
+// token buckets for one api. every request takes a token from the api's
+// bucket and from the bucket of each route its uri starts with
+#[derive(Debug)]
 pub struct RateLimiter {
-    pub tokens: f64,
-    last_timestamp: Instant,
-    rate: f64,
-    per: Duration,
-    offset_secs: f64,
+    name: Arc<str>,
+    // uri prefix a route bucket is named after
+    routes: Vec<Arc<str>>,
+    buckets: Mutex<HashMap<Arc<str>, TokenBucket>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl RateLimiter {
-    pub fn new(rate: f64, per: Duration) -> Self {
-        RateLimiter {
-            tokens: rate,
-            last_timestamp: Instant::now(),
-            rate,
-            per,
-            offset_secs: 0.0,
+    pub fn new(name: &str, rate: f64, per: Duration) -> Self {
+        Self::with_clock(name, rate, per, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(name: &str, rate: f64, per: Duration, clock: Arc<dyn Clock>) -> Self {
+        let name: Arc<str> = name.into();
+        let bucket = TokenBucket::new(rate, per, clock.now());
+        Self {
+            name: name.clone(),
+            routes: vec![],
+            buckets: Mutex::new(HashMap::from([(name, bucket)])),
+            clock,
         }
     }
 
-    fn can_make_request(&mut self) -> bool {
-        let now = Instant::now();
-        let elapsed = now.duration_since(self.last_timestamp);
-        let elapsed_secs = elapsed.as_secs_f64();
+    pub fn route(mut self, prefix: &str, rate: f64, per: Duration) -> Self {
+        let prefix: Arc<str> = prefix.into();
+        let bucket = TokenBucket::new(rate, per, self.clock.now());
+        self.buckets
+            .get_mut()
+            .unwrap()
+            .insert(prefix.clone(), bucket);
+        self.routes.push(prefix);
+        self
+    }
+
+    fn buckets_for<'a>(&'a self, uri: &'a str) -> impl Iterator<Item = &'a Arc<str>> + 'a {
+        std::iter::once(&self.name).chain(
+            self.routes
+                .iter()
+                .filter(move |prefix| uri.starts_with(prefix.as_ref())),
+        )
+    }
+
+    // the most specific bucket, that's the one the server's answer is about
+    fn bucket_for<'a>(&'a self, uri: &'a str) -> &'a Arc<str> {
+        self.buckets_for(uri)
+            .max_by_key(|name| name.len())
+            .unwrap_or(&self.name)
+    }
+
+    // waits without holding the lock, so other requests aren't stuck behind
+    // one that has to wait longer
+    pub async fn acquire(&self, uri: &str) {
+        loop {
+            let wait = {
+                let now = self.clock.now();
+                let mut buckets = self.buckets.lock().unwrap();
+                let wait = self
+                    .buckets_for(uri)
+                    .filter_map(|name| buckets.get_mut(name).map(|bucket| bucket.wait(now)))
+                    .max()
+                    .unwrap_or_default();
+                if wait.is_zero() {
+                    for name in self.buckets_for(uri) {
+                        if let Some(bucket) = buckets.get_mut(name) {
+                            bucket.tokens -= 1.0;
+                        }
+                    }
+                }
+                wait
+            };
+            if wait.is_zero() {
+                return;
+            }
+            self.clock.sleep(wait).await;
+        }
+    }
 
-        // Refill tokens based on how much time has passed since the last request
-        self.tokens += elapsed_secs / (self.per.as_secs_f64() * self.rate) + self.offset_secs;
-        self.last_timestamp = now;
+    // takes in what the server says about its limits. on a 429 it returns
+    // how long the route is paused for
+    pub fn observe(&self, uri: &str, status: &StatusCode, headers: &Headers) -> Option<Duration> {
+        let now = self.clock.now();
+        let wall_clock = OffsetDateTime::now_utc();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_mut(self.bucket_for(uri))?;
 
-        self.offset_secs = 0.0;
+        let remaining = ["X-RateLimit-Remaining", "RateLimit-Remaining"]
+            .into_iter()
+            .find_map(|key| headers.get(key))
+            .and_then(|v| v.trim().parse::<f64>().ok());
+        let reset = ["X-RateLimit-Reset", "RateLimit-Reset"]
+            .into_iter()
+            .find_map(|key| headers.get(key))
+            .and_then(|v| reset_after(&v, wall_clock));
+        if let Some(remaining) = remaining {
+            bucket.refill(now);
+            bucket.tokens = bucket.tokens.min(remaining);
+            if remaining < 1.0 {
+                bucket.pause(now, reset.unwrap_or(bucket.interval));
+            }
+        }
 
-        // Ensure the number of tokens doesn't exceed the rate
-        self.tokens = self.tokens.min(self.rate);
+        let retry_after = headers
+            .get("Retry-After")
+            .and_then(|v| retry_after(&v, wall_clock));
+        match (status.code, retry_after) {
+            (429, retry_after) => {
+                let wait = retry_after.or(reset).unwrap_or(bucket.interval);
+                bucket.pause(now, wait);
+                Some(wait)
+            }
+            // 503s can ask for a break too
+            (_, Some(retry_after)) => {
+                bucket.pause(now, retry_after);
+                None
+            }
+            _ => None,
+        }
+    }
+}
 
-        if self.tokens < 1.0 {
-            false
-        } else {
-            // Remove a token and allow the request
-            self.tokens -= 1.0;
-            true
+// `Retry-After` is either seconds or an http date
+fn retry_after(value: &str, now: OffsetDateTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = OffsetDateTime::parse(value, &Rfc2822).ok()?;
+    Some(Duration::try_from(date - now).unwrap_or_default())
+}
+
+static MAX_RESET_SECS: f64 = 24.0 * 60.0 * 60.0;
+
+// seconds until the limit resets. some apis send a unix timestamp instead,
+// anything that big can't be meant as seconds from now. waits longer than a
+// day are cut down to one, a broken header shouldn't stop requests for good
+fn reset_after(value: &str, now: OffsetDateTime) -> Option<Duration> {
+    let value = value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v >= 0.0)?;
+    let secs = if value < 1_000_000_000.0 {
+        value
+    } else {
+        (value - now.unix_timestamp() as f64).max(0.0)
+    };
+    Some(Duration::from_secs_f64(secs.min(MAX_RESET_SECS)))
+}
+
+// time only moves when something sleeps, sleeping moves it forward right away
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+    sleeps: Mutex<Vec<Duration>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+            sleeps: Mutex::new(vec![]),
+        })
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'_, ()> {
+        self.sleeps.lock().unwrap().push(duration);
+        self.advance(duration);
+        Box::pin(async {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::macros::datetime;
+
+    use super::{reset_after, retry_after, MockClock, RateLimiter};
+    use crate::http_client::client::{Headers, StatusCode};
+
+    fn status(code: u16) -> StatusCode {
+        StatusCode {
+            code,
+            text: "".into(),
         }
     }
 
-    pub fn add_delay(&mut self, delay_secs: f64) {
-        self.offset_secs += delay_secs;
+    fn headers(headers: &[&str]) -> Headers {
+        headers
+            .iter()
+            .map(|header| header.parse().unwrap())
+            .collect()
     }
 
-    pub async fn wait_for_token(&mut self) {
-        while !self.can_make_request() {
-            let time_to_wait_secs =
-                self.per.as_secs_f64() / self.rate - self.last_timestamp.elapsed().as_secs_f64();
-            let time_to_wait = Duration::from_secs_f64(time_to_wait_secs);
-            sleep(time_to_wait);
+    #[tokio::test]
+    async fn test_token_bucket() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock("api", 3.0, Duration::from_secs(1), clock.clone());
+
+        // a full bucket goes out at once, then one every third of a second
+        for _ in 0..3 {
+            limiter.acquire("https://api/a").await;
         }
+        assert!(clock.sleeps().is_empty());
+        limiter.acquire("https://api/a").await;
+        limiter.acquire("https://api/a").await;
+        assert_eq!(clock.elapsed(), Duration::from_secs(1).div_f64(3.0) * 2);
+
+        // idle time refills, but never past the burst size
+        clock.advance(Duration::from_secs(60));
+        let before = clock.elapsed();
+        for _ in 0..4 {
+            limiter.acquire("https://api/a").await;
+        }
+        assert_eq!(
+            clock.elapsed() - before,
+            Duration::from_secs(1).div_f64(3.0)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_rate() {
+        // the old refill divided by the rate twice, this would wait 4s
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock("api", 0.5, Duration::from_secs(1), clock.clone());
+        limiter.acquire("https://api/a").await;
+        limiter.acquire("https://api/a").await;
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_route_buckets() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock("api", 10.0, Duration::from_secs(1), clock.clone())
+            .route("https://api/search", 1.0, Duration::from_secs(2));
+
+        limiter.acquire("https://api/search?q=a").await;
+        limiter.acquire("https://api/other").await;
+        assert!(clock.sleeps().is_empty());
+        limiter.acquire("https://api/search?q=b").await;
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_server_limits() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::with_clock("api", 10.0, Duration::from_secs(1), clock.clone())
+            .route("https://api/search", 10.0, Duration::from_secs(1));
+
+        let wait = limiter.observe(
+            "https://api/search",
+            &status(429),
+            &headers(&["retry-after: 5"]),
+        );
+        assert_eq!(wait, Some(Duration::from_secs(5)));
+        // only the route is paused
+        limiter.acquire("https://api/other").await;
+        assert!(clock.sleeps().is_empty());
+        limiter.acquire("https://api/search").await;
+        assert_eq!(clock.elapsed(), Duration::from_secs(5));
+        // and it comes back slowly
+        limiter.acquire("https://api/search").await;
+        assert_eq!(clock.elapsed(), Duration::from_millis(5100));
+
+        // running out according to the server waits for its reset
+        let wait = limiter.observe(
+            "https://api/other",
+            &status(200),
+            &headers(&["X-RateLimit-Remaining: 0", "X-RateLimit-Reset: 2"]),
+        );
+        assert_eq!(wait, None);
+        let before = clock.elapsed();
+        limiter.acquire("https://api/other").await;
+        assert_eq!(clock.elapsed() - before, Duration::from_secs(2));
+
+        // a 429 without any hints waits for one token
+        let wait = limiter.observe("https://api/other", &status(429), &headers(&[]));
+        assert_eq!(wait, Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = datetime!(2015-10-21 07:28:00 UTC);
+        assert_eq!(retry_after(" 120 ", now), Some(Duration::from_secs(120)));
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after("soon", now), None);
+
+        assert_eq!(reset_after("1.5", now), Some(Duration::from_millis(1500)));
+        let in_a_minute = (now.unix_timestamp() + 60).to_string();
+        assert_eq!(
+            reset_after(&in_a_minute, now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(reset_after("-1", now), None);
+        assert_eq!(reset_after("inf", now), None);
+        assert_eq!(reset_after("NaN", now), None);
+        assert_eq!(reset_after("1e30", now), Some(Duration::from_secs(24 * 60 * 60)));
     }
 }
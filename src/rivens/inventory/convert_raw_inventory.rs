@@ -88,6 +88,9 @@ pub struct Attribute {
     pub url_name: String,
     pub units: Units,
     pub short_string: String,
+    // where the value landed between the lowest and highest possible roll for
+    // the weapon's disposition and buff count, in percent
+    pub roll: Option<f64>,
 }
 
 #[derive(Debug)]
@@ -128,23 +131,11 @@ pub fn convert_inventory_data(lookup: &RivenDataLookup, upgrades: Vec<Upgrades>)
     upgrades.iter().for_each(|upgrade| {
         let mut raw_attributes: Vec<RawAttributes> = vec![];
         let buff_count = upgrade.upgrade_fingerprint.buffs.len();
-        let (good_multiplier, bad_multiplier) = if !upgrade.upgrade_fingerprint.curses.is_empty() {
-            if buff_count == 2 {
-                (1.2375, -0.495)
-            } else if buff_count == 3 {
-                (0.9375, -0.75)
-            } else {
-                panic!("no buffs with the associated riven!")
-            }
-        } else {
-            if buff_count == 2 {
-                (0.99, 0.0)
-            } else if buff_count == 3 {
-                (0.75, 0.0)
-            } else {
-                panic!("no buffs with the associated riven!")
-            }
-        };
+        let (good_multiplier, bad_multiplier) = attribute_multipliers(
+            buff_count,
+            !upgrade.upgrade_fingerprint.curses.is_empty(),
+        )
+        .expect("no buffs with the associated riven!");
         upgrade.upgrade_fingerprint.buffs.iter().for_each(|buff| {
             raw_attributes.push(RawAttributes {
                 positive: true,
@@ -166,7 +157,7 @@ pub fn convert_inventory_data(lookup: &RivenDataLookup, upgrades: Vec<Upgrades>)
         ) {
             Ok(v) => v,
             Err(e) => {
-                println!("{}", e);
+                eprintln!("{}", e);
                 return;
             }
         };
@@ -174,7 +165,7 @@ pub fn convert_inventory_data(lookup: &RivenDataLookup, upgrades: Vec<Upgrades>)
             match lookup_riven_data(lookup, weapon_type.as_str(), raw_attributes) {
                 Ok(v) => v,
                 Err(e) => {
-                    println!("{}", e);
+                    eprintln!("{}", e);
                     return;
                 }
             };
@@ -232,6 +223,35 @@ fn parse_riven_name(attributes_info: &[AttributeInfo], num_buffs: usize) -> Stri
     )
 }
 
+// the (positive, negative) value multipliers for a riven with `buff_count`
+// positives and maybe a curse
+pub fn attribute_multipliers(buff_count: usize, cursed: bool) -> Option<(f64, f64)> {
+    match (buff_count, cursed) {
+        (2, true) => Some((1.2375, -0.495)),
+        (3, true) => Some((0.9375, -0.75)),
+        (2, false) => Some((0.99, 0.0)),
+        (3, false) => Some((0.75, 0.0)),
+        _ => None,
+    }
+}
+
+// the displayed value of an attribute, `x` being the 0.9-1.1 roll multiplier
+pub fn attribute_value(
+    x: f64,
+    base_value: f64,
+    disposition: f64,
+    multiplier: f64,
+    lvl: u8,
+    units: &Units,
+) -> f64 {
+    let y = 90.0 * base_value * disposition * multiplier;
+    let value = x * y * 100.0 * (lvl + 1) as f64 / 9.0;
+    match units {
+        Units::Multiply => (value + 100.0).round() / 100.0,
+        _ => (value * 10.0).round() / 10.0,
+    }
+}
+
 fn calculate_attributes(
     attribute_info: Vec<AttributeInfo>,
     good_multiplier: f64,
@@ -250,12 +270,15 @@ fn calculate_attributes(
         } else {
             bad_multiplier
         };
-        let y = 90.0 * attr.base_value * disposition * good_bad_multiplier;
-        let value = x * y * 100.0 * (lvl + 1) as f64 / 9.0;
-        let value = match attr.units {
-            Units::Multiply => (value + 100.0).round() / 100.0,
-            _ => (value * 10.0).round() / 10.0,
-        };
+        let roll = ((x - 0.9) / 0.2 * 1000.0).round() / 10.0;
+        let value = attribute_value(
+            x,
+            attr.base_value,
+            disposition,
+            good_bad_multiplier,
+            lvl,
+            &attr.units,
+        );
         let (_, short_string) = attr
             .short_string
             .split_once('>')
@@ -266,6 +289,7 @@ fn calculate_attributes(
             short_string: short_string.to_string(),
             units: attr.units.clone(),
             url_name: attr.wfm_url.to_string(),
+            roll: Some(roll),
         });
     });
     attributes
@@ -466,14 +490,14 @@ impl ToSql for Units {
 }
 
 #[derive(Debug)]
-enum UnitsLookupError {
-    InvalidField(UnitsLookupField),
-    InvalidAttribute(Rc<str>),
-    InvalidUnits(Arc<str>),
+pub(crate) enum UnitsLookupError {
+    Field(UnitsLookupField),
+    Attribute(Rc<str>),
+    Units(Arc<str>),
 }
 
 #[derive(Debug)]
-enum UnitsLookupField {
+pub(crate) enum UnitsLookupField {
     AvailableAttributes,
     Units,
     UrlName,
@@ -482,9 +506,9 @@ enum UnitsLookupField {
 impl Display for UnitsLookupError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let err = match self {
-            UnitsLookupError::InvalidField(v) => format!("Invalid field: {}", v),
-            UnitsLookupError::InvalidAttribute(v) => format!("Invalid Attribute: {}", v),
-            UnitsLookupError::InvalidUnits(v) => format!("Invalid Units: {}", v),
+            UnitsLookupError::Field(v) => format!("Invalid field: {}", v),
+            UnitsLookupError::Attribute(v) => format!("Invalid Attribute: {}", v),
+            UnitsLookupError::Units(v) => format!("Invalid Units: {}", v),
         };
         f.write_str(err.as_str())
     }
@@ -501,12 +525,12 @@ impl Display for UnitsLookupField {
     }
 }
 
-fn lookup_units<'a>(
+pub(crate) fn lookup_units<'a>(
     lookup: &'a RivenDataLookup,
     wfm_url: &'a str,
 ) -> Result<Units, UnitsLookupError> {
     if lookup.available_attributes.is_none() {
-        return Err(UnitsLookupError::InvalidField(
+        return Err(UnitsLookupError::Field(
             UnitsLookupField::AvailableAttributes,
         ));
     }
@@ -516,14 +540,14 @@ fn lookup_units<'a>(
         .find(|attr| attr.url_name.is_none())
         .is_some()
     {
-        return Err(UnitsLookupError::InvalidField(UnitsLookupField::UrlName));
+        return Err(UnitsLookupError::Field(UnitsLookupField::UrlName));
     }
 
     let attr = available_attributes
         .iter()
         .find(|&attr| attr.url_name.clone() == Some(wfm_url.into()));
     if attr.is_none() {
-        return Err(UnitsLookupError::InvalidAttribute(wfm_url.into()));
+        return Err(UnitsLookupError::Attribute(wfm_url.into()));
     }
     let attr = attr.unwrap();
     match attr.units.clone() {
@@ -531,7 +555,7 @@ fn lookup_units<'a>(
             "percent" => Ok(Units::Percent),
             "multiply" => Ok(Units::Multiply),
             "seconds" => Ok(Units::Seconds),
-            _ => Err(UnitsLookupError::InvalidUnits(unit)),
+            _ => Err(UnitsLookupError::Units(unit)),
         },
         None => Ok(Units::Null),
     }
@@ -638,22 +662,49 @@ mod tests {
     use tokio::sync::{broadcast, Mutex};
 
     use crate::{
+        config::Config,
         http_client::{auth_state, qf_client::QFClient},
         rivens::inventory::{raw_inventory::decrypt_last_data, riven_lookop::RivenDataLookup},
     };
 
-    use super::convert_inventory_data;
+    use super::{calculate_attributes, convert_inventory_data, AttributeInfo, Units};
+
+    #[test]
+    fn test_calculate_roll() {
+        let info = |value: i32| AttributeInfo {
+            positive: true,
+            value,
+            units: Units::Percent,
+            wfm_url: "critical_chance".into(),
+            short_string: "<DT_CRIT>Critical Chance".into(),
+            prefix: "crita".into(),
+            suffix: "cron".into(),
+            base_value: 0.0167,
+        };
+        let attributes = calculate_attributes(
+            vec![info(0), info(536870910), info(1073741820), info(i32::MAX)],
+            1.0,
+            1.0,
+            1.0,
+            8,
+        );
+        let rolls: Vec<_> = attributes.iter().map(|attr| attr.roll).collect();
+
+        assert_eq!(rolls, vec![Some(0.0), Some(50.0), Some(100.0), Some(100.0)]);
+        assert!(attributes[0].value < attributes[2].value);
+    }
 
     #[tokio::test]
     async fn test_convert_inventory_data() {
         dotenv().unwrap();
-        let auth = auth_state::AuthState::setup().expect("hehe");
+        let (config, _) = Config::load(vec![]).unwrap();
+        let auth = auth_state::AuthState::setup(&config.paths.auth, config.secret_key()).expect("hehe");
         let auth = Arc::new(Mutex::new(auth));
         let (send_stop, _) = broadcast::channel(1);
         let qf = QFClient::new(auth, send_stop.subscribe());
         let qf = Arc::new(Mutex::new(qf));
-        let lookup = RivenDataLookup::setup(qf).await.unwrap();
-        let raw_upgrades = decrypt_last_data(None).unwrap();
+        let lookup = RivenDataLookup::setup(qf, &config.paths.riven_lookup).await.unwrap();
+        let raw_upgrades = decrypt_last_data(&config.paths.last_data, &config.decrypt).unwrap();
         let items = convert_inventory_data(&lookup, raw_upgrades);
         let out = to_value(items).unwrap();
         let mut file = File::create("rivenData.json").unwrap();
@@ -1,10 +1,14 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
-use rusqlite::{params, Connection, Transaction};
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Transaction};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-use crate::rivens::inventory::convert_raw_inventory::{Attribute, Item, Units};
+use crate::rivens::{
+    inventory::convert_raw_inventory::{Attribute, Item, Units},
+    reroll::{RollGrade, RollRule},
+    wfm_auctions::WFMAuction,
+};
 
 pub struct InventoryDB {
     connection: Connection,
@@ -19,6 +23,7 @@ pub struct Auction {
     pub updated: Option<OffsetDateTime>,
     pub is_direct_sell: bool,
     pub id: Option<String>,
+    #[serde(default)]
     pub oid: String,
 }
 
@@ -36,44 +41,77 @@ impl Default for Auction {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriceSuggestion {
+    pub low: u32,
+    pub median: u32,
+    pub high: u32,
+    pub samples: u32,
+    pub updated: OffsetDateTime,
+}
+
 // I need to do something about this...
 static SQL_TABLE_ITEMS: &str = "CREATE TABLE IF NOT EXISTS items ( item_id text primary key, mastery_level integer, name text, weapon_name text, polarity text, weapon_url_name text, re_rolls integer, mod_rank integer)";
-static SQL_TABLE_ATTRIBUTES: &str = "CREATE TABLE IF NOT EXISTS attributes ( item_id text, value float, positive bit, units text, url_name text, short_string text)";
+static SQL_TABLE_ATTRIBUTES: &str = "CREATE TABLE IF NOT EXISTS attributes ( item_id text, value float, positive bit, units text, url_name text, short_string text, roll float)";
+// databases created before roll grading don't have the column yet
+static SQL_ATTRIBUTES_HAS_ROLL: &str = "SELECT COUNT(*) FROM pragma_table_info('attributes') WHERE name = 'roll'";
+static SQL_ATTRIBUTES_ADD_ROLL: &str = "ALTER TABLE attributes ADD COLUMN roll float";
 static SQL_TABLE_AUCTIONS: &str = "CREATE TABLE IF NOT EXISTS auctions ( item_id text primary key, wfm_id text, starting_price integer, buyout_price integer, owner text, updated datetime, is_direct_sell bit)";
-
-static SQL_ATTRIBUTE_INSERT: &str = "INSERT INTO attributes ( item_id, value, positive, units, url_name, short_string) values (?1, ?2, ?3, ?4, ?5, ?6)";
-static SQL_AUCTION_INSERT: &str = "INSERT INTO auctions ( oid, wfm_id, starting_price, buyout_price, owner, updated, is_direct_sell) values (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+static SQL_TABLE_BLACKLIST: &str = "CREATE TABLE IF NOT EXISTS blacklist ( item_id text primary key)";
+static SQL_TABLE_ROLL_RULES: &str = "CREATE TABLE IF NOT EXISTS roll_rules ( weapon_url_name text, grade text, positives text, negatives text, primary key ( weapon_url_name, grade))";
+// searches are shared by every riven of the weapon, suggestions are worked out
+// from them for each riven
+static SQL_TABLE_AUCTION_SEARCHES: &str = "CREATE TABLE IF NOT EXISTS auction_searches ( weapon_url_name text primary key, auctions text, updated datetime)";
+
+static SQL_ATTRIBUTE_INSERT: &str = "INSERT INTO attributes ( item_id, value, positive, units, url_name, short_string, roll) values (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+static SQL_AUCTION_INSERT: &str = "INSERT OR REPLACE INTO auctions ( item_id, wfm_id, starting_price, buyout_price, owner, updated, is_direct_sell) values (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+static SQL_BLACKLIST_INSERT: &str = "INSERT OR IGNORE INTO blacklist ( item_id) values (?1)";
+static SQL_AUCTION_SEARCH_INSERT: &str = "INSERT OR REPLACE INTO auction_searches ( weapon_url_name, auctions, updated) values (?1, ?2, ?3)";
+static SQL_ROLL_RULE_INSERT: &str = "INSERT OR REPLACE INTO roll_rules ( weapon_url_name, grade, positives, negatives) values (?1, ?2, ?3, ?4)";
 static SQL_ITEM_INSERT: &str = "INSERT INTO items ( item_id, mastery_level, name, weapon_name, polarity, weapon_url_name, re_rolls, mod_rank) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)";
 
 static SQL_SELECT_ITEMS: &str = "SELECT * FROM items";
+static SQL_SELECT_ITEM: &str = "SELECT * FROM items WHERE item_id = ?1";
 static SQL_SELECT_ATTRIBUTES: &str = "SELECT * FROM attributes WHERE item_id = ?1";
+static SQL_SELECT_AUCTION: &str = "SELECT * FROM auctions WHERE item_id = ?1";
 static SQL_SELECT_AUCTIONS: &str = "SELECT * FROM auctions";
+static SQL_SELECT_AUCTION_SEARCH: &str = "SELECT * FROM auction_searches WHERE weapon_url_name = ?1";
+static SQL_SELECT_ROLL_RULES: &str = "SELECT * FROM roll_rules";
+static SQL_SELECT_BLACKLIST: &str = "SELECT * FROM blacklist";
+static SQL_SELECT_BLACKLISTED: &str = "SELECT * FROM blacklist WHERE item_id = ?1";
 
 static SQL_DELETE_ITEMS: &str = "DELETE FROM items WHERE item_id = ?1";
 static SQL_DELETE_ATTRIBUTES: &str = "DELETE FROM attributes WHERE item_id = ?1";
-static SQL_DELETE_AUCTIONS: &str = "DELETE FROM auctions WHERE oid = ?1";
+static SQL_DELETE_AUCTIONS: &str = "DELETE FROM auctions WHERE item_id = ?1";
+static SQL_DELETE_BLACKLIST: &str = "DELETE FROM blacklist WHERE item_id = ?1";
+static SQL_DELETE_ROLL_RULE: &str = "DELETE FROM roll_rules WHERE weapon_url_name = ?1 AND grade = ?2";
 
 impl InventoryDB {
-    pub fn open(custom_path: &str) -> Result<Self, rusqlite::Error> {
+    pub fn open<P: AsRef<Path>>(custom_path: P) -> Result<Self, rusqlite::Error> {
         let mut connection = Connection::open(custom_path)?;
         let tx = connection.transaction()?;
         tx.execute(SQL_TABLE_ITEMS, ())?;
         tx.execute(SQL_TABLE_AUCTIONS, ())?;
         tx.execute(SQL_TABLE_ATTRIBUTES, ())?;
+        let has_roll: u32 = tx.query_row(SQL_ATTRIBUTES_HAS_ROLL, (), |row| row.get(0))?;
+        if has_roll == 0 {
+            tx.execute(SQL_ATTRIBUTES_ADD_ROLL, ())?;
+        }
+        tx.execute(SQL_TABLE_BLACKLIST, ())?;
+        tx.execute(SQL_TABLE_AUCTION_SEARCHES, ())?;
+        tx.execute(SQL_TABLE_ROLL_RULES, ())?;
         tx.commit()?;
         Ok(Self { connection })
     }
 
     pub fn close(self) -> Result<(), (Connection, rusqlite::Error)> {
-        println!("INFO: Database connection closed");
+        eprintln!("INFO: Database connection closed");
         self.connection.close()
     }
 
-    fn insert_auctions(
-        &mut self,
-        auctions: Vec<Auction>,
-        oid: &str,
-    ) -> Result<(), rusqlite::Error> {
+    /// inserts the auctions keyed by their `oid`, replacing any auction
+    /// already stored for the same item
+    pub fn insert_auctions(&mut self, auctions: &[Auction]) -> Result<(), rusqlite::Error> {
         let tx = self.connection.transaction()?;
         let mut auc_insert = tx.prepare(SQL_AUCTION_INSERT)?;
 
@@ -81,7 +119,7 @@ impl InventoryDB {
             .iter()
             .try_for_each(|auc| -> Result<(), rusqlite::Error> {
                 let res = auc_insert.execute(params![
-                    &oid,
+                    &auc.oid,
                     &auc.id,
                     &auc.starting_price,
                     &auc.buyout_price,
@@ -108,7 +146,6 @@ fn insert_attributes(
 ) -> Result<(), rusqlite::Error> {
     let mut attr_insert = tx.prepare(SQL_ATTRIBUTE_INSERT)?;
 
-    println!("{attributes:#?}");
     attributes
         .iter()
         .try_for_each(|attr| -> Result<(), rusqlite::Error> {
@@ -119,6 +156,7 @@ fn insert_attributes(
                 attr.units,
                 attr.url_name,
                 attr.short_string,
+                attr.roll,
             ]);
             if res.is_err() {
                 Err(res.unwrap_err())
@@ -130,7 +168,7 @@ fn insert_attributes(
 
 impl InventoryDB {
     pub(super) fn insert_items(&mut self, items: &[Item]) -> Result<(), rusqlite::Error> {
-        println!("inserting items");
+        eprintln!("inserting items");
         let tx = self.connection.transaction()?;
         let mut item_insert = tx.prepare(SQL_ITEM_INSERT)?;
 
@@ -159,7 +197,24 @@ impl InventoryDB {
         tx.commit()
     }
 
-    pub(super) fn delete_items_auctions(
+    // auctions are kept around so the listing can still be closed on
+    // warframe.market before `delete_items_auctions` drops them
+    pub(super) fn delete_items(&mut self, items: &[Arc<str>]) -> Result<(), rusqlite::Error> {
+        items
+            .iter()
+            .try_for_each(|oid| -> Result<(), rusqlite::Error> {
+                let mut items_delete = self.connection.prepare(SQL_DELETE_ITEMS)?;
+                let mut attrs_delete = self.connection.prepare(SQL_DELETE_ATTRIBUTES)?;
+                let mut blacklist_delete = self.connection.prepare(SQL_DELETE_BLACKLIST)?;
+
+                items_delete.execute([oid])?;
+                attrs_delete.execute([oid])?;
+                blacklist_delete.execute([oid])?;
+                Ok(())
+            })
+    }
+
+    pub fn delete_items_auctions(
         &mut self,
         items: Vec<Arc<str>>,
     ) -> Result<(), rusqlite::Error> {
@@ -177,7 +232,149 @@ impl InventoryDB {
             })
     }
 
-    pub(super) fn select_items(&self) -> Result<Vec<Item>, rusqlite::Error> {
+    pub fn delete_auction(&mut self, oid: &str) -> Result<(), rusqlite::Error> {
+        let mut auc_delete = self.connection.prepare(SQL_DELETE_AUCTIONS)?;
+        auc_delete.execute([oid])?;
+        Ok(())
+    }
+
+    pub fn blacklist_item(&mut self, oid: &str) -> Result<(), rusqlite::Error> {
+        let mut blacklist_insert = self.connection.prepare(SQL_BLACKLIST_INSERT)?;
+        blacklist_insert.execute([oid])?;
+        Ok(())
+    }
+
+    pub fn unblacklist_item(&mut self, oid: &str) -> Result<(), rusqlite::Error> {
+        let mut blacklist_delete = self.connection.prepare(SQL_DELETE_BLACKLIST)?;
+        blacklist_delete.execute([oid])?;
+        Ok(())
+    }
+
+    pub fn select_blacklist(&self) -> Result<Vec<Arc<str>>, rusqlite::Error> {
+        let mut blacklist_select = self.connection.prepare(SQL_SELECT_BLACKLIST)?;
+        let blacklist = blacklist_select
+            .query_map([], |row| row.get::<_, Arc<str>>("item_id"))?
+            .collect::<Result<Vec<Arc<str>>, rusqlite::Error>>()?;
+        Ok(blacklist)
+    }
+
+    // stat lists are stored as comma separated url names
+    pub fn insert_roll_rule(&mut self, rule: &RollRule) -> Result<(), rusqlite::Error> {
+        let mut rule_insert = self.connection.prepare(SQL_ROLL_RULE_INSERT)?;
+        rule_insert.execute(params![
+            rule.weapon_url_name,
+            rule.grade.as_str(),
+            rule.positives.join(","),
+            rule.negatives.join(","),
+        ])?;
+        Ok(())
+    }
+
+    pub fn delete_roll_rule(
+        &mut self,
+        weapon_url_name: &str,
+        grade: &RollGrade,
+    ) -> Result<(), rusqlite::Error> {
+        let mut rule_delete = self.connection.prepare(SQL_DELETE_ROLL_RULE)?;
+        rule_delete.execute([weapon_url_name, grade.as_str()])?;
+        Ok(())
+    }
+
+    pub fn select_roll_rules(&self) -> Result<Vec<RollRule>, rusqlite::Error> {
+        let split = |stats: String| -> Vec<Arc<str>> {
+            stats
+                .split(',')
+                .filter(|stat| !stat.is_empty())
+                .map(Arc::from)
+                .collect()
+        };
+        let mut rules_select = self.connection.prepare(SQL_SELECT_ROLL_RULES)?;
+        let rules = rules_select
+            .query_map([], |row| {
+                let grade: String = row.get("grade")?;
+                let grade = RollGrade::try_from(grade.as_str()).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        1,
+                        rusqlite::types::Type::Text,
+                        Box::new(e),
+                    )
+                })?;
+                Ok(RollRule {
+                    weapon_url_name: row.get("weapon_url_name")?,
+                    grade,
+                    positives: split(row.get("positives")?),
+                    negatives: split(row.get("negatives")?),
+                })
+            })?
+            .collect::<Result<Vec<RollRule>, rusqlite::Error>>()?;
+        Ok(rules)
+    }
+
+    pub fn is_blacklisted(&self, oid: &str) -> Result<bool, rusqlite::Error> {
+        let mut blacklisted_select = self.connection.prepare(SQL_SELECT_BLACKLISTED)?;
+        let blacklisted = blacklisted_select
+            .query_row([oid], |row| row.get::<_, Arc<str>>("item_id"))
+            .optional()?;
+        Ok(blacklisted.is_some())
+    }
+
+    pub fn insert_auction_search(
+        &mut self,
+        weapon_url_name: &str,
+        auctions: &[WFMAuction],
+        updated: OffsetDateTime,
+    ) -> Result<(), rusqlite::Error> {
+        let auctions = serde_json::to_string(auctions)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        let mut search_insert = self.connection.prepare(SQL_AUCTION_SEARCH_INSERT)?;
+        search_insert.execute(params![weapon_url_name, auctions, updated])?;
+        Ok(())
+    }
+
+    pub fn select_auction_search(
+        &self,
+        weapon_url_name: &str,
+    ) -> Result<Option<(Vec<WFMAuction>, OffsetDateTime)>, rusqlite::Error> {
+        let mut search_select = self.connection.prepare(SQL_SELECT_AUCTION_SEARCH)?;
+        let search = search_select
+            .query_row([weapon_url_name], |row| {
+                let auctions: String = row.get("auctions")?;
+                let auctions = serde_json::from_str::<Vec<WFMAuction>>(&auctions)
+                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(1, Type::Text, Box::new(e)))?;
+                Ok((auctions, row.get("updated")?))
+            })
+            .optional()?;
+        Ok(search)
+    }
+
+    pub fn select_item(&self, oid: &str) -> Result<Option<Item>, rusqlite::Error> {
+        let mut item_select = self.connection.prepare(SQL_SELECT_ITEM)?;
+        let item = item_select
+            .query_row([oid], |row| {
+                Ok(Item {
+                    mastery_level: row.get("mastery_level")?,
+                    name: row.get("name")?,
+                    weapon_name: row.get("weapon_name")?,
+                    polarity: row.get("polarity")?,
+                    attributes: vec![],
+                    weapon_url_name: row.get("weapon_url_name")?,
+                    re_rolls: row.get("re_rolls")?,
+                    mod_rank: row.get("mod_rank")?,
+                    oid: row.get("item_id")?,
+                })
+            })
+            .optional()?;
+        let item = match item {
+            Some(mut item) => {
+                item.attributes = self.select_attributes(item.oid.clone())?;
+                Some(item)
+            }
+            None => None,
+        };
+        Ok(item)
+    }
+
+    pub fn select_items(&self) -> Result<Vec<Item>, rusqlite::Error> {
         let mut items_select = self.connection.prepare(SQL_SELECT_ITEMS)?;
         let items = items_select
             .query_map([], |row| {
@@ -218,6 +415,7 @@ impl InventoryDB {
                     url_name: row.get("url_name")?,
                     units,
                     short_string: row.get("short_string")?,
+                    roll: row.get("roll")?,
                 })
             })?
             .try_fold(
@@ -231,19 +429,39 @@ impl InventoryDB {
         Ok(attributes)
     }
 
-    pub(super) fn select_auction(&self, oid: Arc<str>) -> Result<Auction, rusqlite::Error> {
+    pub fn select_auctions(&self) -> Result<Vec<Auction>, rusqlite::Error> {
         let mut auctions_select = self.connection.prepare(SQL_SELECT_AUCTIONS)?;
-        let auc = auctions_select.query_row(&[&oid], |row| {
-            Ok(Auction {
-                starting_price: row.get("starting_price")?,
-                buyout_price: row.get("buyout_price")?,
-                owner: row.get("owner")?,
-                updated: row.get("updated")?,
-                is_direct_sell: row.get("is_direct_sell")?,
-                id: row.get("id")?,
-                oid: row.get("item_id")?,
+        let auctions = auctions_select
+            .query_map([], |row| {
+                Ok(Auction {
+                    starting_price: row.get("starting_price")?,
+                    buyout_price: row.get("buyout_price")?,
+                    owner: row.get("owner")?,
+                    updated: row.get("updated")?,
+                    is_direct_sell: row.get("is_direct_sell")?,
+                    id: row.get("wfm_id")?,
+                    oid: row.get("item_id")?,
+                })
+            })?
+            .collect::<Result<Vec<Auction>, rusqlite::Error>>()?;
+        Ok(auctions)
+    }
+
+    pub fn select_auction(&self, oid: &str) -> Result<Option<Auction>, rusqlite::Error> {
+        let mut auctions_select = self.connection.prepare(SQL_SELECT_AUCTION)?;
+        let auc = auctions_select
+            .query_row([oid], |row| {
+                Ok(Auction {
+                    starting_price: row.get("starting_price")?,
+                    buyout_price: row.get("buyout_price")?,
+                    owner: row.get("owner")?,
+                    updated: row.get("updated")?,
+                    is_direct_sell: row.get("is_direct_sell")?,
+                    id: row.get("wfm_id")?,
+                    oid: row.get("item_id")?,
+                })
             })
-        })?;
+            .optional()?;
         Ok(auc)
     }
 }
@@ -263,6 +481,7 @@ mod tests {
     use tokio::sync::{broadcast, Mutex};
 
     use crate::{
+        config::Config,
         http_client::{auth_state::AuthState, qf_client::QFClient},
         rivens::inventory::{
             convert_raw_inventory::convert_inventory_data, raw_inventory::decrypt_last_data,
@@ -270,22 +489,118 @@ mod tests {
         },
     };
 
-    use super::Auction;
+    use crate::rivens::reroll::{RollGrade, RollRule};
+
+    use crate::rivens::wfm_auctions::WFMAuction;
+
+    use super::{Auction, InventoryDB};
+
+    #[test]
+    fn test_auction_upsert() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = InventoryDB::open(dir.path().join("inventory_db.sqlite3")).unwrap();
+        let mut auction = Auction {
+            starting_price: Some(100),
+            buyout_price: Some(100),
+            id: Some("66368ad69454320dffff15f1".into()),
+            oid: "riven_oid".into(),
+            ..Default::default()
+        };
+        db.insert_auctions(&[auction.clone()]).unwrap();
+        auction.buyout_price = Some(250);
+        db.insert_auctions(&[auction]).unwrap();
+
+        let stored = db.select_auction("riven_oid").unwrap();
+        let missing = db.select_auction("not_listed").unwrap();
+        db.close().unwrap();
+
+        let stored = stored.expect("auction should be stored");
+        assert_eq!(stored.id.as_deref(), Some("66368ad69454320dffff15f1"));
+        assert_eq!(stored.buyout_price, Some(250));
+        assert!(missing.is_none());
+    }
 
     async fn _test_insert_data() {
         dotenv().unwrap();
-        let auth = AuthState::setup().expect("hehe");
+        let (config, _) = Config::load(vec![]).unwrap();
+        let auth = AuthState::setup(&config.paths.auth, config.secret_key()).expect("hehe");
         let auth = Arc::new(Mutex::new(auth));
         let (send_stop, _) = broadcast::channel(1);
         let qf = QFClient::new(auth, send_stop.subscribe());
         let qf = Arc::new(Mutex::new(qf));
-        let lookup = RivenDataLookup::setup(qf).await.unwrap();
-        let raw_upgrades = decrypt_last_data(None).unwrap();
+        let lookup = RivenDataLookup::setup(qf, &config.paths.riven_lookup).await.unwrap();
+        let raw_upgrades = decrypt_last_data(&config.paths.last_data, &config.decrypt).unwrap();
         let items = convert_inventory_data(&lookup, raw_upgrades);
         let mut auctions = Vec::with_capacity(items.len());
         auctions.fill(Auction::default());
     }
 
+    #[test]
+    fn test_blacklist() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = InventoryDB::open(dir.path().join("inventory_db.sqlite3")).unwrap();
+        db.blacklist_item("kept_riven").unwrap();
+        db.blacklist_item("kept_riven").unwrap();
+        db.blacklist_item("other_riven").unwrap();
+        db.unblacklist_item("other_riven").unwrap();
+
+        let blacklist = db.select_blacklist().unwrap();
+        let kept = db.is_blacklisted("kept_riven").unwrap();
+        let other = db.is_blacklisted("other_riven").unwrap();
+        db.close().unwrap();
+
+        assert_eq!(blacklist, vec![Arc::<str>::from("kept_riven")]);
+        assert!(kept);
+        assert!(!other);
+    }
+
+    #[test]
+    fn test_auction_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = InventoryDB::open(dir.path().join("inventory_db.sqlite3")).unwrap();
+        let auction: WFMAuction = serde_json::from_value(serde_json::json!({
+            "id": "auction",
+            "buyout_price": 150,
+            "starting_price": 100,
+            "item": {"type": "riven", "weapon_url_name": "skana", "attributes": []},
+        }))
+        .unwrap();
+        let updated = time::macros::datetime!(2024-05-04 19:21:58 UTC);
+        db.insert_auction_search("skana", &[], updated).unwrap();
+        db.insert_auction_search("skana", &[auction], updated).unwrap();
+
+        let stored = db.select_auction_search("skana").unwrap();
+        let missing = db.select_auction_search("kronen").unwrap();
+        db.close().unwrap();
+
+        let (auctions, stored_updated) = stored.unwrap();
+        assert_eq!(stored_updated, updated);
+        assert_eq!(auctions.len(), 1);
+        assert_eq!(auctions[0].buyout_price, Some(150));
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn test_roll_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = InventoryDB::open(dir.path().join("inventory_db.sqlite3")).unwrap();
+        let rule = RollRule {
+            weapon_url_name: "skana".into(),
+            grade: RollGrade::GodRoll,
+            positives: vec!["critical_chance".into(), "critical_damage".into()],
+            negatives: vec![],
+        };
+        db.insert_roll_rule(&RollRule { positives: vec![], ..rule.clone() }).unwrap();
+        db.insert_roll_rule(&rule).unwrap();
+        db.insert_roll_rule(&RollRule { grade: RollGrade::Decent, ..rule.clone() }).unwrap();
+        db.delete_roll_rule("skana", &RollGrade::Decent).unwrap();
+
+        let rules = db.select_roll_rules().unwrap();
+        db.close().unwrap();
+
+        assert_eq!(rules, vec![rule]);
+    }
+
     unsafe fn _any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
         ::core::slice::from_raw_parts((p as *const T) as *const u8, ::core::mem::size_of::<T>())
     }
@@ -1,11 +1,16 @@
 use std::{ops::DerefMut, sync::Arc};
 
+use serde::Serialize;
+use time::OffsetDateTime;
 use tokio::sync::Mutex;
 
-use crate::rivens::inventory::{
-    convert_raw_inventory::{convert_inventory_data, Item, Upgrades},
-    raw_inventory::{decrypt_last_data, InventoryDecryptError},
-    riven_lookop::RivenDataLookup,
+use crate::{
+    config::Config,
+    rivens::inventory::{
+        convert_raw_inventory::{convert_inventory_data, Item, Upgrades},
+        raw_inventory::{decrypt_last_data, InventoryDecryptError},
+        riven_lookop::RivenDataLookup,
+    },
 };
 
 use super::database::InventoryDB;
@@ -16,9 +21,55 @@ pub enum DataBaseSyncError {
     DatabaseError(rusqlite::Error),
 }
 
+// outcome of the last sync, for anything that wants to show it without
+// syncing again
+#[derive(Clone, Debug, Serialize)]
+pub struct SyncStatus {
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_sync: Option<OffsetDateTime>,
+    pub items: usize,
+    pub removed: usize,
+    pub error: Option<String>,
+}
+
+pub static SYNC_STATUS: std::sync::Mutex<SyncStatus> = std::sync::Mutex::new(SyncStatus {
+    last_sync: None,
+    items: 0,
+    removed: 0,
+    error: None,
+});
+
+fn record_sync(res: &Result<(Vec<Item>, Vec<Arc<str>>), DataBaseSyncError>) {
+    let mut status = match SYNC_STATUS.lock() {
+        Ok(v) => v,
+        Err(e) => e.into_inner(),
+    };
+    status.last_sync = Some(OffsetDateTime::now_utc());
+    match res {
+        Ok((items, removed)) => {
+            status.items = items.len();
+            status.removed = removed.len();
+            status.error = None;
+        }
+        Err(e) => status.error = Some(format!("{e:?}")),
+    }
+}
+
 pub async fn sync_db(
     db: Arc<Mutex<Option<InventoryDB>>>,
     lookup: &RivenDataLookup,
+    config: &Config,
+    inventory_items_test: Option<Vec<Upgrades>>,
+) -> Result<(Vec<Item>, Vec<Arc<str>>), DataBaseSyncError> {
+    let res = sync_inventory(db, lookup, config, inventory_items_test).await;
+    record_sync(&res);
+    res
+}
+
+async fn sync_inventory(
+    db: Arc<Mutex<Option<InventoryDB>>>,
+    lookup: &RivenDataLookup,
+    config: &Config,
     inventory_items_test: Option<Vec<Upgrades>>,
 ) -> Result<(Vec<Item>, Vec<Arc<str>>), DataBaseSyncError> {
     let mut db = db.lock().await;
@@ -28,25 +79,22 @@ pub async fn sync_db(
     let inventory_items = if let Some(invitest) = inventory_items_test {
         invitest
     } else {
-        decrypt_last_data(None).map_err(|e| DataBaseSyncError::DecryptError(e))?
+        decrypt_last_data(&config.paths.last_data, &config.decrypt)
+            .map_err(DataBaseSyncError::DecryptError)?
     };
 
     let mut same_items = get_same_items(&db_items, &inventory_items);
 
     let old_items: Vec<Item> = get_old_items(&db_items, &inventory_items);
 
-    // DELETE OLD ITEMS + ANY AUCTIONS FOR OLD ITEMS IN DB
+    // DELETE OLD ITEMS IN DB, THE CALLER CLOSES AND DELETES THEIR AUCTIONS
 
     let delete_ids: Vec<Arc<str>> = old_items.into_iter().map(|item| item.oid).collect();
 
-    db.delete_items_auctions(delete_ids.clone())
-        .map_err(|e| DataBaseSyncError::DatabaseError(e))?;
+    db.delete_items(&delete_ids)
+        .map_err(DataBaseSyncError::DatabaseError)?;
 
     let new_items = get_new_items(&db_items, inventory_items);
-    assert!(
-        new_items.is_empty(),
-        "new_items should be empty when getting items out of db"
-    );
 
     // ADD NEW ITEMS TO DB
 
@@ -110,87 +158,129 @@ mod tests {
 
     use std::sync::Arc;
 
-    use dotenv::dotenv;
+    use serde_json::json;
     use tokio::sync::{broadcast, Mutex};
 
     use crate::{
-        http_client::{auth_state::AuthState, qf_client::QFClient},
+        config::Config,
+        http_client::{
+            auth_state::AuthState, mock_transport::MockTransport, qf_client::QFClient,
+            secret_store::SecretKey,
+        },
         rivens::inventory::{
-            convert_raw_inventory::{convert_inventory_data, Item},
-            database::{database::InventoryDB, inventory_sync::{get_new_items, get_old_items, get_same_items}},
-            raw_inventory::decrypt_last_data,
+            convert_raw_inventory::Upgrades, database::database::InventoryDB,
             riven_lookop::RivenDataLookup,
         },
     };
 
-    fn update_db(
-        db: &mut InventoryDB,
-        new: Option<Vec<Item>>,
-        old: Option<Vec<Item>>,
-    ) -> Vec<Item> {
-        if let Some(new) = new {
-            db.insert_items(&new).unwrap();
-        };
-        if let Some(old) = old {
-            let old = old.into_iter().map(|item| item.oid).collect();
-            db.delete_items_auctions(old).unwrap();
-        };
-        db.select_items().unwrap()
+    use super::sync_db;
+
+    static WEAPON: &str = "/Lotus/Weapons/Tenno/Pistol/Lato";
+    static UPGRADE_TYPE: &str = "/Lotus/Upgrades/Mods/Randomized/LotusPistolRandomModRare";
+
+    fn lookup_fixture() -> serde_json::Value {
+        json!({
+            "weapons": [{
+                "wfm_url_name": "lato",
+                "unique_name": WEAPON,
+                "name": "Lato",
+                "disposition": 1.3,
+                "upgrade_type": UPGRADE_TYPE,
+            }],
+            "rivens_attributes": [{
+                "unique_name": UPGRADE_TYPE,
+                "upgrades": [
+                    {
+                        "wfm_url": "base_damage_/_melee_damage",
+                        "short_string": "Damage",
+                        "modifier_tag": "WeaponDamageAmountMod",
+                        "prefix": "visi",
+                        "suffix": "ata",
+                        "value": 0.165,
+                    },
+                    {
+                        "wfm_url": "critical_chance",
+                        "short_string": "Crit Chance",
+                        "modifier_tag": "WeaponCritChanceMod",
+                        "prefix": "crita",
+                        "suffix": "cron",
+                        "value": 0.15,
+                    },
+                ],
+            }],
+            "available_attributes": [
+                {"units": "percent", "url_name": "base_damage_/_melee_damage"},
+                {"units": "percent", "url_name": "critical_chance"},
+            ],
+        })
+    }
+
+    fn upgrade(oid: &str) -> Upgrades {
+        serde_json::from_value(json!({
+            "UpgradeFingerprint": {
+                "compat": WEAPON,
+                "lim": 0,
+                "lvlReq": 10,
+                "lvl": 8,
+                "rerolls": 2,
+                "pol": "AP_ATTACK",
+                "buffs": [
+                    {"Tag": "WeaponDamageAmountMod", "Value": 100000000},
+                    {"Tag": "WeaponCritChanceMod", "Value": 200000000},
+                ],
+                "curses": [],
+            },
+            "ItemType": UPGRADE_TYPE,
+            "ItemId": {"$oid": oid},
+        }))
+        .unwrap()
+    }
+
+    fn inventory(oids: &[&str]) -> Option<Vec<Upgrades>> {
+        Some(oids.iter().map(|oid| upgrade(oid)).collect())
     }
 
     #[tokio::test]
     async fn test_sync_db() {
-        dotenv().unwrap();
-        let contrl_items = decrypt_last_data(Some("lastDataControl.dat")).unwrap();
-        let added_items = decrypt_last_data(Some("lastDataAdded.dat")).unwrap();
-        let subtracted_items = decrypt_last_data(Some("lastDataSubtracted.dat")).unwrap();
-        let auth = AuthState::setup().expect("hehe");
-        let auth = Arc::new(Mutex::new(auth));
+        let dir = tempfile::tempdir().unwrap();
+        let auth = AuthState::setup(
+            &dir.path().join("auth.json"),
+            SecretKey::KeyFile(dir.path().join("auth.key")),
+        )
+        .unwrap();
+        let mock = MockTransport::from_json(json!([
+            {"method": "GET", "path": "/items/riven/raw", "body": lookup_fixture()},
+        ]));
         let (stop_send, _) = broadcast::channel(1);
-        let qf = QFClient::new(auth, stop_send.subscribe());
-        let qf = Arc::new(Mutex::new(qf));
-        let lookup = RivenDataLookup::setup(qf).await.unwrap();
-        let mut db = InventoryDB::open("test_db.sqlite3").unwrap();
-
-        let mut db_items = update_db(&mut db, None, None);
-        let same = {
-            let inventory_items = contrl_items;
-            let same = get_same_items(&db_items, &inventory_items).len();
-            let new = get_new_items(&db_items, inventory_items);
-            let new = convert_inventory_data(&lookup, new);
-            db_items = update_db(&mut db, Some(new), None);
-            same
-        };
-        assert_eq!(same, 0, "same itms: {same}");
-
-        let (same, old, new) = {
-            let inventory_items = added_items;
-            let same = get_same_items(&db_items, &inventory_items).len();
-            let old = get_old_items(&db_items, &inventory_items);
-            let new = get_new_items(&db_items, inventory_items);
-            let new = convert_inventory_data(&lookup, new);
-            db_items = update_db(&mut db, Some(new.clone()), Some(old.clone()));
-            (same, old.len(), new.len())
-        };
-        assert_ne!(same, 0, "same itms: {same}");
-        assert_eq!(old, 0, "{old}");
-        assert_eq!(new, 1, "{new} added");
-
-        let (same, old, new) = {
-            let inventory_items = subtracted_items;
-            let same = get_same_items(&db_items, &inventory_items).len();
-            let old = get_old_items(&db_items, &inventory_items);
-            let new = get_new_items(&db_items, inventory_items);
-            let new = convert_inventory_data(&lookup, new);
-            update_db(&mut db, Some(new.clone()), Some(old.clone()));
-            (same, old.len(), new.len())
-        };
-        drop(db);
-
-        println!("same: {same}");
-        assert_eq!(old, 2, "{old}");
-        assert_eq!(new, 0, "{new}");
-
-        std::fs::remove_file("test_db.sqlite3").unwrap();
+        let qf = QFClient::new(Arc::new(Mutex::new(auth)), stop_send.subscribe()).transport(mock);
+        let lookup = RivenDataLookup::setup(Arc::new(Mutex::new(qf)), &dir.path().join("lookup.json"))
+            .await
+            .unwrap();
+        let db = InventoryDB::open(dir.path().join("test_db.sqlite3")).unwrap();
+        let db = Arc::new(Mutex::new(Some(db)));
+        let config = Config::default();
+
+        let (items, removed) = sync_db(db.clone(), &lookup, &config, inventory(&["a", "b", "c"]))
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(removed.is_empty());
+        assert_eq!(&*items[0].name, "Critaata");
+        assert_eq!(&*items[0].weapon_url_name, "lato");
+
+        let (items, removed) = sync_db(db.clone(), &lookup, &config, inventory(&["a", "b", "c", "d"]))
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 4);
+        assert!(removed.is_empty());
+
+        let (items, mut removed) = sync_db(db.clone(), &lookup, &config, inventory(&["a", "d"]))
+            .await
+            .unwrap();
+        removed.sort();
+        assert_eq!(items.len(), 2);
+        assert_eq!(removed, vec![Arc::from("b"), Arc::from("c")]);
+        let stored = db.lock().await.as_ref().unwrap().select_items().unwrap();
+        assert_eq!(stored.len(), 2);
     }
 }
@@ -1,10 +1,10 @@
 use core::str;
-use std::{env::{self, VarError}, error::Error, fmt::Display, fs::File, io::{self, Read}, num::ParseIntError, ops::Deref, path::PathBuf, str::Utf8Error, sync::Arc};
+use std::{error::Error, fmt::Display, fs::File, io::{self, Read}, num::ParseIntError, ops::Deref, path::{Path, PathBuf}, str::Utf8Error, sync::Arc};
 
 use aes::cipher::{block_padding::{NoPadding, UnpadError}, BlockDecryptMut, KeyIvInit};
 use serde_json::{from_value, Value};
 
-use crate::rivens::inventory::convert_raw_inventory::Upgrades;
+use crate::{config::DecryptConfig, rivens::inventory::convert_raw_inventory::Upgrades};
 
 type DecryptThingy = cbc::Decryptor<aes::Aes128>;
 
@@ -13,7 +13,7 @@ pub enum InventoryDecryptError {
     DecryptorError(UnpadError),
     ParseError(ParseErrorType),
     IoError(io::Error, PathBuf),
-    EnvVarError(VarError),
+    MissingKey(&'static str),
     DeserializeError(serde_json::Error),
     OtherError(Arc<str>),
 }
@@ -25,7 +25,7 @@ impl Display for InventoryDecryptError {
             InventoryDecryptError::DecryptorError(e) => format!("DecryptorError: {}", e),
             InventoryDecryptError::ParseError(e) => format!("ParseError: {}", e),
             InventoryDecryptError::IoError(e, path) => format!("IoErrort: {}, {}", e, path.to_str().unwrap()),
-            InventoryDecryptError::EnvVarError(e) => format!("EnvVarErrort: {}", e),
+            InventoryDecryptError::MissingKey(e) => format!("MissingKey: no {} in the config", e),
             InventoryDecryptError::DeserializeError(e) => format!("DeserializeErrort: {}", e),
             InventoryDecryptError::OtherError(e) => String::from(e.deref()),
         };
@@ -49,24 +49,25 @@ impl Display for ParseErrorType {
     }
 }
 
-pub fn decrypt_last_data<'a>(custom_path: Option<&str>) -> Result<Vec<Upgrades>, InventoryDecryptError> {
-    let path = if let Some(path) = custom_path {
-        PathBuf::from(path)
-    } else {
-        PathBuf::from("lastData.dat")
-    };
-    let mut file = File::open(path.clone()).map_err(|e| InventoryDecryptError::IoError(e, path))?;
+pub fn decrypt_last_data(path: &Path, keys: &DecryptConfig) -> Result<Vec<Upgrades>, InventoryDecryptError> {
+    let mut file = File::open(path).map_err(|e| InventoryDecryptError::IoError(e, path.into()))?;
     let mut ciphertext: Vec<u8> = vec![];
-    file.read_to_end(&mut ciphertext).map_err(|e| InventoryDecryptError::IoError(e, "".into()))?;
+    file.read_to_end(&mut ciphertext).map_err(|e| InventoryDecryptError::IoError(e, path.into()))?;
 
-    let key_var = env::var("KEY").map_err(|e| InventoryDecryptError::EnvVarError(e))?;
+    if keys.key.is_empty() {
+        return Err(InventoryDecryptError::MissingKey("key"));
+    }
+    let key_var = &keys.key;
     let mut key: Vec<u8> = Vec::with_capacity(16);
     key_var.split(",").try_for_each(|num| -> Result<(), ParseIntError> {
         let num: u8 = num.parse()?;
         key.push(num);
         Ok(())
     }).map_err(|e| InventoryDecryptError::ParseError(ParseErrorType::ParseInt(e)))?;
-    let iv_var = env::var("IV").map_err(|e| InventoryDecryptError::EnvVarError(e))?;
+    if keys.iv.is_empty() {
+        return Err(InventoryDecryptError::MissingKey("iv"));
+    }
+    let iv_var = &keys.iv;
     let mut iv: Vec<u8> = Vec::with_capacity(16);
     iv_var.split(",").try_for_each(|num| -> Result<(), ParseIntError> {
         let num: u8 = num.parse()?;
@@ -102,12 +103,15 @@ pub fn decrypt_last_data<'a>(custom_path: Option<&str>) -> Result<Vec<Upgrades>,
 mod tests {
     use dotenv::dotenv;
 
+    use crate::config::Config;
+
     use super::decrypt_last_data;
 
     #[test]
     fn test_deserialize() {
         dotenv().unwrap();
-        let _upgrades = decrypt_last_data(None).unwrap();
+        let (config, _) = Config::load(vec![]).unwrap();
+        let _upgrades = decrypt_last_data(&config.paths.last_data, &config.decrypt).unwrap();
         // println!("{}")
     }
 }
@@ -1,9 +1,8 @@
 use std::{
-    env,
     fs::{read_to_string, File},
     io::Write,
     ops::DerefMut,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
@@ -81,7 +80,7 @@ fn from_file(path: PathBuf) -> Option<RivenDataLookup> {
     let mut v = from_str::<Value>(s.as_str()).expect("malformed json");
     let ts = v["unix_ts"].as_i64();
     if ts.is_none() {
-        println!(
+        eprintln!(
         "WARNING: No timestamp associated with riven lookup data\nGetting data from external server..."
         );
         return None;
@@ -90,12 +89,12 @@ fn from_file(path: PathBuf) -> Option<RivenDataLookup> {
     let now = ODT::now_utc().unix_timestamp();
     let elapsed = now - ts.expect("could not parse timestamp");
     if elapsed >= MONTH_IN_SECONDS {
-        println!("WARNING: Riven lookup data is too old\nGetting data from external server...");
+        eprintln!("WARNING: Riven lookup data is too old\nGetting data from external server...");
         return None;
     }
     let res = from_value::<RivenDataLookup>(v["data"].take());
     if res.is_err() {
-        println!(
+        eprintln!(
             "WARNING: Could not parse riven lookup data\nGetting data from external server..."
         );
         return None;
@@ -104,16 +103,13 @@ fn from_file(path: PathBuf) -> Option<RivenDataLookup> {
 }
 
 impl RivenDataLookup {
-    pub async fn setup(qf: Arc<Mutex<QFClient>>) -> Result<Self, AppError> {
-        let path: PathBuf = env::var("PWD")
-            .map_err(|e| AppError::new(e.to_string().into(), "setup: env::var".into()))?
-            .into();
-        let path = path.join("rivenLookupData.json");
+    pub async fn setup(qf: Arc<Mutex<QFClient>>, path: &Path) -> Result<Self, AppError> {
         let riven_data: Option<Self> = if path.exists() {
-            from_file(path.clone())
+            from_file(path.into())
         } else {
-            println!(
-            "WARNING: `rivenLookupData.json` does not exist\nGetting data from external server..."
+            eprintln!(
+            "WARNING: `{}` does not exist\nGetting data from external server...",
+            path.display()
             );
             None
         };
@@ -153,13 +149,13 @@ impl RivenDataLookup {
                     data: data.clone(),
                 });
                 if json.is_err() {
-                    println!("ERR: Could not write lookup data to file (serialize failed)");
+                    eprintln!("ERR: Could not write lookup data to file (serialize failed)");
                 };
                 if f.write_all(json.unwrap().as_bytes()).is_err() {
-                    println!("ERR: Could not write lookup data to file (write failed)");
+                    eprintln!("ERR: Could not write lookup data to file (write failed)");
                 };
             } else {
-                println!("ERR: Could not write lookup data to file (file create failed)")
+                eprintln!("ERR: Could not write lookup data to file (file create failed)")
             };
             data
         };
@@ -1,2 +1,4 @@
 pub mod wfm_auctions;
 pub mod inventory;
+pub mod price_suggestion;
+pub mod reroll;
@@ -0,0 +1,219 @@
+use std::sync::Arc;
+
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+
+use crate::{
+    http_client::wfm_client::WFMClient,
+    rivens::{
+        inventory::{
+            convert_raw_inventory::Item,
+            database::database::{InventoryDB, PriceSuggestion},
+        },
+        wfm_auctions::{WFMAuction, WFMAuctionItem},
+    },
+    AppError,
+};
+
+// a weapon's auctions are searched at most every 6 hours so opening the edit
+// dialog doesn't eat into the request budget every time, rivens of the same
+// weapon share the search
+static SUGGESTION_MAX_AGE_SECONDS: i64 = 21600;
+
+// share of attributes two rivens need to have in common to be comparable
+static MIN_SIMILARITY: f64 = 0.5;
+
+// shared attributes over all the attributes either riven has, positives and
+// negatives only count as shared when they're on the same side
+pub fn similarity(item: &Item, auction: &WFMAuctionItem) -> f64 {
+    let shared = item
+        .attributes
+        .iter()
+        .filter(|attr| {
+            auction.attributes.iter().any(|wfm_attr| {
+                wfm_attr.url_name.as_ref() == attr.url_name && wfm_attr.positive == attr.positive
+            })
+        })
+        .count();
+    let total = item.attributes.len() + auction.attributes.len() - shared;
+    if total == 0 {
+        return 0.0;
+    }
+    shared as f64 / total as f64
+}
+
+// the first price past `pct` of the total weight, `sorted` by price
+fn percentile(sorted: &[(u32, f64)], pct: f64) -> u32 {
+    let target = pct * sorted.iter().map(|(_, weight)| weight).sum::<f64>();
+    let mut acc = 0.0;
+    sorted
+        .iter()
+        .find(|(_, weight)| {
+            acc += weight;
+            acc > target
+        })
+        .or(sorted.last())
+        .map(|(price, _)| *price)
+        .unwrap_or_default()
+}
+
+// prices are weighted by how similar their riven is, so close matches count
+// for more than ones that only share half the stats
+pub fn suggest_price(
+    item: &Item,
+    auctions: &[WFMAuction],
+    own_wfm_id: Option<&str>,
+    updated: OffsetDateTime,
+) -> PriceSuggestion {
+    let mut prices: Vec<(u32, f64)> = auctions
+        .iter()
+        .filter(|auc| !auc.closed && Some(auc.id.as_ref()) != own_wfm_id)
+        .map(|auc| (auc, similarity(item, &auc.item)))
+        .filter(|(_, similarity)| *similarity >= MIN_SIMILARITY)
+        .filter_map(|(auc, similarity)| auc.buyout_price.or(auc.starting_price).map(|price| (price, similarity)))
+        .collect();
+    prices.sort_unstable_by_key(|(price, _)| *price);
+
+    if prices.is_empty() {
+        return PriceSuggestion {
+            low: 0,
+            median: 0,
+            high: 0,
+            samples: 0,
+            updated,
+        };
+    }
+    PriceSuggestion {
+        low: percentile(&prices, 0.25),
+        median: percentile(&prices, 0.5),
+        high: percentile(&prices, 0.75),
+        samples: prices.len() as u32,
+        updated,
+    }
+}
+
+// uses the weapon's cached search when it's recent enough, otherwise searches
+// warframe.market and caches the new one
+pub async fn get_price_suggestion(
+    item: &Item,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<PriceSuggestion, AppError> {
+    let (cached, own_auction) = {
+        let db = db.lock().await;
+        let db = match db.as_ref() {
+            Some(v) => v,
+            None => {
+                return Err(AppError::new(
+                    "Database is closed".into(),
+                    "get_price_suggestion".into(),
+                ))
+            }
+        };
+        let cached = db.select_auction_search(&item.weapon_url_name).map_err(|e| {
+            AppError::new(e.to_string(), "get_price_suggestion: select_auction_search".into())
+        })?;
+        let own_auction = db.select_auction(&item.oid).map_err(|e| {
+            AppError::new(e.to_string(), "get_price_suggestion: select_auction".into())
+        })?;
+        (cached, own_auction)
+    };
+    let own_wfm_id = own_auction.and_then(|auc| auc.id);
+    if let Some((auctions, updated)) = cached {
+        let age = OffsetDateTime::now_utc() - updated;
+        if age.whole_seconds() < SUGGESTION_MAX_AGE_SECONDS {
+            return Ok(suggest_price(item, &auctions, own_wfm_id.as_deref(), updated));
+        }
+    }
+
+    // a clone so lookups for other rivens don't wait for this one
+    let mut wfm = wfm.lock().await.clone();
+    let auctions = wfm
+        .search_auctions(&item.weapon_url_name)
+        .await
+        .map_err(|e| e.prop("get_price_suggestion".into()))?;
+    let updated = OffsetDateTime::now_utc();
+
+    let mut db = db.lock().await;
+    if let Some(db) = db.as_mut() {
+        db.insert_auction_search(&item.weapon_url_name, &auctions, updated).map_err(|e| {
+            AppError::new(e.to_string(), "get_price_suggestion: insert_auction_search".into())
+        })?;
+    }
+    Ok(suggest_price(item, &auctions, own_wfm_id.as_deref(), updated))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+    use time::OffsetDateTime;
+
+    use crate::rivens::{
+        inventory::convert_raw_inventory::{Attribute, Item, Units},
+        wfm_auctions::WFMAuction,
+    };
+
+    use super::suggest_price;
+
+    fn attr(url_name: &str, positive: bool) -> Attribute {
+        Attribute {
+            value: if positive { 100.0 } else { -50.0 },
+            positive,
+            url_name: url_name.into(),
+            units: Units::Percent,
+            short_string: "".into(),
+            roll: None,
+        }
+    }
+
+    fn auction(id: &str, price: u32, attributes: &[(&str, bool)]) -> WFMAuction {
+        let attributes: Vec<_> = attributes
+            .iter()
+            .map(|(url_name, positive)| json!({"url_name": url_name, "value": 1.0, "positive": positive}))
+            .collect();
+        from_value(json!({
+            "id": id,
+            "buyout_price": price,
+            "starting_price": price,
+            "item": {"type": "riven", "weapon_url_name": "skana", "attributes": attributes},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_suggest_price() {
+        let item = Item {
+            weapon_url_name: "skana".into(),
+            attributes: vec![
+                attr("critical_chance", true),
+                attr("critical_damage", true),
+                attr("zoom", false),
+            ],
+            oid: "riven".into(),
+            ..Default::default()
+        };
+        let auctions = vec![
+            auction("same", 300, &[("critical_chance", true), ("critical_damage", true), ("zoom", false)]),
+            auction("close", 200, &[("critical_chance", true), ("critical_damage", true)]),
+            auction("cheap", 100, &[("critical_chance", true), ("critical_damage", true), ("impact_damage", false)]),
+            auction("expensive", 900, &[("critical_chance", true), ("critical_damage", true), ("multishot", true)]),
+            auction("unrelated", 5, &[("zoom", true), ("impact_damage", true)]),
+            auction("own", 1, &[("critical_chance", true), ("critical_damage", true), ("zoom", false)]),
+        ];
+        let suggestion = suggest_price(&item, &auctions, Some("own"), OffsetDateTime::now_utc());
+
+        assert_eq!(suggestion.samples, 4);
+        assert_eq!(suggestion.low, 200);
+        assert_eq!(suggestion.median, 300);
+        assert_eq!(suggestion.high, 300);
+
+        // two half matches don't outweigh the exact one
+        let auctions = vec![
+            auction("cheap", 100, &[("critical_chance", true), ("critical_damage", true), ("impact_damage", false)]),
+            auction("other_cheap", 100, &[("critical_chance", true), ("critical_damage", true), ("multishot", true)]),
+            auction("same", 500, &[("critical_chance", true), ("critical_damage", true), ("zoom", false)]),
+        ];
+        let suggestion = suggest_price(&item, &auctions, None, OffsetDateTime::now_utc());
+        assert_eq!(suggestion.median, 500);
+    }
+}
@@ -0,0 +1,394 @@
+use std::sync::Arc;
+
+use crate::{
+    rivens::inventory::{
+        convert_raw_inventory::{
+            attribute_multipliers, attribute_value, lookup_units, Item, Units,
+        },
+        riven_lookop::RivenDataLookup,
+    },
+    AppError,
+};
+
+// kuva needed for the nth reroll of a riven, every reroll after the last one
+// costs the same
+static KUVA_COSTS: [u32; 10] = [900, 1000, 1200, 1400, 1700, 2000, 2350, 2750, 3150, 3500];
+
+// rivens are always graded at max rank
+static MAX_RANK: u8 = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RollGrade {
+    GodRoll,
+    Decent,
+    Dissolve,
+}
+
+impl RollGrade {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RollGrade::GodRoll => "god_roll",
+            RollGrade::Decent => "decent",
+            RollGrade::Dissolve => "dissolve",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RollGrade::GodRoll => "God Roll",
+            RollGrade::Decent => "Decent",
+            RollGrade::Dissolve => "Dissolve",
+        }
+    }
+}
+
+impl TryFrom<&str> for RollGrade {
+    type Error = AppError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "god_roll" => Ok(RollGrade::GodRoll),
+            "decent" => Ok(RollGrade::Decent),
+            "dissolve" => Ok(RollGrade::Dissolve),
+            _ => Err(AppError::new(
+                format!("Invalid roll grade: {value}"),
+                "RollGrade::try_from".into(),
+            )),
+        }
+    }
+}
+
+// what a riven for `weapon_url_name` needs to roll to get `grade`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RollRule {
+    pub weapon_url_name: Arc<str>,
+    pub grade: RollGrade,
+    // every one of these has to be rolled as a positive, the rest are free
+    pub positives: Vec<Arc<str>>,
+    // curses that are fine to have, any curse is fine when empty
+    pub negatives: Vec<Arc<str>>,
+}
+
+impl RollRule {
+    fn matches_roll(&self, positives: &[&str], curse: Option<&str>) -> bool {
+        let has_positives = self
+            .positives
+            .iter()
+            .all(|wanted| positives.contains(&wanted.as_ref()));
+        let curse_ok = match curse {
+            Some(curse) => {
+                self.negatives.is_empty() || self.negatives.iter().any(|neg| neg.as_ref() == curse)
+            }
+            None => true,
+        };
+        has_positives && curse_ok
+    }
+
+    pub fn matches(&self, item: &Item) -> bool {
+        if item.weapon_url_name != self.weapon_url_name {
+            return false;
+        }
+        let positives: Vec<&str> = item
+            .attributes
+            .iter()
+            .filter(|attr| attr.positive)
+            .map(|attr| attr.url_name.as_str())
+            .collect();
+        let curse = item
+            .attributes
+            .iter()
+            .find(|attr| !attr.positive)
+            .map(|attr| attr.url_name.as_str());
+        self.matches_roll(&positives, curse)
+    }
+}
+
+// the best grade the riven gets from the rules for its weapon, or `None` when
+// there are no rules for it. rivens that miss every rule are up for dissolving
+pub fn grade_riven(item: &Item, rules: &[RollRule]) -> Option<RollGrade> {
+    let rules: Vec<&RollRule> = rules
+        .iter()
+        .filter(|rule| rule.weapon_url_name == item.weapon_url_name)
+        .collect();
+    if rules.is_empty() {
+        return None;
+    }
+    [RollGrade::GodRoll, RollGrade::Decent]
+        .into_iter()
+        .find(|grade| {
+            rules
+                .iter()
+                .any(|rule| &rule.grade == grade && rule.matches(item))
+        })
+        .or(Some(RollGrade::Dissolve))
+}
+
+#[derive(Clone, Debug)]
+pub struct RollableUpgrade {
+    pub url_name: Arc<str>,
+    pub short_string: Arc<str>,
+    pub base_value: f64,
+    pub units: Units,
+}
+
+// everything a riven for a weapon can roll
+#[derive(Clone, Debug)]
+pub struct WeaponRolls {
+    pub disposition: f64,
+    pub upgrades: Vec<RollableUpgrade>,
+}
+
+impl WeaponRolls {
+    pub fn from_lookup(lookup: &RivenDataLookup, weapon_url_name: &str) -> Result<Self, AppError> {
+        let weapon = lookup
+            .weapons
+            .as_ref()
+            .and_then(|weapons| {
+                weapons
+                    .iter()
+                    .find(|weap| weap.wfm_url_name.as_deref() == Some(weapon_url_name))
+            })
+            .ok_or_else(|| {
+                AppError::new(
+                    format!("Could not find weapon: {weapon_url_name}"),
+                    "WeaponRolls::from_lookup".into(),
+                )
+            })?;
+        let (disposition, upgrade_type) = match (weapon.disposition, weapon.upgrade_type.as_ref()) {
+            (Some(disposition), Some(upgrade_type)) => (disposition, upgrade_type),
+            _ => {
+                return Err(AppError::new(
+                    format!("Missing disposition or upgrade type for: {weapon_url_name}"),
+                    "WeaponRolls::from_lookup".into(),
+                ))
+            }
+        };
+        let upgrades = lookup
+            .rivens_attributes
+            .as_ref()
+            .and_then(|attrs| {
+                attrs
+                    .iter()
+                    .find(|attr| attr.unique_name.as_ref() == Some(upgrade_type))
+            })
+            .and_then(|attr| attr.upgrades.as_ref())
+            .ok_or_else(|| {
+                AppError::new(
+                    format!("Could not find upgrades for weapon type: {upgrade_type}"),
+                    "WeaponRolls::from_lookup".into(),
+                )
+            })?;
+        let upgrades = upgrades
+            .iter()
+            .filter_map(|upgr| {
+                let url_name = upgr.wfm_url.clone()?;
+                let units = lookup_units(lookup, &url_name).ok()?;
+                let short_string = upgr.short_string.clone().unwrap_or_default();
+                let (_, short_string) = short_string
+                    .split_once('>')
+                    .unwrap_or(("", short_string.as_ref()));
+                Some(RollableUpgrade {
+                    short_string: short_string.into(),
+                    url_name,
+                    base_value: upgr.value?,
+                    units,
+                })
+            })
+            .collect();
+        Ok(Self {
+            disposition,
+            upgrades,
+        })
+    }
+
+    // lowest and highest value the attribute can roll at max rank
+    pub fn value_range(
+        &self,
+        url_name: &str,
+        buff_count: usize,
+        cursed: bool,
+        positive: bool,
+    ) -> Option<(f64, f64)> {
+        let upgrade = self
+            .upgrades
+            .iter()
+            .find(|upgr| upgr.url_name.as_ref() == url_name)?;
+        let (good, bad) = attribute_multipliers(buff_count, cursed)?;
+        let multiplier = if positive { good } else { bad };
+        let value = |x: f64| {
+            attribute_value(
+                x,
+                upgrade.base_value,
+                self.disposition,
+                multiplier,
+                MAX_RANK,
+                &upgrade.units,
+            )
+        };
+        Some((value(0.9), value(1.1)))
+    }
+
+    // chance a single reroll satisfies the rule. rerolls are two or three
+    // positives with or without a curse, each equally likely, and every stat
+    // is drawn uniformly from the ones that weren't picked yet
+    pub fn roll_odds(&self, rule: &RollRule) -> f64 {
+        let names: Vec<&str> = self
+            .upgrades
+            .iter()
+            .map(|upgr| upgr.url_name.as_ref())
+            .collect();
+        [2, 3]
+            .into_iter()
+            .map(|buff_count| {
+                let combos = combinations(names.len(), buff_count);
+                if combos.is_empty() {
+                    return 0.0;
+                }
+                let (uncursed, cursed) =
+                    combos.iter().fold((0.0, 0.0), |(uncursed, cursed), combo| {
+                        let positives: Vec<&str> = combo.iter().map(|&i| names[i]).collect();
+                        let curses: Vec<&str> = names
+                            .iter()
+                            .filter(|name| !positives.contains(name))
+                            .copied()
+                            .collect();
+                        let uncursed_hit = rule.matches_roll(&positives, None) as u8 as f64;
+                        let cursed_hit = if curses.is_empty() {
+                            0.0
+                        } else {
+                            curses
+                                .iter()
+                                .filter(|&&curse| rule.matches_roll(&positives, Some(curse)))
+                                .count() as f64
+                                / curses.len() as f64
+                        };
+                        (uncursed + uncursed_hit, cursed + cursed_hit)
+                    });
+                let total = combos.len() as f64;
+                0.25 * uncursed / total + 0.25 * cursed / total
+            })
+            .sum()
+    }
+}
+
+fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![vec![]];
+    }
+    if n < k {
+        return vec![];
+    }
+    // every combination either has the last index or it doesn't
+    let mut with_last: Vec<Vec<usize>> = combinations(n - 1, k - 1)
+        .into_iter()
+        .map(|mut combo| {
+            combo.push(n - 1);
+            combo
+        })
+        .collect();
+    with_last.extend(combinations(n - 1, k));
+    with_last
+}
+
+// kuva it takes on average to hit something with `odds` per reroll, starting
+// from a riven that was rerolled `re_rolls` times
+pub fn expected_kuva(re_rolls: i32, odds: f64) -> Option<f64> {
+    if odds <= 0.0 {
+        return None;
+    }
+    let miss = 1.0 - odds;
+    let start = re_rolls.max(0) as usize;
+    let last = KUVA_COSTS.len() - 1;
+    // the nth reroll only happens when all the ones before it missed
+    let (expected, reach) = (start..last).fold((0.0, 1.0), |(expected, reach), nth| {
+        (expected + reach * KUVA_COSTS[nth] as f64, reach * miss)
+    });
+    Some(expected + reach * KUVA_COSTS[last] as f64 / odds)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::rivens::inventory::convert_raw_inventory::{Attribute, Item, Units};
+
+    use super::{
+        combinations, expected_kuva, grade_riven, RollGrade, RollRule, RollableUpgrade,
+        WeaponRolls,
+    };
+
+    fn rolls(names: &[&str]) -> WeaponRolls {
+        WeaponRolls {
+            disposition: 1.0,
+            upgrades: names
+                .iter()
+                .map(|name| RollableUpgrade {
+                    url_name: (*name).into(),
+                    short_string: "".into(),
+                    base_value: 0.1,
+                    units: Units::Percent,
+                })
+                .collect(),
+        }
+    }
+
+    fn rule(grade: RollGrade, positives: &[&str], negatives: &[&str]) -> RollRule {
+        RollRule {
+            weapon_url_name: "skana".into(),
+            grade,
+            positives: positives.iter().map(|&p| Arc::from(p)).collect(),
+            negatives: negatives.iter().map(|&n| Arc::from(n)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_roll_odds() {
+        assert_eq!(combinations(5, 3).len(), 10);
+
+        let rolls = rolls(&["a", "b", "c", "d", "e"]);
+        // with five stats any rule without requirements always hits
+        assert!((rolls.roll_odds(&rule(RollGrade::Decent, &[], &[])) - 1.0).abs() < 1e-9);
+
+        // "a" is a positive in 2 of 5 two stat rolls and 3 of 5 three stat rolls
+        let odds = rolls.roll_odds(&rule(RollGrade::Decent, &["a"], &[]));
+        assert!((odds - 0.5).abs() < 1e-9);
+
+        // cursed rolls also need the curse to be "e", which is a third of
+        // the two stat rolls with "a" and half of the three stat ones
+        let odds = rolls.roll_odds(&rule(RollGrade::Decent, &["a"], &["e"]));
+        assert!((odds - 0.25 * (0.4 + 0.1 + 0.6 + 0.15)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_expected_kuva() {
+        assert_eq!(expected_kuva(0, 0.0), None);
+        assert_eq!(expected_kuva(0, 1.0), Some(900.0));
+        assert_eq!(expected_kuva(20, 1.0), Some(3500.0));
+        assert_eq!(expected_kuva(20, 0.5), Some(7000.0));
+    }
+
+    #[test]
+    fn test_grade_riven() {
+        let attr = |url_name: &str, positive: bool| Attribute {
+            value: 1.0,
+            positive,
+            url_name: url_name.into(),
+            units: Units::Percent,
+            short_string: "".into(),
+            roll: None,
+        };
+        let item = Item {
+            weapon_url_name: "skana".into(),
+            attributes: vec![attr("a", true), attr("b", true), attr("c", false)],
+            ..Default::default()
+        };
+        let rules = vec![
+            rule(RollGrade::GodRoll, &["a", "b"], &["d"]),
+            rule(RollGrade::Decent, &["a"], &[]),
+        ];
+
+        assert_eq!(grade_riven(&item, &rules), Some(RollGrade::Decent));
+        assert_eq!(grade_riven(&item, &rules[..1]), Some(RollGrade::Dissolve));
+        assert_eq!(grade_riven(&item, &[]), None);
+    }
+}
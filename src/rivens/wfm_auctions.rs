@@ -1,10 +1,241 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+
+use crate::{
+    http_client::wfm_client::WFMClient,
+    rivens::inventory::{convert_raw_inventory::Item, database::database::{Auction, InventoryDB}},
+    AppError,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WFMAuction {
+    pub id: Arc<str>,
+    pub starting_price: Option<u32>,
+    pub buyout_price: Option<u32>,
+    // a plain user id when creating auctions, the whole profile when listing them
+    #[serde(default)]
+    pub owner: Value,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub updated: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub is_direct_sell: bool,
+    #[serde(default)]
+    pub closed: bool,
+    pub item: WFMAuctionItem,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WFMAuctionItem {
+    #[serde(rename = "type")]
+    pub item_type: Arc<str>,
+    pub weapon_url_name: Option<Arc<str>>,
+    #[serde(default)]
+    pub attributes: Vec<WFMAttribute>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WFMAttribute {
+    pub url_name: Arc<str>,
+    pub value: f64,
+    pub positive: bool,
+}
+
+impl WFMAuction {
+    fn owner_id(&self) -> Option<String> {
+        match &self.owner {
+            Value::String(id) => Some(id.clone()),
+            Value::Object(profile) => profile
+                .get("id")
+                .and_then(|id| id.as_str())
+                .map(|id| id.to_string()),
+            _ => None,
+        }
+    }
+
+    fn into_auction(self, oid: &str) -> Auction {
+        Auction {
+            starting_price: self.starting_price,
+            buyout_price: self.buyout_price,
+            owner: self.owner_id(),
+            updated: self.updated,
+            is_direct_sell: self.is_direct_sell,
+            id: Some(self.id.to_string()),
+            oid: oid.into(),
+        }
+    }
+}
+
+// how far apart the attribute values of an auction and an item are, or `None`
+// when they don't roll the same stats on the same weapon
+fn match_distance(auction: &WFMAuctionItem, item: &Item) -> Option<f64> {
+    if auction.weapon_url_name.as_deref() != Some(item.weapon_url_name.as_ref()) {
+        return None;
+    }
+    if auction.attributes.len() != item.attributes.len() {
+        return None;
+    }
+    auction.attributes.iter().try_fold(0.0, |acc, wfm_attr| {
+        let attr = item.attributes.iter().find(|attr| {
+            attr.url_name == wfm_attr.url_name.as_ref() && attr.positive == wfm_attr.positive
+        })?;
+        Some(acc + (attr.value - wfm_attr.value).abs())
+    })
+}
+
+// listings are matched to the riven they were stored for first, the rest by
+// their stats. open listings that don't match any riven in the inventory are
+// returned too, their riven was sold or dissolved
+pub fn match_auctions(
+    items: &[Item],
+    stored: &[Auction],
+    auctions: Vec<WFMAuction>,
+) -> (Vec<Auction>, Vec<WFMAuction>) {
+    let mut matched: Vec<Auction> = Vec::with_capacity(auctions.len());
+    let mut unmatched = vec![];
+    let (known, rest): (Vec<_>, Vec<_>) = auctions
+        .into_iter()
+        .filter(|auc| !auc.closed && auc.item.item_type.as_ref() == "riven")
+        .partition(|auc| stored.iter().any(|s| s.id.as_deref() == Some(auc.id.as_ref())));
+    known.into_iter().for_each(|auc| {
+        let oid = stored
+            .iter()
+            .find(|s| s.id.as_deref() == Some(auc.id.as_ref()))
+            .map(|s| s.oid.clone())
+            .unwrap_or_default();
+        if items.iter().any(|item| item.oid.as_ref() == oid) {
+            matched.push(auc.into_auction(&oid));
+        } else {
+            unmatched.push(auc);
+        }
+    });
+    rest.into_iter().for_each(|auc| {
+        // rivens with identical stats are told apart by their values, the
+        // closest one that isn't taken yet gets the auction
+        let closest = items
+            .iter()
+            .filter(|item| matched.iter().all(|m| m.oid != item.oid.as_ref()))
+            .filter_map(|item| match_distance(&auc.item, item).map(|dist| (item, dist)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        match closest {
+            Some((item, _)) => matched.push(auc.into_auction(&item.oid)),
+            None => unmatched.push(auc),
+        }
+    });
+    (matched, unmatched)
+}
+
+// closes the riven's listing, if it has one, and forgets the riven. the row
+// is kept when closing fails so the listing isn't lost track of
+pub async fn close_auction(
+    oid: &str,
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(), AppError> {
+    let auction = {
+        let db = db.lock().await;
+        let db = match db.as_ref() {
+            Some(v) => v,
+            None => return Err(AppError::new("Database is closed".into(), "close_auction".into())),
+        };
+        db.select_auction(oid)
+            .map_err(|e| AppError::new(e.to_string(), "close_auction: select_auction".into()))?
+    };
+
+    if let Some(wfm_id) = auction.and_then(|auc| auc.id) {
+        let mut wfm = wfm.lock().await.clone();
+        wfm.close_auction(&wfm_id)
+            .await
+            .map_err(|e| e.prop("close_auction".into()))?;
+    }
+
+    let mut db = db.lock().await;
+    match db.as_mut() {
+        Some(db) => db
+            .delete_items_auctions(vec![oid.into()])
+            .map_err(|e| AppError::new(e.to_string(), "close_auction: delete_items_auctions".into())),
+        None => Err(AppError::new("Database is closed".into(), "close_auction".into())),
+    }
+}
+
+// for the ids a sync removed, their rivens aren't in the inventory anymore.
+// an inventory that came back empty is more likely unreadable than sold off,
+// so nothing is closed then
+pub async fn close_removed_auctions(
+    synced: &[Item],
+    removed: &[Arc<str>],
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) {
+    if synced.is_empty() && !removed.is_empty() {
+        eprintln!(
+            "WARNING: The inventory is empty, not closing the auctions of {} removed rivens",
+            removed.len()
+        );
+        return;
+    }
+    for oid in removed {
+        if let Err(e) = close_auction(oid, wfm.clone(), db.clone()).await {
+            eprintln!("ERROR: Could not close the auction of removed riven {oid}: {e}");
+        }
+    }
+}
+
+// returns how many auctions were matched, and the open ones that match no
+// riven in the inventory for the caller to report. those aren't closed, their
+// rows are kept so they can still be closed later
+pub async fn import_auctions(
+    wfm: Arc<Mutex<WFMClient>>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+) -> Result<(usize, Vec<WFMAuction>), AppError> {
+    let wfm_auctions = {
+        let mut wfm = wfm.lock().await.clone();
+        wfm.get_user_auctions()
+            .await
+            .map_err(|e| e.prop("import_auctions".into()))?
+    };
+
+    let mut db = db.lock().await;
+    let db = match db.as_mut() {
+        Some(v) => v,
+        None => return Err(AppError::new("Database is closed".into(), "import_auctions".into())),
+    };
+    let items = db
+        .select_items()
+        .map_err(|e| AppError::new(e.to_string(), "import_auctions: select_items".into()))?;
+    let stored = db
+        .select_auctions()
+        .map_err(|e| AppError::new(e.to_string(), "import_auctions: select_auctions".into()))?;
+
+    let (auctions, unmatched) = match_auctions(&items, &stored, wfm_auctions);
+
+    // auctions that were closed on the site since the last run
+    stored
+        .iter()
+        .filter(|auc| auctions.iter().all(|new| new.oid != auc.oid))
+        .filter(|auc| unmatched.iter().all(|open| auc.id.as_deref() != Some(open.id.as_ref())))
+        .try_for_each(|auc| db.delete_auction(&auc.oid))
+        .map_err(|e| AppError::new(e.to_string(), "import_auctions: delete_auction".into()))?;
+    db.insert_auctions(&auctions)
+        .map_err(|e| AppError::new(e.to_string(), "import_auctions: insert_auctions".into()))?;
+    Ok((auctions.len(), unmatched))
+}
+
 #[cfg(test)]
 mod tests {
     use time::macros::datetime;
 
-    #[test]
-    fn test_date_time() {
-        let _input = r#"{
+    use crate::rivens::inventory::{
+        convert_raw_inventory::{Attribute, Item, Units},
+        database::database::Auction,
+    };
+
+    use super::{match_auctions, WFMAuction};
+
+    static INPUT: &str = r#"{
         "starting_price": 160,
         "minimal_reputation": 0,
         "item": {
@@ -56,8 +287,75 @@ mod tests {
         "oid": "66368ad69454320dffff15f1",
         "private": false
       }"#;
-        let _sample = datetime!(2024-05-04 19:21:58.000+00:00);
 
-        // assert_eq!(sample, auction.updated.unwrap())
+    fn sample_item(oid: &str, toxin: f64) -> Item {
+        let attr = |url_name: &str, value: f64, positive: bool| Attribute {
+            value,
+            positive,
+            url_name: url_name.into(),
+            units: Units::Percent,
+            short_string: "".into(),
+            roll: None,
+        };
+        Item {
+            weapon_url_name: "skana".into(),
+            attributes: vec![
+                attr("fire_rate_/_attack_speed", 7.7, true),
+                attr("finisher_damage", 16.1, true),
+                attr("toxin_damage", toxin, true),
+                attr("status_chance", -10.4, false),
+            ],
+            oid: oid.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_date_time() {
+        let auction: WFMAuction = serde_json::from_str(INPUT).unwrap();
+        let sample = datetime!(2024-05-04 19:21:58.000+00:00);
+
+        assert_eq!(sample, auction.updated.unwrap())
+    }
+
+    #[test]
+    fn test_match_auctions() {
+        let auction: WFMAuction = serde_json::from_str(INPUT).unwrap();
+        let items = vec![
+            sample_item("further_off", 9.0),
+            sample_item("closest", 12.4),
+            Item {
+                weapon_url_name: "kronen".into(),
+                ..sample_item("other_weapon", 12.5)
+            },
+        ];
+        let (matched, unmatched) = match_auctions(&items, &[], vec![auction]);
+
+        assert!(unmatched.is_empty());
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].oid, "closest");
+        assert_eq!(matched[0].id.as_deref(), Some("66368ad69454320dffff15f1"));
+        assert_eq!(matched[0].owner.as_deref(), Some("6457e7aa3545810677d216a5"));
+        assert_eq!(matched[0].buyout_price, Some(160));
+    }
+
+    #[test]
+    fn test_match_stored_auctions() {
+        let auction: WFMAuction = serde_json::from_str(INPUT).unwrap();
+        let items = vec![sample_item("closest", 12.4), sample_item("rerolled", 3.0)];
+        let stored = |oid: &str| Auction {
+            id: Some("66368ad69454320dffff15f1".into()),
+            oid: oid.into(),
+            ..Default::default()
+        };
+
+        // the stored riven keeps its auction even when another one is closer
+        let (matched, _) = match_auctions(&items, &[stored("rerolled")], vec![auction.clone()]);
+        assert_eq!(matched[0].oid, "rerolled");
+
+        // the riven left the inventory, so the auction has to be closed
+        let (matched, unmatched) = match_auctions(&items, &[stored("sold")], vec![auction]);
+        assert!(matched.is_empty());
+        assert_eq!(unmatched[0].id.as_ref(), "66368ad69454320dffff15f1");
     }
 }
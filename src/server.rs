@@ -1,146 +1,265 @@
-use ascii::AsciiString;
-use dotenv::dotenv;
-use once_cell::sync::OnceCell;
-use std::{ops::DerefMut, sync::Arc, thread};
-use tiny_http::{Request, Server};
-use tokio::{select, sync::{broadcast::{self, Receiver}, Mutex}};
+use std::sync::{Arc, RwLock};
+use tiny_http::{Method, Request, Server};
+use tokio::{select, sync::{broadcast::Receiver, watch, Mutex}};
 
 use crate::{
-    api_operations::{uri_api_blacklist_riven, uri_api_delete_riven, uri_api_login, uri_api_update_riven}, http_client::{auth_state::AuthState, qf_client::QFClient, wfm_client::WFMClient}, pages::{
+    api_operations::{uri_api_add_account, uri_api_blacklist_riven, uri_api_delete_riven, uri_api_delete_roll_rule, uri_api_login, uri_api_logout, uri_api_save_roll_rule, uri_api_switch_account, uri_api_unblacklist_riven, uri_api_update_riven}, http_client::{auth_state::AuthState, qf_client::QFClient, session_refresh::keep_sessions_alive, wfm_client::WFMClient}, pages::{
+        accounts::uri_accounts,
+        blacklist::uri_blacklist,
         home::{
-            uri_edit_cancel, uri_edit_open, uri_home, uri_main, uri_not_found, uri_unauthorized,
+            uri_bulk_edit_open, uri_edit_cancel, uri_edit_open, uri_forbidden, uri_home, uri_main,
+            uri_not_found, uri_unauthorized,
         },
-        login::uri_login,
-    }, resources::{uri_htmx, uri_logo, uri_styles, uri_wfmlogo}, rivens::inventory::riven_lookop::RivenDataLookup, websocket::start_websocket, AppError, StopSignal
+        login::{uri_login, uri_session, uri_session_status},
+        reroll::uri_reroll,
+    }, resources::{uri_htmx, uri_logo, uri_styles, uri_wfmlogo}, rivens::{inventory::{database::{database::InventoryDB, inventory_sync::sync_db}, riven_lookop::RivenDataLookup}, wfm_auctions::{close_removed_auctions, import_auctions}}, websocket::start_websocket, config::Config, json_api::uri_api_v1, session::{Credentials, Session, CSRF_HEADER}, AppError, StopSignal
 };
 
-#[derive(Debug)]
-struct User(AsciiString);
+// loaded again on every login, so it can be swapped out while requests are
+// still using the old one
+static RIVEN_LOOKUP: RwLock<Option<Arc<RivenDataLookup>>> = RwLock::new(None);
 
-static USER: OnceCell<User> = OnceCell::new();
-pub static RIVEN_LOOKUP: OnceCell<RivenDataLookup> = OnceCell::new();
+pub fn riven_lookup() -> Option<Arc<RivenDataLookup>> {
+    RIVEN_LOOKUP.read().expect("riven lookup lock poisoned").clone()
+}
 
-async fn recv_request(server: &Server) -> tiny_http::Request {
-    server.recv().unwrap()
+pub fn set_riven_lookup(lookup: RivenDataLookup) {
+    *RIVEN_LOOKUP.write().expect("riven lookup lock poisoned") = Some(Arc::new(lookup));
 }
 
-struct ServerState<'a> {
-    server: &'a Server,
-    stop_receiver: Receiver<StopSignal>,
+// everything a request handler needs, cheap to clone into each request's task
+#[derive(Clone)]
+struct ServerState {
+    auth_state: Arc<Mutex<AuthState>>,
     wfm_client: Arc<Mutex<WFMClient>>,
     qf_client: Arc<Mutex<QFClient>>,
-    logged_in: Option<bool>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+    config: Arc<Config>,
+    // `None` until the user has logged in or an old session was validated,
+    // `Some(false)` after logging out. every login or account switch sends
+    // `Some(true)` again
+    logged_in: Arc<watch::Sender<Option<bool>>>,
+    session: Arc<Session>,
 }
 
-pub async fn start_server(stop_receiver: Receiver<StopSignal>) -> Result<(), AppError> {
-    dotenv().expect("FATAL: Could not load envvars from `.env`");
-    let server = tiny_http::Server::http("127.0.0.1:8000").unwrap();
-    let logged_in: Option<bool> = None;
-    println!("SERVER STARTED");
+pub async fn start_server(
+    stop_receiver: Receiver<StopSignal>,
+    config: Arc<Config>,
+    session: Arc<Session>,
+) -> Result<(), AppError> {
+    let _ = tokio_rustls::rustls::crypto::ring::default_provider().install_default();
+    let server = tiny_http::Server::http((config.server.host.as_str(), config.server.port))
+        .map_err(|e| AppError::new(e.to_string(), "start_server: Server::http".into()))?;
+    let server = Arc::new(server);
+    eprintln!("SERVER STARTED");
 
-    let auth_state = AuthState::setup().map_err(|e| e.prop("start_server".into()))?;
+    let auth_state = AuthState::setup(&config.paths.auth, config.secret_key())
+        .map_err(|e| e.prop("start_server".into()))?
+        .profiles_dir(&config.paths.profiles);
     let auth_state = Arc::new(Mutex::new(auth_state));
 
-    let wfm_client = WFMClient::new(auth_state.clone(), stop_receiver.resubscribe());
+    let response_cache = config.response_cache();
+    let wfm_client = WFMClient::new(auth_state.clone(), stop_receiver.resubscribe())
+        .http_config(&config.http)
+        .response_cache(response_cache.clone());
     let wfm_client = Arc::new(Mutex::new(wfm_client));
 
-    let qf_client = QFClient::new(auth_state, stop_receiver.resubscribe());
+    let qf_client = QFClient::new(auth_state.clone(), stop_receiver.resubscribe())
+        .http_config(&config.http)
+        .response_cache(response_cache);
     let qf_client = Arc::new(Mutex::new(qf_client));
 
-    let receiver_clone = stop_receiver.resubscribe();
+    tokio::task::spawn(keep_sessions_alive(
+        auth_state.clone(),
+        wfm_client.clone(),
+        qf_client.clone(),
+        stop_receiver.resubscribe(),
+    ));
+
+    let db = InventoryDB::open(&config.paths.database)
+        .map_err(|e| AppError::new(e.to_string(), "start_server: InventoryDB::open".to_string()))?;
+    let db = Arc::new(Mutex::new(Some(db)));
+
+    let (logged_in, _) = watch::channel(None);
     let server_state = ServerState {
-        server: &server,
-        stop_receiver,
+        auth_state,
         wfm_client,
         qf_client,
-        logged_in,
+        db,
+        config,
+        logged_in: Arc::new(logged_in),
+        session,
     };
-    let server_state = Arc::new(Mutex::new(server_state));
 
-    handle_request(server_state.clone(), false).await.map_err(|e| e.prop("start_server".into()))?;
+    tokio::task::spawn(start_inventory(server_state.clone(), stop_receiver.resubscribe()));
+    serve(server, server_state, stop_receiver).await;
+    Ok(())
+}
 
-    thread::spawn(move || start_websocket(receiver_clone));
+// syncs the inventory and imports the account's auctions on every login, the
+// lookup data needed for both is loaded on login. the websocket is started
+// after the first one
+async fn start_inventory(server_state: ServerState, mut stop_receiver: Receiver<StopSignal>) {
+    let mut logged_in = server_state.logged_in.subscribe();
+    let ServerState { wfm_client, db, config, session, .. } = server_state;
+    let mut websocket_started = false;
+    loop {
+        select! {
+            res = logged_in.wait_for(|v| *v == Some(true)) => if res.is_err() { return },
+            _ = stop_receiver.recv() => return,
+        }
 
-    let _ = tokio_rustls::rustls::crypto::ring::default_provider().install_default();
+        // the inventory has to be in the database before auctions can be matched to it
+        let lookup = riven_lookup().expect("FATAL: Could not access lookup data");
+        // rivens sold or dissolved while the app was closed take their listings with them
+        match sync_db(db.clone(), &lookup, &config, None).await {
+            Ok((items, removed)) => close_removed_auctions(&items, &removed, wfm_client.clone(), db.clone()).await,
+            Err(e) => eprintln!("ERROR: Could not sync inventory database: {e:?}"),
+        }
+        match import_auctions(wfm_client.clone(), db.clone()).await {
+            Ok((count, unmatched)) => {
+                eprintln!("INFO: Imported {count} auctions from warframe.market");
+                unmatched.iter().for_each(|auc| {
+                    eprintln!("WARNING: Auction {} doesn't match any riven in the inventory", auc.id)
+                });
+            }
+            Err(e) => eprintln!("ERROR: Could not import auctions: {e}"),
+        }
 
-    handle_request(server_state, true).await.map_err(|e| e.prop("start_server".into()))?;
-    Ok(())
+        if !websocket_started {
+            tokio::task::spawn(start_websocket(
+                stop_receiver.resubscribe(),
+                db.clone(),
+                wfm_client.clone(),
+                config.clone(),
+                session.clone(),
+            ));
+            websocket_started = true;
+        }
+
+        // the next login or account switch
+        select! {
+            res = logged_in.changed() => if res.is_err() { return },
+            _ = stop_receiver.recv() => return,
+        }
+    }
 }
 
-async fn handle_request<'a>(server_state: Arc<Mutex<ServerState<'a>>>, main_loop: bool) -> Result<(), AppError> {
-    let mut server_state_mutex = server_state.lock().await;
-    let server_state = server_state_mutex.deref_mut();
-    while main_loop || server_state.logged_in.is_none() {
+// every request is handled on tokio's blocking pool, so a slow login doesn't
+// hold up pages and assets. stopping only waits for the pending `recv`
+async fn serve(server: Arc<Server>, server_state: ServerState, mut stop_receiver: Receiver<StopSignal>) {
+    loop {
+        let recv_server = server.clone();
         select! {
-            mut rq = recv_request(server_state.server) => {
-                println!(
-                    "received request! method: {:?}, url: {:?}",
-                    rq.method(),
-                    rq.url(),
-                );
-                if let Some(User(user)) = USER.get() {
-                    if &rq
-                        .headers()
-                        .iter()
-                        .find(|&v| v.field.equiv("User-Agent"))
-                        .unwrap()
-                    .value
-                    != user
-                    {
-                        uri_unauthorized(rq).unwrap();
+            rq = tokio::task::spawn_blocking(move || recv_server.recv()) => {
+                let rq = match rq {
+                    Ok(Ok(rq)) => rq,
+                    Ok(Err(e)) => {
+                        eprintln!("ERROR: Could not receive request: {e}");
                         continue;
                     }
-                } else {
-                    let head = rq.headers().iter().find(|&v| v.field.equiv("User-Agent"));
-                    let head = head.unwrap().value.clone();
-                    USER.set(User(head)).unwrap();
-                }
-                let mut body = String::new();
-                rq.as_reader().read_to_string(&mut body).unwrap();
-                let uri = rq.url().to_owned();
-                match_request(
-                    rq,
-                    uri.as_str(),
-                    server_state.wfm_client.clone(),
-                    server_state.qf_client.clone(),
-                    Some(body.as_str()),
-                    &mut server_state.logged_in,
-                )
-                    .map_err(|e| e.prop("start_server: spawn".into()))?;
+                    // `unblock` was called or the runtime is shutting down
+                    Err(_) => break,
+                };
+                let server_state = server_state.clone();
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = handle_request(rq, &server_state) {
+                        eprintln!("ERROR: {e}");
+                    }
+                });
+            }
+            _ = stop_receiver.recv() => {
+                server.unblock();
+                eprintln!("SERVER CLOSED");
+                break;
             }
-                _ = server_state.stop_receiver.recv() => {println!("SERVER CLOSED"); break;}
         }
     }
-    Ok(())
+}
+
+fn handle_request(mut rq: Request, server_state: &ServerState) -> Result<(), AppError> {
+    eprintln!(
+        "received request! method: {:?}, url: {:?}",
+        rq.method(),
+        rq.url(),
+    );
+    let session = &server_state.session;
+    let url = rq.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    if path == "/session" {
+        return uri_session(rq, query, session)
+            .map_err(|e| AppError::new(e.to_string(), "handle_request".into()));
+    }
+
+    let header = |name: &'static str| {
+        rq.headers()
+            .iter()
+            .find(|&v| v.field.equiv(name))
+            .map(|v| v.value.to_string())
+    };
+    let credentials = session.authenticate(header("Cookie").as_deref(), header("Authorization").as_deref());
+    let credentials = match credentials {
+        Some(v) => v,
+        None => {
+            return uri_unauthorized(rq)
+                .map_err(|e| AppError::new(e.to_string(), "handle_request".into()))
+        }
+    };
+    // a page on another site can make the browser send the cookie, but it
+    // can't read the csrf token out of our pages
+    let changes_state = !matches!(rq.method(), Method::Get | Method::Head | Method::Options);
+    if changes_state
+        && credentials == Credentials::Cookie
+        && !session.check_csrf(header(CSRF_HEADER).as_deref())
+    {
+        return uri_forbidden(rq)
+            .map_err(|e| AppError::new(e.to_string(), "handle_request".into()));
+    }
+
+    let mut body = String::new();
+    rq.as_reader()
+        .read_to_string(&mut body)
+        .map_err(|e| AppError::new(e.to_string(), "handle_request: read_to_string".into()))?;
+    let uri = rq.url().to_owned();
+    match_request(rq, uri.as_str(), Some(body.as_str()), server_state)
+        .map_err(|e| e.prop("handle_request".into()))
 }
 
 fn match_request(
     rq: Request,
     uri: &str,
-    wfm: Arc<Mutex<WFMClient>>,
-    qf: Arc<Mutex<QFClient>>,
     body: Option<&str>,
-    logged_in: &mut Option<bool>,
+    server_state: &ServerState,
 ) -> Result<(), AppError> {
+    let auth = server_state.auth_state.clone();
+    let wfm = server_state.wfm_client.clone();
+    let qf = server_state.qf_client.clone();
+    let db = server_state.db.clone();
+    let (config, session, logged_in) = (&*server_state.config, &*server_state.session, &*server_state.logged_in);
     let (root, other) = uri[1..].split_once('/').unwrap_or((&uri[1..], ""));
     let (root, _) = root.split_once('?').unwrap_or((root, ""));
     match root {
-        "" | "/" => uri_main(rq, wfm, qf, logged_in).map_err(|e| e.prop("handle_request".into())),
+        "" | "/" => uri_main(rq, wfm, qf, config, session, logged_in).map_err(|e| e.prop("handle_request".into())),
         "htmx.min.js" => {
             uri_htmx(rq).map_err(|e| AppError::new(e.to_string(), "handle_request".to_string()))
         }
         "styles.css" => {
             uri_styles(rq).map_err(|e| AppError::new(e.to_string(), "handle_request".to_string()))
         }
-        "api" => match_uri_api(rq, other, body, wfm, qf, logged_in)
+        "api" => match_uri_api(rq, other, body, server_state)
             .map_err(|e| e.prop("handle_request".into())),
         "login" => {
             uri_login(rq).map_err(|e| AppError::new(e.to_string(), "handle_request".to_string()))
         }
+        "session_status" => uri_session_status(rq, wfm, logged_in).map_err(|e| e.prop("handle_request".into())),
         "home" => {
-            uri_home(rq).map_err(|e| AppError::new(e.to_string(), "handle_request".to_string()))
+            uri_home(rq, config).map_err(|e| AppError::new(e.to_string(), "handle_request".to_string()))
         }
-        "edit_open" => uri_edit_open(rq, other)
+        "accounts" => uri_accounts(rq, auth).map_err(|e| e.prop("handle_request".into())),
+        "blacklist" => uri_blacklist(rq, db).map_err(|e| e.prop("handle_request".into())),
+        "reroll" => uri_reroll(rq, db, None).map_err(|e| e.prop("handle_request".into())),
+        "edit_open" => uri_edit_open(rq, other, wfm, db).map_err(|e| e.prop("handle_request".into())),
+        "bulk_edit_open" => uri_bulk_edit_open(rq, body)
             .map_err(|e| AppError::new(e.to_string(), "handle_request".to_string())),
         "edit_cancel" => uri_edit_cancel(rq)
             .map_err(|e| AppError::new(e.to_string(), "handle_request".to_string())),
@@ -159,26 +278,45 @@ fn match_uri_api(
     rq: Request,
     uri: &str,
     body: Option<&str>,
-    wfm: Arc<Mutex<WFMClient>>,
-    qf: Arc<Mutex<QFClient>>,
-    logged_in: &mut Option<bool>,
+    server_state: &ServerState,
 ) -> Result<(), AppError> {
+    let auth = server_state.auth_state.clone();
+    let wfm = server_state.wfm_client.clone();
+    let qf = server_state.qf_client.clone();
+    let db = server_state.db.clone();
+    let (config, logged_in) = (&*server_state.config, &*server_state.logged_in);
     let (root, other) = uri.split_once('/').unwrap_or((uri, ""));
-    let (other, _) = other.split_once('?').unwrap_or((other, ""));
+    let (other, query) = other.split_once('?').unwrap_or((other, ""));
     match root {
-        "login" => uri_api_login(rq, body.unwrap(), wfm.clone(), qf, logged_in)
+        "v1" => uri_api_v1(rq, other, query, db).map_err(|e| e.prop("match_uri_api".into())),
+        "login" => uri_api_login(rq, body.unwrap_or_default(), wfm.clone(), qf, config, logged_in)
+            .map_err(|e| e.prop("match_uri_api".into())),
+        "logout" => uri_api_logout(rq, auth, logged_in).map_err(|e| e.prop("match_uri_api".into())),
+        "add_account" => {
+            uri_api_add_account(rq, auth, logged_in).map_err(|e| e.prop("match_uri_api".into()))
+        }
+        "switch_account" => uri_api_switch_account(rq, other, auth, qf, config, logged_in)
             .map_err(|e| e.prop("match_uri_api".into())),
         "delete_riven" => {
-            uri_api_delete_riven(rq, other).map_err(|e| e.prop("match_uri_api".into()))
+            uri_api_delete_riven(rq, other, wfm, db).map_err(|e| e.prop("match_uri_api".into()))
         }
         "blacklist_riven" => {
-            uri_api_blacklist_riven(rq, other).map_err(|e| e.prop("match_uri_api".into()))
+            uri_api_blacklist_riven(rq, other, wfm, db).map_err(|e| e.prop("match_uri_api".into()))
+        }
+        "unblacklist_riven" => {
+            uri_api_unblacklist_riven(rq, other, db).map_err(|e| e.prop("match_uri_api".into()))
+        }
+        "save_roll_rule" => {
+            uri_api_save_roll_rule(rq, body, db).map_err(|e| e.prop("match_uri_api".into()))
+        }
+        "delete_roll_rule" => {
+            uri_api_delete_roll_rule(rq, other, db).map_err(|e| e.prop("match_uri_api".into()))
         }
         "update_single_riven" => {
-            uri_api_update_riven(rq, false, other, body).map_err(|e| e.prop("match_uri_api".into()))
+            uri_api_update_riven(rq, false, other, body, wfm, db).map_err(|e| e.prop("match_uri_api".into()))
         }
         "update_mult_riven" => {
-            uri_api_update_riven(rq, true, other, body).map_err(|e| e.prop("match_uri_api".into()))
+            uri_api_update_riven(rq, true, other, body, wfm, db).map_err(|e| e.prop("match_uri_api".into()))
         }
         _ => uri_not_found(rq)
             .map_err(|e| AppError::new(e.to_string(), "handle_request".to_string())),
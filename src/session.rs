@@ -0,0 +1,118 @@
+use rand::{rngs::OsRng, RngCore};
+
+pub static SESSION_COOKIE: &str = "rhr_session";
+pub static CSRF_HEADER: &str = "X-CSRF-Token";
+
+// how a request proved it belongs to the session
+#[derive(Debug, PartialEq)]
+pub enum Credentials {
+    Cookie,
+    // scripts send the token themselves, so they can't be tricked into
+    // making requests the way a browser can
+    Bearer,
+}
+
+// a random token per launch that the webview gets through its start url and
+// keeps as a cookie, anything else has to present it as well
+pub struct Session {
+    token: String,
+    csrf_token: String,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// doesn't return early on the first differing byte
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn cookie_value<'a>(cookies: &'a str, name: &str) -> Option<&'a str> {
+    cookies
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            token: random_token(),
+            csrf_token: random_token(),
+        }
+    }
+
+    // the url that sets the session cookie and then redirects to the ui
+    pub fn start_url(&self, base_url: &str) -> String {
+        format!("{base_url}/session?token={}", self.token)
+    }
+
+    #[cfg(test)]
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn csrf_token(&self) -> &str {
+        &self.csrf_token
+    }
+
+    // `hx-headers` value that makes htmx send the csrf token with every request
+    pub fn csrf_hx_headers(&self) -> String {
+        format!("{{\"{CSRF_HEADER}\": \"{}\"}}", self.csrf_token)
+    }
+
+    pub fn set_cookie(&self) -> String {
+        format!("{SESSION_COOKIE}={}; Path=/; HttpOnly; SameSite=Strict", self.token)
+    }
+
+    pub fn check_token(&self, token: &str) -> bool {
+        tokens_match(token, &self.token)
+    }
+
+    pub fn authenticate(
+        &self,
+        cookies: Option<&str>,
+        authorization: Option<&str>,
+    ) -> Option<Credentials> {
+        if let Some(token) = authorization.and_then(|v| v.strip_prefix("Bearer ")) {
+            return self.check_token(token.trim()).then_some(Credentials::Bearer);
+        }
+        let token = cookies.and_then(|cookies| cookie_value(cookies, SESSION_COOKIE))?;
+        self.check_token(token).then_some(Credentials::Cookie)
+    }
+
+    pub fn check_csrf(&self, header: Option<&str>) -> bool {
+        header.is_some_and(|token| tokens_match(token, &self.csrf_token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Credentials, Session};
+
+    #[test]
+    fn test_session() {
+        let session = Session::new();
+        let other = Session::new();
+        assert_eq!(session.token.len(), 64);
+        assert_ne!(session.token, other.token);
+
+        let cookie = format!("theme=dark; rhr_session={}", session.token);
+        let bearer = format!("Bearer {}", session.token);
+        assert_eq!(session.authenticate(Some(&cookie), None), Some(Credentials::Cookie));
+        assert_eq!(session.authenticate(None, Some(&bearer)), Some(Credentials::Bearer));
+        assert_eq!(other.authenticate(Some(&cookie), None), None);
+        assert_eq!(session.authenticate(Some("rhr_session="), None), None);
+        assert_eq!(session.authenticate(None, None), None);
+        // a wrong bearer token isn't saved by a right cookie
+        assert_eq!(session.authenticate(Some(&cookie), Some("Bearer nope")), None);
+
+        assert!(session.check_csrf(Some(session.csrf_token())));
+        assert!(!session.check_csrf(Some(other.csrf_token())));
+        assert!(!session.check_csrf(None));
+    }
+}
@@ -1,30 +1,54 @@
-use std::{
-    fs, io,
-    net::{SocketAddr, TcpListener, TcpStream},
-    sync::Arc,
-    thread,
-    time::{Duration, SystemTime},
-};
+use std::{collections::HashMap, future, net::SocketAddr, sync::Arc, time::Duration};
 
 use crate::{
-    rivens::inventory::{
-        convert_raw_inventory::{Attribute, Item, Units},
-        database::{database::InventoryDB, inventory_sync::sync_db},
-        riven_lookop::RivenDataLookup,
+    config::Config,
+    file_watcher::FileWatcher,
+    http_client::wfm_client::WFMClient,
+    rivens::{
+        inventory::{
+            convert_raw_inventory::{Attribute, Item, Units},
+            database::{
+                database::{Auction, InventoryDB},
+                inventory_sync::sync_db,
+            },
+            riven_lookop::RivenDataLookup,
+        },
+        reroll::{grade_riven, RollGrade, RollRule},
+        wfm_auctions::close_removed_auctions,
     },
-    server::RIVEN_LOOKUP,
-    StopSignal,
+    server::riven_lookup,
+    session::Session,
+    AppError, StopSignal,
+};
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
 };
 use maud::{html, PreEscaped};
 use tokio::{
+    net::{TcpListener, TcpStream},
     select,
     sync::{
-        broadcast::{self, Receiver},
-        Mutex,
+        broadcast::{self, error::RecvError, Receiver},
+        mpsc, Mutex,
     },
     task::JoinHandle,
+    time::{interval, sleep, timeout, MissedTickBehavior},
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{Callback, ErrorResponse, Request, Response},
+        http::{header, StatusCode},
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    WebSocketStream,
 };
-use tungstenite::WebSocket;
+
+// how long a client gets to finish the handshake or answer a close frame
+static HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+static CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone)]
 enum MessageType {
@@ -32,161 +56,368 @@ enum MessageType {
     HTML(Vec<PreEscaped<String>>),
 }
 
-fn send_elements(conn: &mut WebSocket<TcpStream>, new_elements: Vec<PreEscaped<String>>) {
-    new_elements.into_iter().for_each(|element| {
-        match conn.send(element.into_string().into()) {
-            Ok(_) => println!("INFO: Sent message to client"),
-            Err(e) => println!("ERROR: Could not send message to client: {e:?}"),
-        }
+type WsWriter = SplitSink<WebSocketStream<TcpStream>, Message>;
+type WsReader = SplitStream<WebSocketStream<TcpStream>>;
+
+async fn send_elements(
+    writer: &mut WsWriter,
+    new_elements: Vec<PreEscaped<String>>,
+) -> Result<(), AppError> {
+    for element in new_elements {
+        writer
+            .send(Message::Text(element.into_string()))
+            .await
+            .map_err(|e| AppError::new(e.to_string(), "send_elements: send".into()))?;
 
         // just for looks, can be removed in the future
-        thread::sleep(Duration::from_millis(10));
-    });
+        sleep(Duration::from_millis(10)).await;
+    }
+    Ok(())
+}
+
+// sends a close frame and waits a bit for the client to answer with its own,
+// the connection is dropped either way afterwards
+async fn close_connection(writer: &mut WsWriter, reader: &mut WsReader, code: CloseCode, reason: &str) {
+    let frame = CloseFrame {
+        code,
+        reason: reason.to_owned().into(),
+    };
+    if writer.send(Message::Close(Some(frame))).await.is_err() {
+        return;
+    }
+    let _ = timeout(CLOSE_TIMEOUT, async {
+        while let Some(Ok(msg)) = reader.next().await {
+            if msg.is_close() {
+                break;
+            }
+        }
+    })
+    .await;
+}
+
+// the browser sends the session cookie along with the handshake, websockets
+// aren't covered by the same origin policy so this is the only check
+struct SessionCheck<'a>(&'a Session);
+
+impl Callback for SessionCheck<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        let header = |name: header::HeaderName| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        if self
+            .0
+            .authenticate(header(header::COOKIE), header(header::AUTHORIZATION))
+            .is_some()
+        {
+            return Ok(response);
+        }
+        let mut response = ErrorResponse::new(Some("401 Unauthorized".into()));
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        Err(response)
+    }
+}
+
+// only connections that pass the session check get anywhere near the hub
+async fn accept(stream: TcpStream, addr: SocketAddr, session: &Session) -> Option<WebSocketStream<TcpStream>> {
+    let handshake = accept_hdr_async(stream, SessionCheck(session));
+    match timeout(HANDSHAKE_TIMEOUT, handshake).await {
+        Ok(Ok(v)) => Some(v),
+        Ok(Err(e)) => {
+            eprintln!("ERROR: Failed to handshake with {addr}: {e}");
+            None
+        }
+        Err(_) => {
+            eprintln!("ERROR: {addr} took too long to handshake");
+            None
+        }
+    }
 }
 
-async fn handle(mut conn: WebSocket<TcpStream>, mut receiver: Receiver<MessageType>) {
+async fn handle(
+    conn: WebSocketStream<TcpStream>,
+    id: u64,
+    snapshot: Vec<PreEscaped<String>>,
+    mut receiver: Receiver<MessageType>,
+    ping_interval: Duration,
+) {
+    let (mut writer, mut reader) = conn.split();
+    if let Err(e) = send_elements(&mut writer, snapshot).await {
+        eprintln!("ERROR: Could not send table to client {id}: {e}");
+        return;
+    }
+
+    let mut ping = interval(ping_interval);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the first tick completes right away
+    ping.tick().await;
+    let mut awaiting_pong = false;
     loop {
-        if let Ok(new_content) = receiver.recv().await {
-            println!("INFO: received content through channel");
-            match new_content {
-                MessageType::CloseFrame => break,
-                MessageType::HTML(new_elements) => {
-                    println!("INFO: {} new elements", new_elements.len());
-                    send_elements(&mut conn, new_elements);
+        select! {
+            msg = receiver.recv() => match msg {
+                Ok(MessageType::CloseFrame) | Err(RecvError::Closed) => {
+                    close_connection(&mut writer, &mut reader, CloseCode::Away, "server shutting down").await;
+                    break;
+                }
+                Ok(MessageType::HTML(new_elements)) => {
+                    eprintln!("INFO: Sending {} new elements to client {id}", new_elements.len());
+                    if let Err(e) = send_elements(&mut writer, new_elements).await {
+                        eprintln!("INFO: Client {id} disconnected: {e}");
+                        break;
+                    }
+                }
+                // the client's table is out of date now, closing makes it
+                // reconnect and get a new one
+                Err(RecvError::Lagged(n)) => {
+                    eprintln!("WARNING: Client {id} missed {n} messages, closing connection");
+                    close_connection(&mut writer, &mut reader, CloseCode::Again, "missed updates").await;
+                    break;
+                }
+            },
+            frame = reader.next() => match frame {
+                Some(Ok(Message::Pong(_))) => awaiting_pong = false,
+                Some(Ok(Message::Close(_))) => {
+                    // sends the close reply tungstenite queued up
+                    let _ = writer.close().await;
+                    eprintln!("INFO: Client {id} closed the connection");
+                    break;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    eprintln!("INFO: Client {id} disconnected: {e}");
+                    break;
+                }
+                None => break,
+            },
+            _ = ping.tick() => {
+                if awaiting_pong {
+                    eprintln!("INFO: Client {id} stopped answering pings");
+                    break;
+                }
+                if writer.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
                 }
+                awaiting_pong = true;
             }
-        } else {
-            println!("ERROR: Couldn't retrieve channel message");
         }
     }
 }
 
-struct LastModified(SystemTime, SystemTime);
+struct Client {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+// every connected client gets the whole table when it connects and then the
+// same changes as everyone else through the broadcast channel
+struct WebSocketHub {
+    clients: HashMap<u64, Client>,
+    next_id: u64,
+    sender: broadcast::Sender<MessageType>,
+    ping_interval: Duration,
+}
+
+impl WebSocketHub {
+    fn new(ping_interval: Duration) -> Self {
+        let (sender, _) = broadcast::channel::<MessageType>(50);
+        Self {
+            clients: HashMap::new(),
+            next_id: 0,
+            sender,
+            ping_interval,
+        }
+    }
+
+    fn add(&mut self, conn: WebSocketStream<TcpStream>, addr: SocketAddr, snapshot: Vec<PreEscaped<String>>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let handle = tokio::task::spawn(handle(
+            conn,
+            id,
+            snapshot,
+            self.sender.subscribe(),
+            self.ping_interval,
+        ));
+        self.clients.insert(id, Client { addr, handle });
+        eprintln!("INFO: Client {id} connected from {addr}, {} connected", self.clients.len());
+    }
+
+    // forgets clients whose connection was closed
+    fn prune(&mut self) {
+        self.clients.retain(|id, client| {
+            if client.handle.is_finished() {
+                eprintln!("INFO: Client {id} at {} disconnected", client.addr);
+            }
+            !client.handle.is_finished()
+        });
+    }
+
+    #[cfg(test)]
+    fn is_empty(&mut self) -> bool {
+        self.prune();
+        self.clients.is_empty()
+    }
+
+    fn broadcast(&self, new_elements: Vec<PreEscaped<String>>) {
+        if new_elements.is_empty() || self.clients.is_empty() {
+            return;
+        }
+        if let Err(e) = self.sender.send(MessageType::HTML(new_elements)) {
+            eprintln!("ERROR: Could not send message through channel: {e}")
+        }
+    }
 
-impl LastModified {
-    fn detect_file_change(&mut self) -> io::Result<bool> {
-        let attrs = fs::metadata("lastData.dat")?;
-        self.1 = attrs.modified().unwrap();
-        if self.1 != self.0 {
-            self.0 = self.1;
-            return Ok(true);
+    async fn close(self) {
+        let _ = self.sender.send(MessageType::CloseFrame);
+        for (id, client) in self.clients {
+            if let Err(e) = client.handle.await {
+                eprintln!("ERROR: Could not shut down client {id}: {e}");
+            }
         }
-        Ok(false)
     }
 }
 
 async fn handle_connection(
-    accept_result: Result<(TcpStream, SocketAddr), io::Error>,
+    (conn, addr): (WebSocketStream<TcpStream>, SocketAddr),
     rivens: &mut Vec<Item>,
-    current_connection: &mut Option<JoinHandle<()>>,
-    last_modified: &mut LastModified,
-    sender: &broadcast::Sender<MessageType>,
+    hub: &mut WebSocketHub,
     db: Arc<Mutex<Option<InventoryDB>>>,
+    wfm: Arc<Mutex<WFMClient>>,
     lookup: &RivenDataLookup,
+    config: &Config,
 ) {
-    if let Ok((stream, _addr)) = accept_result {
-        let wsoc_connection =
-            tungstenite::accept(stream).expect("FATAL: Failed to handshake with client");
-
-        // if there's an existing connection and the client is reconnecting
-        if let Some(_current_conn) = &current_connection {
-            sender
-                .send(MessageType::CloseFrame)
-                .expect("FATAL: Could not send channel close frame");
-            *current_connection = None;
-            println!("INFO: reconnecting");
-
-            // if there isnt an existing connection and the client is connecting
-            // for the first time
-        } else {
-            assert!(
-                current_connection.is_none(),
-                "FATAL: There should be no existing connection at this point"
-            );
-            *current_connection = Some(tokio::task::spawn(handle(
-                wsoc_connection,
-                sender.subscribe(),
-            )));
-            println!("INFO: Handshake complete");
-
-            let new_elements = sync_ui(rivens.clone(), vec![]).await;
-            if let Err(e) = sender.send(MessageType::HTML(new_elements)) {
-                println!("ERROR: Could not send message through channel: {e}")
-            } else {
-                println!("INFO: Sent content through channel")
-            };
-        };
+    hub.prune();
+
+    // pick up rivens that were blacklisted or unblacklisted since the last
+    // sync, clients that are already connected only need what changed
+    let (new_rivens, old_ids, error) = match sync_ui_rivens(rivens, db.clone(), wfm, lookup, config).await {
+        Ok((new_rivens, old_ids)) => (new_rivens, old_ids, None),
+        Err(e) => {
+            eprintln!("ERROR: Could not sync rivens: {e}");
+            (vec![], vec![], Some(e))
+        }
+    };
+    let auctions = select_auctions(db.clone()).await;
+    let rules = select_roll_rules(db.clone()).await;
+    let mut elements = sync_ui(new_rivens, old_ids, &auctions, &rules).await;
+    elements.push(sync_error(error.as_ref()));
+    hub.broadcast(elements);
+
+    let mut snapshot = sync_ui(rivens.clone(), vec![], &auctions, &rules).await;
+    snapshot.push(sync_error(error.as_ref()));
+    hub.add(conn, addr, snapshot);
+}
 
-        // when not handling new connections
-    } else {
-        if current_connection.is_some() {
-            if last_modified.detect_file_change().unwrap_or(false) {
-                // get changes in database and inventory state
-                let (new_rivens, old_ids) = sync_ui_rivens(rivens, db.clone(), lookup).await;
-                let new_elements = sync_ui(new_rivens, old_ids).await;
-
-                // send new elements to the connection handle
-                if let Err(e) = sender.send(MessageType::HTML(new_elements)) {
-                    println!("ERROR: Could not send message through channel: {e}")
-                };
-            }
+// synced even without clients, sold rivens' listings shouldn't wait for one
+async fn handle_file_change(
+    rivens: &mut Vec<Item>,
+    hub: &mut WebSocketHub,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+    wfm: Arc<Mutex<WFMClient>>,
+    lookup: &RivenDataLookup,
+    config: &Config,
+) {
+    eprintln!("INFO: Inventory changed");
+    hub.prune();
+
+    // get changes in database and inventory state, the rivens the clients
+    // have are kept when that fails
+    let (new_rivens, old_ids, error) = match sync_ui_rivens(rivens, db.clone(), wfm, lookup, config).await {
+        Ok((new_rivens, old_ids)) => (new_rivens, old_ids, None),
+        Err(e) => {
+            eprintln!("ERROR: Could not sync rivens: {e}");
+            (vec![], vec![], Some(e))
         }
-    }
+    };
+    let auctions = select_auctions(db.clone()).await;
+    let rules = select_roll_rules(db.clone()).await;
+    let mut elements = sync_ui(new_rivens, old_ids, &auctions, &rules).await;
+    elements.push(sync_error(error.as_ref()));
+    hub.broadcast(elements);
 }
 
-#[tokio::main]
-pub async fn start_websocket(mut stop_signal: Receiver<StopSignal>) {
-    let server = TcpListener::bind("localhost:8069").expect("FATAL: could not bind to port: ");
+async fn file_changed(watcher: &mut Option<FileWatcher>) {
+    match watcher {
+        Some(watcher) => watcher.changed().await,
+        None => future::pending().await,
+    }
+}
 
-    let db = InventoryDB::open("inventory_db.sqlite3").expect("grrrr2");
-    let db = Arc::new(Mutex::new(Some(db)));
+pub async fn start_websocket(
+    mut stop_signal: Receiver<StopSignal>,
+    db: Arc<Mutex<Option<InventoryDB>>>,
+    wfm: Arc<Mutex<WFMClient>>,
+    config: Arc<Config>,
+    session: Arc<Session>,
+) {
+    let server = TcpListener::bind((config.server.host.as_str(), config.server.websocket_port))
+        .await
+        .expect("FATAL: could not bind to port: ");
 
-    let lookup = RIVEN_LOOKUP
-        .get()
-        .expect("FATAL: Could not access lookup data");
+    let lookup = riven_lookup().expect("FATAL: Could not access lookup data");
 
-    let mut current_connection: Option<JoinHandle<()>> = None;
+    let mut hub = WebSocketHub::new(Duration::from_secs(config.server.websocket_ping_secs));
+    // handshakes happen in their own tasks so a slow client can't hold up
+    // everyone else, the ones that pass come back through here
+    let (accepted_sender, mut accepted) = mpsc::unbounded_channel();
     let mut rivens = Vec::new();
-    let mut last_modified = LastModified(SystemTime::UNIX_EPOCH, SystemTime::UNIX_EPOCH);
+    let mut watcher = match FileWatcher::new(&config.paths.last_data, &config.watcher) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            eprintln!("ERROR: Could not watch inventory for changes: {e}");
+            None
+        }
+    };
 
     // dont need the returned rivens or old id's as they're automatically
     // appended to `rivens` on startup and we wont have anything.
-    let _ = sync_ui_rivens(&mut rivens, db.clone(), lookup).await;
-
-    // we're using a channel here to be able to communicate with connection
-    // handles being used throughout the server's lifetime.
-    // although only one connection will be handled at any given time, using a
-    // broadcast channel is just for convenience to be able to quickly get a new
-    // receiver just by subscribing to the sender end for each reconnection.
-    let (sender, _) = broadcast::channel::<MessageType>(50);
+    if let Err(e) = sync_ui_rivens(&mut rivens, db.clone(), wfm.clone(), &lookup, &config).await {
+        eprintln!("ERROR: Could not sync rivens: {e}");
+    }
 
     loop {
         select! {
-            accept_result = async { server.accept() } => {
+            accept_result = server.accept() => match accept_result {
+                Ok((stream, addr)) => {
+                    let session = session.clone();
+                    let accepted_sender = accepted_sender.clone();
+                    tokio::task::spawn(async move {
+                        if let Some(conn) = accept(stream, addr, &session).await {
+                            let _ = accepted_sender.send((conn, addr));
+                        }
+                    });
+                }
+                Err(e) => eprintln!("ERROR: Could not accept websocket connection: {e}"),
+            },
+            Some(accepted) = accepted.recv() => {
                 handle_connection(
-                    accept_result,
+                    accepted,
                     &mut rivens,
-                    &mut current_connection,
-                    &mut last_modified,
-                    &sender,
+                    &mut hub,
                     db.clone(),
-                    lookup
+                    wfm.clone(),
+                    &lookup,
+                    &config
                 ).await
             }
-                _ = stop_signal.recv() => {
-                    sender
-                        .send(MessageType::CloseFrame)
-                        .expect("FATAL: Could not send channel close frame");
-
-                    if let Some(conn) = current_connection {
-                        conn.await
-                            .expect("FATAL: could not shut down client connection");
-                    }
-
-                    println!("INFO: shutting down client connection");
-                    println!("INFO: WebSocket Closed");
-                    break;
-                }
+            _ = file_changed(&mut watcher) => {
+                handle_file_change(
+                    &mut rivens,
+                    &mut hub,
+                    db.clone(),
+                    wfm.clone(),
+                    &lookup,
+                    &config
+                ).await
+            }
+            _ = stop_signal.recv() => {
+                eprintln!("INFO: shutting down client connections");
+                hub.close().await;
+                eprintln!("INFO: WebSocket Closed");
+                break;
+            }
         };
     }
 
@@ -197,15 +428,55 @@ pub async fn start_websocket(mut stop_signal: Receiver<StopSignal>) {
         .expect("FATAL: Error while closing database connection");
 }
 
+async fn select_auctions(db: Arc<Mutex<Option<InventoryDB>>>) -> Vec<Auction> {
+    let db = db.lock().await;
+    let db = db.as_ref().expect("db must be some");
+    match db.select_auctions() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("ERROR: Could not retrieve auctions: {e}");
+            vec![]
+        }
+    }
+}
+
+async fn select_roll_rules(db: Arc<Mutex<Option<InventoryDB>>>) -> Vec<RollRule> {
+    let db = db.lock().await;
+    let db = db.as_ref().expect("db must be some");
+    match db.select_roll_rules() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("ERROR: Could not retrieve roll rules: {e}");
+            vec![]
+        }
+    }
+}
+
+// listings of rivens that left the inventory are closed before the clients
+// are told, so the browser's delete finds nothing left to close
 pub async fn sync_ui_rivens(
     current_ui_rivens: &mut Vec<Item>,
     db: Arc<Mutex<Option<InventoryDB>>>,
+    wfm: Arc<Mutex<WFMClient>>,
     lookup: &RivenDataLookup,
-) -> (Vec<Item>, Vec<Arc<str>>) {
-    let (current_db_items, old_ids) = sync_db(db, lookup, None).await.unwrap();
+    config: &Config,
+) -> Result<(Vec<Item>, Vec<Arc<str>>), AppError> {
+    let (current_db_items, old_ids) = sync_db(db.clone(), lookup, config, None)
+        .await
+        .map_err(|e| AppError::new(format!("{e:?}"), "sync_ui_rivens: sync_db".into()))?;
+    close_removed_auctions(&current_db_items, &old_ids, wfm, db.clone()).await;
+
+    let blacklist = {
+        let db = db.lock().await;
+        let db = db.as_ref().expect("db must be some");
+        db.select_blacklist()
+            .map_err(|e| AppError::new(e.to_string(), "sync_ui_rivens: select_blacklist".into()))?
+    };
+    current_ui_rivens.retain(|item| !blacklist.contains(&item.oid));
 
     let mut new_items: Vec<Item> = current_db_items
         .into_iter()
+        .filter(|upgrade| !blacklist.contains(&upgrade.oid))
         .filter(|upgrade| {
             current_ui_rivens
                 .iter()
@@ -229,10 +500,10 @@ pub async fn sync_ui_rivens(
         });
     }
     current_ui_rivens.append(&mut new_items);
-    (new_items, old_ids)
+    Ok((new_items, old_ids))
 }
 
-fn construct_stats(attributes: &[Attribute]) -> PreEscaped<String> {
+pub fn construct_stats(attributes: &[Attribute]) -> PreEscaped<String> {
     attributes.iter().fold(PreEscaped::default(), |acc, attr| {
         let stat = match attr.units {
             Units::Percent => {
@@ -254,16 +525,24 @@ fn construct_stats(attributes: &[Attribute]) -> PreEscaped<String> {
         };
         html! {
         (acc)
-        p style="text-align: center; margin: 10px;"{(stat)}
+        p style="text-align: center; margin: 10px;"{
+            (stat)
+            @if let Some(roll) = attr.roll {
+                span style="font-size: 0.75em; opacity: 0.7; padding-left: 6px;" {(format!("{roll}%"))}
+            }
+        }
         }
     })
 }
 
 fn construct_riven_element(
+    oid: &str,
     id: &str,
     title: &str,
     edit_uri: &str,
     stats: PreEscaped<String>,
+    listing: Option<&Auction>,
+    grade: Option<RollGrade>,
 ) -> PreEscaped<String> {
     html! {
         div id="riven-table" class="row" hx-swap-oob="beforeend" {
@@ -272,6 +551,11 @@ fn construct_riven_element(
                     (title)
                 }
                 hr style="width: 100%";
+                @if let Some(grade) = grade {
+                    div class=(format!("roll-grade {}", grade.as_str())) style="text-align: center; font-size: 0.8em;" {
+                        (grade.label())
+                    }
+                }
                 div style="flex-grow: 1"{
                     (stats)
                 }
@@ -283,7 +567,15 @@ fn construct_riven_element(
                             hx-target="#screen"
                             hx-swap="beforeend" {"Edit"}
                     }
-                    // img src="/wfm_favicon.ico" style="float: right; margin-left: 23px; padding-right: 13px;";
+                    div style="float: right;" {
+                        input class="riven-select" type="checkbox" name="oid" value=(oid);
+                    }
+                    @if let Some(price) = listing.and_then(|auc| auc.buyout_price) {
+                        div style="float: right; padding-right: 13px;" {
+                            img src="/wfm_favicon.ico" style="height: 1em; padding-right: 5px;";
+                            (format!("{price}p"))
+                        }
+                    }
                 }
             }
         }
@@ -293,6 +585,8 @@ fn construct_riven_element(
 pub async fn sync_ui(
     mut new_rivens: Vec<Item>,
     delete_ids: Vec<Arc<str>>,
+    auctions: &[Auction],
+    rules: &[RollRule],
 ) -> Vec<PreEscaped<String>> {
     new_rivens.sort_by(|a, b| a.attributes.len().cmp(&b.attributes.len()));
     let mut pagecontent =
@@ -308,11 +602,16 @@ pub async fn sync_ui(
 
                 let edit_uri = format!("/edit_open/{oid}");
 
+                let listing = auctions.iter().find(|auc| auc.oid == oid.as_ref());
+
                 acc.push(construct_riven_element(
+                    &oid,
                     id.as_str(),
                     title.as_str(),
                     edit_uri.as_str(),
                     stats,
+                    listing,
+                    grade_riven(riven, rules),
                 ));
                 acc
             });
@@ -325,6 +624,17 @@ pub async fn sync_ui(
     pagecontent
 }
 
+// empty once a sync works again
+fn sync_error(error: Option<&AppError>) -> PreEscaped<String> {
+    html! {
+        div id="sync-error" hx-swap-oob="true" {
+            @if let Some(e) = error {
+                "Could not sync the inventory: " (e.err)
+            }
+        }
+    }
+}
+
 fn delete_riven(id: &str) -> PreEscaped<String> {
     let del_id = format!("a{id}");
     let del_id_target = format!("#{del_id}");
@@ -340,3 +650,129 @@ fn delete_riven(id: &str) -> PreEscaped<String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use maud::PreEscaped;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::{
+        client_async,
+        tungstenite::{client::IntoClientRequest, Error, Message},
+        WebSocketStream,
+    };
+
+    use crate::session::Session;
+
+    use super::{accept, WebSocketHub};
+
+    async fn try_connect(
+        listener: &TcpListener,
+        hub: &mut WebSocketHub,
+        session: &Session,
+        snapshot: &str,
+        authorization: Option<String>,
+    ) -> Result<WebSocketStream<TcpStream>, Error> {
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut request = format!("ws://{addr}").into_client_request().unwrap();
+            if let Some(authorization) = authorization {
+                let value = authorization.parse().unwrap();
+                request.headers_mut().insert("Authorization", value);
+            }
+            let stream = TcpStream::connect(addr).await.unwrap();
+            client_async(request, stream).await.map(|(conn, _)| conn)
+        });
+        let (stream, addr) = listener.accept().await.unwrap();
+        // rejected clients never make it into the hub
+        if let Some(conn) = accept(stream, addr, session).await {
+            hub.add(conn, addr, vec![PreEscaped(snapshot.into())]);
+        }
+        client.await.unwrap()
+    }
+
+    async fn connect(
+        listener: &TcpListener,
+        hub: &mut WebSocketHub,
+        snapshot: &str,
+    ) -> WebSocketStream<TcpStream> {
+        let session = Session::new();
+        let authorization = format!("Bearer {}", session.token());
+        try_connect(listener, hub, &session, snapshot, Some(authorization)).await.unwrap()
+    }
+
+    fn hub(ping_interval: Duration) -> WebSocketHub {
+        WebSocketHub::new(ping_interval)
+    }
+
+    async fn read_text(client: &mut WebSocketStream<TcpStream>) -> String {
+        match client.next().await {
+            Some(Ok(Message::Text(text))) => text,
+            other => panic!("expected text, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hub_broadcast() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut hub = hub(Duration::from_secs(30));
+
+        // each client only gets its own snapshot
+        let mut first = connect(&listener, &mut hub, "first table").await;
+        assert_eq!(read_text(&mut first).await, "first table");
+        let mut second = connect(&listener, &mut hub, "second table").await;
+        assert_eq!(read_text(&mut second).await, "second table");
+        assert!(!hub.is_empty());
+
+        hub.broadcast(vec![PreEscaped("change".into())]);
+        assert_eq!(read_text(&mut first).await, "change");
+        assert_eq!(read_text(&mut second).await, "change");
+
+        // both clients have to answer the close frame for this to return
+        let reader = tokio::spawn(async move {
+            let first = first.next().await;
+            let second = second.next().await;
+            (first, second)
+        });
+        hub.close().await;
+        let (first, second) = reader.await.unwrap();
+        assert!(matches!(first, Some(Ok(Message::Close(_)))));
+        assert!(matches!(second, Some(Ok(Message::Close(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_hub_keepalive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut hub = hub(Duration::from_millis(50));
+
+        let mut client = connect(&listener, &mut hub, "table").await;
+        assert_eq!(read_text(&mut client).await, "table");
+        // the clock only moves once every task is waiting, so a slow machine
+        // can't make the pings or their answers late
+        tokio::time::pause();
+        // reading answers the pings, so the client is kept around
+        let _ = tokio::time::timeout(Duration::from_millis(200), client.next()).await;
+        assert!(!hub.is_empty());
+
+        // not reading means the pings go unanswered
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(hub.is_empty());
+        client.close(None).await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_hub_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut hub = hub(Duration::from_secs(30));
+
+        let session = Session::new();
+        assert!(try_connect(&listener, &mut hub, &session, "table", None).await.is_err());
+        let wrong = Some("Bearer wrong".to_string());
+        assert!(try_connect(&listener, &mut hub, &session, "table", wrong).await.is_err());
+        assert!(hub.is_empty());
+        let mut client = connect(&listener, &mut hub, "table").await;
+        assert_eq!(read_text(&mut client).await, "table");
+    }
+}